@@ -13,6 +13,16 @@ fn main() {
         .with_title("Software rendering example")
         .build(&event_loop)
         .expect("failed to build window");
+    // Keep a single buffer for the window's whole lifetime, resizing it to follow the window.
+    let (width, height): (u32, u32) = window.inner_size().into();
+    let mut buffer = PixelBufferTyped::<NativeFormat>::new_supported(
+        width,
+        height,
+        &window,
+        &window
+            .display_handle()
+            .expect("couldn't get display for window"),
+    );
     // ControlFlow::Poll continuously runs the event loop, even if the OS hasn't
     // dispatched any events. This is ideal for games and similar applications.
     event_loop.set_control_flow(ControlFlow::Poll);
@@ -36,36 +46,32 @@ fn main() {
                 window.request_redraw();
             }
 
+            Event::WindowEvent {
+                event: WindowEvent::Resized(size),
+                window_id,
+            } if window_id == window.id() => {
+                buffer
+                    .resize(size.width, size.height)
+                    .expect("failed to resize pixel buffer");
+            }
             Event::WindowEvent {
                 event: WindowEvent::RedrawRequested,
                 window_id,
                 ..
-            } => {
-                if window_id == window.id() {
-                    let (width, height): (u32, u32) = window.inner_size().into();
-                    let mut buffer = PixelBufferTyped::<NativeFormat>::new_supported(
-                        width,
-                        height,
-                        &window,
-                        &window
-                            .display_handle()
-                            .expect("couldn't get display for window"),
-                    );
-
-                    for (i, row) in buffer.rows_mut().enumerate() {
-                        let value = (i % 256) as u16;
-                        for (j, pixel) in row.into_iter().enumerate() {
-                            let value = value * (j % 256) as u16 / 256;
-                            *pixel = NativeFormat::from_rgb(
-                                (256 * value / 256) as u8,
-                                (256 * value / 256) as u8,
-                                (256 * value / 256) as u8,
-                            );
-                        }
+            } if window_id == window.id() => {
+                for (i, row) in buffer.rows_mut().enumerate() {
+                    let value = (i % 256) as u16;
+                    for (j, pixel) in row.iter_mut().enumerate() {
+                        let value = value * (j % 256) as u16 / 256;
+                        *pixel = NativeFormat::from_rgb(
+                            (256 * value / 256) as u8,
+                            (256 * value / 256) as u8,
+                            (256 * value / 256) as u8,
+                        );
                     }
-
-                    buffer.blit(&window).unwrap();
                 }
+
+                buffer.blit(&window).unwrap();
             }
             _ => (),
         })
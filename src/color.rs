@@ -0,0 +1,399 @@
+//! Color space conversions and color math.
+//!
+//! The sRGB conversions are backed by lookup tables, which are built the first time each one is
+//! used. Linear values are 16 bits per channel, which is enough to tell every 8-bit sRGB value
+//! apart, even among the darkest colors.
+
+use std::sync::OnceLock;
+
+use crate::{BGRA, RGBA};
+
+/// Decodes an sRGB-encoded value in `0.0..=1.0` into linear light.
+///
+/// This is the exact transfer function that the lookup tables are built from.
+pub fn decode_srgb(encoded: f32) -> f32 {
+    if encoded <= 0.040_45 {
+        encoded / 12.92
+    } else {
+        ((encoded + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Encodes a linear value in `0.0..=1.0` with the sRGB transfer function.
+///
+/// This is the exact transfer function that the lookup tables are built from.
+pub fn encode_srgb(linear: f32) -> f32 {
+    if linear <= 0.003_130_8 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Builds a lookup table from `0..=max_index` into `0..=max_value`.
+fn table<T>(max_index: u16, max_value: u16, f: fn(f32) -> f32, to: fn(u16) -> T) -> Box<[T]> {
+    (0..=max_index)
+        .map(|i| {
+            let value = f(f32::from(i) / f32::from(max_index));
+            to((value * f32::from(max_value) + 0.5) as u16)
+        })
+        .collect()
+}
+
+macro_rules! lookup {
+    ($table:ident[$index:expr] = $max_index:expr => $max_value:expr, $f:ident as $t:ty) => {{
+        static $table: OnceLock<Box<[$t]>> = OnceLock::new();
+        $table.get_or_init(|| table($max_index, $max_value, $f, |value| value as $t))
+            [$index as usize]
+    }};
+}
+
+/// Decodes an 8-bit sRGB value into 16-bit linear light.
+pub fn srgb_to_linear(encoded: u8) -> u16 {
+    lookup!(TO_LINEAR[encoded] = 0xFF => 0xFFFF, decode_srgb as u16)
+}
+
+/// Encodes a 16-bit linear value into 8-bit sRGB.
+pub fn linear_to_srgb(linear: u16) -> u8 {
+    lookup!(FROM_LINEAR[linear] = 0xFFFF => 0xFF, encode_srgb as u8)
+}
+
+/// Decodes a 16-bit sRGB value into 16-bit linear light.
+pub fn srgb16_to_linear(encoded: u16) -> u16 {
+    lookup!(TO_LINEAR[encoded] = 0xFFFF => 0xFFFF, decode_srgb as u16)
+}
+
+/// Encodes a 16-bit linear value into 16-bit sRGB.
+pub fn linear_to_srgb16(linear: u16) -> u16 {
+    lookup!(FROM_LINEAR[linear] = 0xFFFF => 0xFFFF, encode_srgb as u16)
+}
+
+/// A color as hue, saturation and value.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Hsv {
+    /// The hue, in degrees from `0.0` (red) up to `360.0`.
+    pub h: f32,
+    /// The saturation, from `0.0` (gray) to `1.0`.
+    pub s: f32,
+    /// The value, from `0.0` (black) to `1.0`.
+    pub v: f32,
+}
+
+/// A color as hue, saturation and lightness.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Hsl {
+    /// The hue, in degrees from `0.0` (red) up to `360.0`.
+    pub h: f32,
+    /// The saturation, from `0.0` (gray) to `1.0`.
+    pub s: f32,
+    /// The lightness, from `0.0` (black) through `0.5` (the pure hue) to `1.0` (white).
+    pub l: f32,
+}
+
+/// The channels of an RGB color in `0.0..=1.0`, along with the largest and smallest of them and
+/// the hue.
+struct Channels {
+    max: f32,
+    min: f32,
+    hue: f32,
+}
+
+impl Channels {
+    fn new(r: u8, g: u8, b: u8) -> Channels {
+        let [r, g, b] = [r, g, b].map(|c| f32::from(c) / 255.0);
+        let (max, min) = (r.max(g).max(b), r.min(g).min(b));
+        let delta = max - min;
+        let hue = if delta == 0.0 {
+            0.0
+        } else if max == r {
+            60.0 * ((g - b) / delta).rem_euclid(6.0)
+        } else if max == g {
+            60.0 * ((b - r) / delta + 2.0)
+        } else {
+            60.0 * ((r - g) / delta + 4.0)
+        };
+        Channels { max, min, hue }
+    }
+}
+
+/// Turns a hue, chroma and the amount to add to every channel back into RGB.
+fn from_hue(hue: f32, chroma: f32, m: f32) -> [u8; 3] {
+    let h = hue.rem_euclid(360.0) / 60.0;
+    let x = chroma * (1.0 - (h % 2.0 - 1.0).abs());
+    let (r, g, b) = match h as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    [r, g, b].map(|c| ((c + m).clamp(0.0, 1.0) * 255.0 + 0.5) as u8)
+}
+
+impl Hsv {
+    pub fn from_rgb(r: u8, g: u8, b: u8) -> Hsv {
+        let Channels { max, min, hue } = Channels::new(r, g, b);
+        let s = if max == 0.0 { 0.0 } else { (max - min) / max };
+        Hsv { h: hue, s, v: max }
+    }
+
+    /// The color as `[r, g, b]`.
+    pub fn to_rgb(self) -> [u8; 3] {
+        let chroma = self.v * self.s;
+        from_hue(self.h, chroma, self.v - chroma)
+    }
+}
+
+impl Hsl {
+    pub fn from_rgb(r: u8, g: u8, b: u8) -> Hsl {
+        let Channels { max, min, hue } = Channels::new(r, g, b);
+        let l = (max + min) / 2.0;
+        let s = if max == min {
+            0.0
+        } else {
+            (max - min) / (1.0 - (2.0 * l - 1.0).abs())
+        };
+        Hsl { h: hue, s, l }
+    }
+
+    /// The color as `[r, g, b]`.
+    pub fn to_rgb(self) -> [u8; 3] {
+        let chroma = (1.0 - (2.0 * self.l - 1.0).abs()) * self.s;
+        from_hue(self.h, chroma, self.l - chroma / 2.0)
+    }
+}
+
+macro_rules! color_math {
+    ($($pixel:ident),+) => {$(
+        impl $pixel {
+            /// Interpolates between `self` at `t = 0.0` and `other` at `t = 1.0` in linear light,
+            /// which keeps the colors in between from looking too dark.
+            ///
+            /// Both colors are straight rather than premultiplied. Alpha is interpolated linearly,
+            /// and `t` is clamped into `0.0..=1.0`.
+            pub fn lerp(self, other: $pixel, t: f32) -> $pixel {
+                let t = t.clamp(0.0, 1.0);
+                let mix = |a: f32, b: f32| a + (b - a) * t;
+                let channel = |a: u8, b: u8| {
+                    let linear = mix(
+                        f32::from(srgb_to_linear(a)),
+                        f32::from(srgb_to_linear(b)),
+                    );
+                    linear_to_srgb((linear + 0.5) as u16)
+                };
+                $pixel {
+                    r: channel(self.r, other.r),
+                    g: channel(self.g, other.g),
+                    b: channel(self.b, other.b),
+                    a: (mix(f32::from(self.a), f32::from(other.a)) + 0.5) as u8,
+                }
+            }
+
+            /// Multiplies the color channels by alpha.
+            pub fn premultiply(self) -> $pixel {
+                let channel = |c: u8| ((u16::from(c) * u16::from(self.a) + 127) / 255) as u8;
+                $pixel {
+                    r: channel(self.r),
+                    g: channel(self.g),
+                    b: channel(self.b),
+                    a: self.a,
+                }
+            }
+
+            /// Divides the color channels by alpha, undoing [`premultiply`](Self::premultiply)
+            /// as far as rounding allows. Fully transparent pixels become transparent black.
+            pub fn unpremultiply(self) -> $pixel {
+                let a = u16::from(self.a);
+                let channel = |c: u8| {
+                    (u16::from(c) * 255 + a / 2).checked_div(a).map_or(0, |c| c.min(255) as u8)
+                };
+                $pixel {
+                    r: channel(self.r),
+                    g: channel(self.g),
+                    b: channel(self.b),
+                    a: self.a,
+                }
+            }
+
+            /// The color as hue, saturation and value. Alpha is dropped.
+            pub fn to_hsv(self) -> Hsv {
+                Hsv::from_rgb(self.r, self.g, self.b)
+            }
+
+            /// The color as hue, saturation and lightness. Alpha is dropped.
+            pub fn to_hsl(self) -> Hsl {
+                Hsl::from_rgb(self.r, self.g, self.b)
+            }
+        }
+
+        impl From<Hsv> for $pixel {
+            /// Converts to an opaque pixel.
+            fn from(hsv: Hsv) -> $pixel {
+                let [r, g, b] = hsv.to_rgb();
+                $pixel::from_rgb(r, g, b)
+            }
+        }
+
+        impl From<Hsl> for $pixel {
+            /// Converts to an opaque pixel.
+            fn from(hsl: Hsl) -> $pixel {
+                let [r, g, b] = hsl.to_rgb();
+                $pixel::from_rgb(r, g, b)
+            }
+        }
+    )+};
+}
+
+color_math!(BGRA, RGBA);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srgb_round_trips() {
+        for encoded in 0..=255 {
+            assert_eq!(linear_to_srgb(srgb_to_linear(encoded)), encoded);
+            // The 16-bit tables agree with the 8-bit ones.
+            let linear = srgb16_to_linear(u16::from(encoded) * 257);
+            assert!(linear.abs_diff(srgb_to_linear(encoded)) <= 1);
+            let encoded16 = linear_to_srgb16(linear);
+            assert!(encoded16.abs_diff(u16::from(encoded) * 257) <= 257 / 2);
+        }
+        assert_eq!(srgb_to_linear(0), 0);
+        assert_eq!(srgb_to_linear(255), 0xFFFF);
+        assert_eq!(srgb_to_linear(188), 32957);
+        assert_eq!(linear_to_srgb(0x7FFF), 188);
+        assert_eq!(srgb16_to_linear(0xFFFF), 0xFFFF);
+        assert_eq!(linear_to_srgb16(0), 0);
+        let linear: Vec<_> = (0..=0xFFFF).map(srgb16_to_linear).collect();
+        assert!(linear.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn lerps_in_linear_light() {
+        let black = BGRA::new(0, 0, 0, 0);
+        let white = BGRA::new(255, 255, 255, 255);
+        assert_eq!(black.lerp(white, 0.0), black);
+        assert_eq!(black.lerp(white, 1.0), white);
+        assert_eq!(black.lerp(white, 0.5), BGRA::new(188, 188, 188, 128));
+        assert_eq!(black.lerp(white, 7.0), white);
+        let red = RGBA::new(255, 0, 0, 255);
+        let green = RGBA::new(0, 255, 0, 255);
+        assert_eq!(red.lerp(green, 0.5), RGBA::new(188, 188, 0, 255));
+    }
+
+    #[test]
+    fn premultiplies() {
+        let pixel = RGBA::new(255, 128, 0, 128);
+        assert_eq!(pixel.premultiply(), RGBA::new(128, 64, 0, 128));
+        assert_eq!(pixel.premultiply().unpremultiply(), pixel);
+        assert_eq!(BGRA::new(1, 2, 3, 0).unpremultiply(), BGRA::new(0, 0, 0, 0));
+        assert_eq!(
+            BGRA::new(1, 2, 3, 255).premultiply(),
+            BGRA::new(1, 2, 3, 255)
+        );
+        for a in 0..=255 {
+            let pixel = BGRA::new(255, a, 0, a).unpremultiply();
+            assert_eq!(pixel.premultiply(), BGRA::new(a, a, 0, a));
+        }
+    }
+
+    #[test]
+    fn hsv() {
+        assert_eq!(
+            Hsv::from_rgb(255, 0, 0),
+            Hsv {
+                h: 0.0,
+                s: 1.0,
+                v: 1.0
+            }
+        );
+        assert_eq!(
+            Hsv::from_rgb(0, 0, 0),
+            Hsv {
+                h: 0.0,
+                s: 0.0,
+                v: 0.0
+            }
+        );
+        assert_eq!(Hsv::from_rgb(0, 255, 255).h, 180.0);
+        assert_eq!(Hsv::from_rgb(255, 0, 255).h, 300.0);
+        assert_eq!(
+            Hsv {
+                h: 120.0,
+                s: 1.0,
+                v: 1.0
+            }
+            .to_rgb(),
+            [0, 255, 0]
+        );
+        assert_eq!(
+            Hsv {
+                h: -120.0,
+                s: 1.0,
+                v: 0.5
+            }
+            .to_rgb(),
+            [0, 0, 128]
+        );
+        assert_eq!(
+            BGRA::from(Hsv {
+                h: 60.0,
+                s: 1.0,
+                v: 1.0
+            }),
+            BGRA::new(0, 255, 255, 255)
+        );
+    }
+
+    #[test]
+    fn hsl() {
+        assert_eq!(
+            Hsl::from_rgb(255, 0, 0),
+            Hsl {
+                h: 0.0,
+                s: 1.0,
+                l: 0.5
+            }
+        );
+        assert_eq!(
+            Hsl::from_rgb(255, 255, 255),
+            Hsl {
+                h: 0.0,
+                s: 0.0,
+                l: 1.0
+            }
+        );
+        assert_eq!(
+            Hsl {
+                h: 240.0,
+                s: 1.0,
+                l: 0.25
+            }
+            .to_rgb(),
+            [0, 0, 128]
+        );
+        assert_eq!(
+            RGBA::from(Hsl {
+                h: 0.0,
+                s: 0.0,
+                l: 0.5
+            }),
+            RGBA::new(128, 128, 128, 255)
+        );
+    }
+
+    #[test]
+    fn hsv_and_hsl_round_trip() {
+        for r in (0..=255).step_by(15) {
+            for g in (0..=255).step_by(17) {
+                for b in (0..=255).step_by(51) {
+                    assert_eq!(Hsv::from_rgb(r, g, b).to_rgb(), [r, g, b]);
+                    assert_eq!(Hsl::from_rgb(r, g, b).to_rgb(), [r, g, b]);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,393 @@
+//! Alpha compositing of one set of pixels onto another.
+//!
+//! Pixels are blended in 16 bits per channel, with premultiplied alpha, whatever format they're
+//! stored in. [`Blend`] picks the blend mode, whether colors are blended as they're stored or in
+//! linear light, and whether the stored colors are premultiplied by their alpha.
+
+use crate::{
+    color::{linear_to_srgb, srgb_to_linear},
+    convert::convert_row,
+    PixelBufferFormat, Rect, BGRA,
+};
+
+/// How the colors of the source and destination pixels are combined.
+///
+/// The separable modes ([`Multiply`](Self::Multiply), [`Screen`](Self::Screen),
+/// [`Darken`](Self::Darken) and [`Lighten`](Self::Lighten)) follow the W3C compositing spec: they
+/// only apply where both pixels are opaque, and fall back on source-over elsewhere.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum BlendMode {
+    /// Replaces the destination with the source, alpha included.
+    Source,
+    /// Porter-Duff source-over: draws the source on top of the destination.
+    #[default]
+    SourceOver,
+    /// Adds the source to the destination, saturating at white. Also known as "plus" or "lighter".
+    Add,
+    /// Multiplies the colors, which can only darken the destination.
+    Multiply,
+    /// Multiplies the inverted colors, which can only lighten the destination.
+    Screen,
+    /// Keeps the darker of each color channel.
+    Darken,
+    /// Keeps the lighter of each color channel.
+    Lighten,
+}
+
+/// Everything that controls how pixels are composited.
+///
+/// ```
+/// use winit_blit::{
+///     composite::{Blend, BlendMode},
+///     BGRA,
+/// };
+///
+/// let blend = Blend {
+///     gamma_correct: true,
+///     ..Blend::new(BlendMode::SourceOver)
+/// };
+/// let red = BGRA::new(0, 0, 255, 128);
+/// assert_eq!(blend.apply(red, BGRA::new(0, 0, 0, 255)), BGRA::new(0, 0, 188, 255));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Blend {
+    pub mode: BlendMode,
+    /// Blend in linear light rather than directly on the sRGB-encoded colors.
+    ///
+    /// Slower, but avoids the dark fringes that blending encoded colors leaves between bright
+    /// colors. Alpha is always linear.
+    pub gamma_correct: bool,
+    /// Whether the source and destination colors are premultiplied by their alpha, rather than
+    /// straight.
+    ///
+    /// With `gamma_correct`, colors are taken to be premultiplied after they're sRGB encoded.
+    pub premultiplied: bool,
+}
+
+impl Blend {
+    /// Blends in `mode`, directly on straight sRGB-encoded colors.
+    pub const fn new(mode: BlendMode) -> Blend {
+        Blend {
+            mode,
+            gamma_correct: false,
+            premultiplied: false,
+        }
+    }
+
+    /// Composites `src` onto `dst`, returning the result.
+    pub fn apply(self, src: BGRA, dst: BGRA) -> BGRA {
+        let mut dst = [dst];
+        blend_bgra(&[src], &mut dst, self);
+        dst[0]
+    }
+}
+
+/// Composites a row of `S` pixels onto a row of `D` pixels, stopping at the end of whichever row
+/// is shorter.
+///
+/// Formats without an alpha channel are treated as opaque, and lose the alpha of the result.
+///
+/// ```
+/// use winit_blit::{
+///     composite::{blend_row, Blend, BlendMode},
+///     BGRA, RGB,
+/// };
+///
+/// let src = [BGRA::new(255, 255, 255, 255), BGRA::new(0, 0, 0, 0)];
+/// let mut dst = [RGB::new(10, 20, 30); 2];
+/// blend_row(&src, &mut dst, Blend::new(BlendMode::SourceOver));
+/// assert_eq!(dst, [RGB::new(255, 255, 255), RGB::new(10, 20, 30)]);
+/// ```
+pub fn blend_row<S: PixelBufferFormat, D: PixelBufferFormat>(
+    src: &[S],
+    dst: &mut [D],
+    blend: Blend,
+) {
+    let mut src_bgra = [BGRA::DEFAULT; 64];
+    let mut dst_bgra = [BGRA::DEFAULT; 64];
+    for (src, dst) in src
+        .chunks(src_bgra.len())
+        .zip(dst.chunks_mut(dst_bgra.len()))
+    {
+        let len = src.len().min(dst.len());
+        let (src_bgra, dst_bgra) = (&mut src_bgra[..len], &mut dst_bgra[..len]);
+        convert_row(src, src_bgra);
+        convert_row(dst, dst_bgra);
+        blend_bgra(src_bgra, dst_bgra, blend);
+        convert_row(dst_bgra, dst);
+    }
+}
+
+/// Composites the rows of a `src_size` image onto the rows of a `dst_size` image with its
+/// top-left corner at `pos`, clipping it to the destination, and returns the part of the
+/// destination that was drawn on.
+pub(crate) fn composite_rows<'a, 'b, S: PixelBufferFormat + 'a, D: PixelBufferFormat + 'b>(
+    src_rows: impl Iterator<Item = &'a [S]>,
+    src_size: (u32, u32),
+    dst_rows: impl Iterator<Item = &'b mut [D]>,
+    dst_size: (u32, u32),
+    pos: (i32, i32),
+    blend: Blend,
+) -> Rect {
+    // Work in `i64`, so neither the offset nor the far edges can overflow.
+    let clip = |pos: i32, src_len: u32, dst_len: u32| {
+        let start = i64::from(pos).max(0);
+        let end = (i64::from(pos) + i64::from(src_len)).min(i64::from(dst_len));
+        (
+            (start - i64::from(pos)) as u32,
+            start as u32,
+            (end - start).max(0) as u32,
+        )
+    };
+    let (src_x, dst_x, width) = clip(pos.0, src_size.0, dst_size.0);
+    let (src_y, dst_y, height) = clip(pos.1, src_size.1, dst_size.1);
+    if width == 0 || height == 0 {
+        return Rect::new(0, 0, 0, 0);
+    }
+    let (src_x, dst_x, len) = (src_x as usize, dst_x as usize, width as usize);
+    let src_rows = src_rows.skip(src_y as usize);
+    let dst_rows = dst_rows.skip(dst_y as usize).take(height as usize);
+    for (src, dst) in src_rows.zip(dst_rows) {
+        blend_row(&src[src_x..][..len], &mut dst[dst_x..][..len], blend);
+    }
+    Rect::new(dst_x as u32, dst_y, width, height)
+}
+
+fn blend_bgra(src: &[BGRA], dst: &mut [BGRA], blend: Blend) {
+    for (&src, dst) in src.iter().zip(dst) {
+        // Skip the arithmetic for the fully transparent and fully opaque pixels that make up most
+        // of a typical sprite.
+        match (blend.mode, src.a) {
+            (BlendMode::Source, _) | (BlendMode::SourceOver, 255) => {
+                *dst = src;
+                continue;
+            }
+            (_, 0) => continue,
+            _ => (),
+        }
+        let blended = blend_pixel(load(src, blend), load(*dst, blend), blend.mode);
+        *dst = store(blended, blend);
+    }
+}
+
+const MAX: u32 = 0xFFFF;
+
+/// Multiplies two 16-bit fractions.
+fn mul(a: u32, b: u32) -> u32 {
+    (a * b + MAX / 2) / MAX
+}
+
+/// Divides a 16-bit premultiplied color by its alpha.
+fn unpremultiply(c: u32, a: u32) -> u32 {
+    (c * MAX + a / 2).checked_div(a).map_or(0, |c| c.min(MAX))
+}
+
+/// Turns a pixel into 16-bit premultiplied channels, in BGRA order.
+fn load(pixel: BGRA, blend: Blend) -> [u32; 4] {
+    let a = u32::from(pixel.a) * 257;
+    let channel = |c: u8| match (blend.gamma_correct, blend.premultiplied) {
+        (false, false) => mul(u32::from(c) * 257, a),
+        (false, true) => u32::from(c) * 257,
+        (true, false) => mul(srgb_to_linear(c).into(), a),
+        (true, true) => {
+            let straight = (unpremultiply(u32::from(c) * 257, a) + 128) / 257;
+            mul(srgb_to_linear(straight as u8).into(), a)
+        }
+    };
+    [channel(pixel.b), channel(pixel.g), channel(pixel.r), a]
+}
+
+/// The inverse of [`load`].
+fn store(channels: [u32; 4], blend: Blend) -> BGRA {
+    let a = channels[3];
+    let channel = |c: u32| match (blend.gamma_correct, blend.premultiplied) {
+        (false, false) => ((unpremultiply(c, a) + 128) / 257) as u8,
+        (false, true) => ((c + 128) / 257) as u8,
+        (true, false) => linear_to_srgb(unpremultiply(c, a) as u16),
+        (true, true) => {
+            let straight = u32::from(linear_to_srgb(unpremultiply(c, a) as u16)) * 257;
+            ((mul(straight, a) + 128) / 257) as u8
+        }
+    };
+    BGRA::new(
+        channel(channels[0]),
+        channel(channels[1]),
+        channel(channels[2]),
+        ((a + 128) / 257) as u8,
+    )
+}
+
+/// Composites two pixels of 16-bit premultiplied channels.
+fn blend_pixel(src: [u32; 4], dst: [u32; 4], mode: BlendMode) -> [u32; 4] {
+    let (src_a, dst_a) = (src[3], dst[3]);
+    // `term` gives `src_a * dst_a * B(src, dst)` for the mode's blend function `B`, in terms of
+    // the premultiplied colors.
+    let separable = |term: fn(u32, u32, u32, u32) -> u32| {
+        let channel = |i: usize| {
+            let (s, d) = (src[i], dst[i]);
+            (mul(s, MAX - dst_a) + mul(d, MAX - src_a) + term(s, d, src_a, dst_a)).min(MAX)
+        };
+        [
+            channel(0),
+            channel(1),
+            channel(2),
+            src_a + dst_a - mul(src_a, dst_a),
+        ]
+    };
+    match mode {
+        BlendMode::Source => src,
+        BlendMode::SourceOver => {
+            let channel = |i: usize| (src[i] + mul(dst[i], MAX - src_a)).min(MAX);
+            [channel(0), channel(1), channel(2), channel(3)]
+        }
+        BlendMode::Add => {
+            let channel = |i: usize| (src[i] + dst[i]).min(MAX);
+            [channel(0), channel(1), channel(2), channel(3)]
+        }
+        BlendMode::Multiply => separable(|s, d, _, _| mul(s, d)),
+        BlendMode::Screen => separable(|s, d, src_a, dst_a| {
+            (mul(s, dst_a) + mul(d, src_a)).saturating_sub(mul(s, d))
+        }),
+        BlendMode::Darken => separable(|s, d, src_a, dst_a| mul(s, dst_a).min(mul(d, src_a))),
+        BlendMode::Lighten => separable(|s, d, src_a, dst_a| mul(s, dst_a).max(mul(d, src_a))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Gray8, RGBA};
+
+    const CLEAR: BGRA = BGRA::new(0, 0, 0, 0);
+
+    const MODES: [BlendMode; 7] = [
+        BlendMode::Source,
+        BlendMode::SourceOver,
+        BlendMode::Add,
+        BlendMode::Multiply,
+        BlendMode::Screen,
+        BlendMode::Darken,
+        BlendMode::Lighten,
+    ];
+
+    fn blends() -> impl Iterator<Item = Blend> {
+        MODES.iter().flat_map(|&mode| {
+            [(false, false), (false, true), (true, false), (true, true)]
+                .iter()
+                .map(move |&(gamma_correct, premultiplied)| Blend {
+                    mode,
+                    gamma_correct,
+                    premultiplied,
+                })
+        })
+    }
+
+    #[test]
+    fn opaque_modes() {
+        let src = BGRA::new(255, 128, 0, 255);
+        let dst = BGRA::new(128, 128, 128, 255);
+        let apply = |mode| Blend::new(mode).apply(src, dst);
+        assert_eq!(apply(BlendMode::Source), src);
+        assert_eq!(apply(BlendMode::SourceOver), src);
+        assert_eq!(apply(BlendMode::Add), BGRA::new(255, 255, 128, 255));
+        assert_eq!(apply(BlendMode::Multiply), BGRA::new(128, 64, 0, 255));
+        assert_eq!(apply(BlendMode::Screen), BGRA::new(255, 192, 128, 255));
+        assert_eq!(apply(BlendMode::Darken), BGRA::new(128, 128, 0, 255));
+        assert_eq!(apply(BlendMode::Lighten), BGRA::new(255, 128, 128, 255));
+    }
+
+    #[test]
+    fn source_over() {
+        let half_white = BGRA::new(255, 255, 255, 128);
+        let black = BGRA::new(0, 0, 0, 255);
+        let naive = Blend::new(BlendMode::SourceOver);
+        assert_eq!(
+            naive.apply(half_white, black),
+            BGRA::new(128, 128, 128, 255)
+        );
+        let gamma_correct = Blend {
+            gamma_correct: true,
+            ..naive
+        };
+        assert_eq!(
+            gamma_correct.apply(half_white, black),
+            BGRA::new(188, 188, 188, 255)
+        );
+
+        // Two translucent layers.
+        let half_red = BGRA::new(0, 0, 255, 128);
+        assert_eq!(
+            naive.apply(half_red, BGRA::new(255, 0, 0, 128)),
+            BGRA::new(85, 0, 170, 192)
+        );
+        // Anything over nothing is unchanged.
+        for blend in blends().filter(|blend| !blend.premultiplied) {
+            assert_eq!(blend.apply(half_red, CLEAR), half_red, "{:?}", blend);
+        }
+    }
+
+    #[test]
+    fn premultiplied() {
+        let blend = Blend {
+            premultiplied: true,
+            ..Blend::new(BlendMode::SourceOver)
+        };
+        let half_red = BGRA::new(0, 0, 128, 128);
+        assert_eq!(
+            blend.apply(half_red, BGRA::new(255, 255, 255, 255)),
+            BGRA::new(127, 127, 255, 255)
+        );
+        assert_eq!(blend.apply(half_red, CLEAR), half_red);
+        // Premultiplied colors can't be brighter than their alpha, so `Add` clamps each channel.
+        let add = Blend {
+            mode: BlendMode::Add,
+            ..blend
+        };
+        assert_eq!(add.apply(half_red, half_red), BGRA::new(0, 0, 255, 255));
+    }
+
+    #[test]
+    fn transparent_source_keeps_destination() {
+        let dst = BGRA::new(10, 20, 30, 200);
+        for blend in blends().filter(|blend| blend.mode != BlendMode::Source) {
+            assert_eq!(blend.apply(CLEAR, dst), dst, "{:?}", blend);
+        }
+    }
+
+    #[test]
+    fn blends_other_formats() {
+        let src = [RGBA::new(255, 0, 0, 255), RGBA::new(255, 0, 0, 0)];
+        let mut dst = [Gray8(50); 3];
+        blend_row(&src, &mut dst, Blend::new(BlendMode::SourceOver));
+        assert_eq!(dst, [Gray8::from_rgb(255, 0, 0), Gray8(50), Gray8(50)]);
+    }
+
+    #[test]
+    fn composite_rows_clips() {
+        let opaque = BGRA::new(1, 1, 1, 255);
+        let src = [[opaque; 3]; 2];
+        let mut dst = [[CLEAR; 4]; 3];
+        let mut composite = |pos| {
+            composite_rows(
+                src.iter().map(|row| &row[..]),
+                (3, 2),
+                dst.iter_mut().map(|row| &mut row[..]),
+                (4, 3),
+                pos,
+                Blend::new(BlendMode::SourceOver),
+            )
+        };
+        assert_eq!(composite((2, -1)), Rect::new(2, 0, 2, 1));
+        assert_eq!(composite((-3, 0)), Rect::new(0, 0, 0, 0));
+        assert_eq!(composite((i32::MAX, 0)), Rect::new(0, 0, 0, 0));
+        assert_eq!(composite((-1, 2)), Rect::new(0, 2, 2, 1));
+        assert_eq!(
+            dst,
+            [
+                [CLEAR, CLEAR, opaque, opaque],
+                [CLEAR; 4],
+                [opaque, opaque, CLEAR, CLEAR],
+            ]
+        );
+    }
+}
@@ -0,0 +1,625 @@
+//! Fast conversion between pixel formats.
+//!
+//! Rows of pixels can be converted between any two [`PixelBufferFormat`]s, with SIMD kernels for
+//! the common cases that are picked at runtime based on what the CPU supports.
+
+use crate::{
+    Gray16, Gray8, Indexed8, PixelBufferFormat, PixelBufferFormatType, ARGB2101010, BGRA, RGB555,
+    RGB565,
+};
+
+#[cfg(target_arch = "aarch64")]
+mod neon;
+/// Portable versions of the conversion kernels. The SIMD versions fall back on these for the
+/// pixels at the end of a row.
+///
+/// Every kernel expects `src` and `dst` to hold the same number of pixels.
+mod scalar;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod x86;
+
+/// Runs the fastest version of a conversion kernel the CPU supports.
+///
+/// Each SIMD version is listed along with the CPU feature it needs, fastest first.
+macro_rules! kernel {
+    (
+        $scalar:ident $args:tt,
+        x86: [$($x86_feature:tt => $x86:ident),*],
+        aarch64: $aarch64:ident
+    ) => {{
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        {
+            $(
+                if is_x86_feature_detected!($x86_feature) {
+                    return unsafe { x86::$x86 $args };
+                }
+            )*
+        }
+        #[cfg(target_arch = "aarch64")]
+        {
+            if std::arch::is_aarch64_feature_detected!("neon") {
+                return unsafe { neon::$aarch64 $args };
+            }
+        }
+        scalar::$scalar $args
+    }};
+}
+
+fn swap_rb4(src: &[u8], dst: &mut [u8]) {
+    kernel!(
+        swap_rb4(src, dst),
+        x86: ["avx2" => swap_rb4_avx2, "sse2" => swap_rb4_sse2],
+        aarch64: swap_rb4
+    )
+}
+
+fn swap_rb3(src: &[u8], dst: &mut [u8]) {
+    kernel!(
+        swap_rb3(src, dst),
+        x86: ["ssse3" => swap_rb3_ssse3],
+        aarch64: swap_rb3
+    )
+}
+
+fn expand(src: &[u8], dst: &mut [u8], swap: bool) {
+    kernel!(
+        expand(src, dst, swap),
+        x86: ["ssse3" => expand_ssse3],
+        aarch64: expand
+    )
+}
+
+fn pack(src: &[u8], dst: &mut [u8], swap: bool) {
+    kernel!(
+        pack(src, dst, swap),
+        x86: ["ssse3" => pack_ssse3],
+        aarch64: pack
+    )
+}
+
+fn fill_alpha4(pixels: &mut [u8], alpha: u8) {
+    kernel!(
+        fill_alpha(pixels, alpha),
+        x86: ["avx2" => fill_alpha_avx2, "sse2" => fill_alpha_sse2],
+        aarch64: fill_alpha
+    )
+}
+
+/// Whether pixels of `format` start with red rather than blue, or `None` if the format doesn't
+/// have a whole byte per channel.
+fn rgb_order(format: PixelBufferFormatType) -> Option<bool> {
+    match format {
+        PixelBufferFormatType::RGB | PixelBufferFormatType::RGBA => Some(true),
+        PixelBufferFormatType::BGR | PixelBufferFormatType::BGRA => Some(false),
+        PixelBufferFormatType::RGB565
+        | PixelBufferFormatType::RGB555
+        | PixelBufferFormatType::ARGB2101010
+        | PixelBufferFormatType::Gray8
+        | PixelBufferFormatType::Gray16
+        | PixelBufferFormatType::Indexed8 => None,
+    }
+}
+
+/// Converts a row of `S` pixels into `D` pixels, stopping at the end of whichever row is shorter.
+///
+/// Pixels are opaque if `S` has no alpha channel, and colors are reduced to their luma when `D` is
+/// grayscale. [`Indexed8`] pixels are treated as indices into
+/// [`Indexed8::GRAYSCALE_PALETTE`]; use [`convert_indexed_row`] for other palettes.
+///
+/// Whichever SIMD instructions the CPU supports are picked at runtime for formats with a whole
+/// byte per channel, so this is much faster than converting one pixel at a time.
+///
+/// ```
+/// use winit_blit::{convert::convert_row, BGRA, RGB};
+///
+/// let src = [RGB::new(1, 2, 3); 64];
+/// let mut dst = [BGRA::DEFAULT; 64];
+/// convert_row(&src, &mut dst);
+/// assert_eq!(dst[63], BGRA::new(3, 2, 1, 255));
+/// ```
+pub fn convert_row<S: PixelBufferFormat, D: PixelBufferFormat>(src: &[S], dst: &mut [D]) {
+    convert_raw_row(
+        S::to_raw_slice(src),
+        S::FORMAT_TYPE,
+        D::to_raw_slice_mut(dst),
+        D::FORMAT_TYPE,
+    )
+}
+
+/// Converts a row of raw `src_format` pixels into `dst_format`, stopping at the end of whichever
+/// row is shorter.
+///
+/// See [`convert_row`].
+pub fn convert_raw_row(
+    src: &[u8],
+    src_format: PixelBufferFormatType,
+    dst: &mut [u8],
+    dst_format: PixelBufferFormatType,
+) {
+    let src_bytes_per_pixel = src_format.bytes_per_pixel();
+    let dst_bytes_per_pixel = dst_format.bytes_per_pixel();
+    let pixels = (src.len() / src_bytes_per_pixel).min(dst.len() / dst_bytes_per_pixel);
+    let src = &src[..pixels * src_bytes_per_pixel];
+    let dst = &mut dst[..pixels * dst_bytes_per_pixel];
+
+    if src_format == dst_format {
+        return dst.copy_from_slice(src);
+    }
+    let (src_rgb, dst_rgb) = match (rgb_order(src_format), rgb_order(dst_format)) {
+        (Some(src_rgb), Some(dst_rgb)) => (src_rgb, dst_rgb),
+        // Everything else goes through BGRA a chunk at a time.
+        _ => {
+            let mut bgra = [BGRA::DEFAULT; 64];
+            let src_chunks = src.chunks(bgra.len() * src_bytes_per_pixel);
+            let dst_chunks = dst.chunks_mut(bgra.len() * dst_bytes_per_pixel);
+            for (src, dst) in src_chunks.zip(dst_chunks) {
+                let bgra = &mut bgra[..src.len() / src_bytes_per_pixel];
+                to_bgra(src, src_format, bgra);
+                from_bgra(bgra, dst, dst_format);
+            }
+            return;
+        }
+    };
+
+    let swap = src_rgb != dst_rgb;
+    match (src_bytes_per_pixel, dst_bytes_per_pixel) {
+        (4, 4) => swap_rb4(src, dst),
+        (3, 3) => swap_rb3(src, dst),
+        (3, 4) => expand(src, dst, swap),
+        (4, 3) => pack(src, dst, swap),
+        _ => unreachable!(),
+    }
+}
+
+/// Looks up a row of [`Indexed8`] pixels in `palette`, and converts the colors into `D` pixels,
+/// stopping at the end of whichever row is shorter.
+///
+/// ```
+/// use winit_blit::{convert::convert_indexed_row, Indexed8, BGRA, RGB};
+///
+/// let mut palette = Indexed8::GRAYSCALE_PALETTE;
+/// palette[1] = BGRA::new(255, 0, 0, 255);
+/// let mut dst = [RGB::DEFAULT; 2];
+/// convert_indexed_row(&[Indexed8(1), Indexed8(2)], &palette, &mut dst);
+/// assert_eq!(dst, [RGB::new(0, 0, 255), RGB::new(2, 2, 2)]);
+/// ```
+pub fn convert_indexed_row<D: PixelBufferFormat>(
+    src: &[Indexed8],
+    palette: &[BGRA; 256],
+    dst: &mut [D],
+) {
+    convert_raw_indexed_row(
+        Indexed8::to_raw_slice(src),
+        palette,
+        D::to_raw_slice_mut(dst),
+        D::FORMAT_TYPE,
+    )
+}
+
+/// Looks up a row of raw [`Indexed8`] pixels in `palette`, and converts the colors into
+/// `dst_format`, stopping at the end of whichever row is shorter.
+///
+/// See [`convert_indexed_row`].
+pub fn convert_raw_indexed_row(
+    src: &[u8],
+    palette: &[BGRA; 256],
+    dst: &mut [u8],
+    dst_format: PixelBufferFormatType,
+) {
+    let dst_bytes_per_pixel = dst_format.bytes_per_pixel();
+    let pixels = src.len().min(dst.len() / dst_bytes_per_pixel);
+    let src = &src[..pixels];
+    let dst = &mut dst[..pixels * dst_bytes_per_pixel];
+
+    if dst_format == PixelBufferFormatType::BGRA {
+        for (&index, dst) in src.iter().zip(BGRA::from_raw_slice_mut(dst)) {
+            *dst = palette[index as usize];
+        }
+        return;
+    }
+    let mut bgra = [BGRA::DEFAULT; 64];
+    let dst_chunks = dst.chunks_mut(bgra.len() * dst_bytes_per_pixel);
+    for (src, dst) in src.chunks(bgra.len()).zip(dst_chunks) {
+        let bgra = &mut bgra[..src.len()];
+        for (&index, bgra) in src.iter().zip(bgra.iter_mut()) {
+            *bgra = palette[index as usize];
+        }
+        from_bgra(bgra, dst, dst_format);
+    }
+}
+
+/// Converts a row of `format` pixels into BGRA. The rows must be the same length.
+fn to_bgra(src: &[u8], format: PixelBufferFormatType, dst: &mut [BGRA]) {
+    fn unpack<P: PixelBufferFormat>(src: &[u8], dst: &mut [BGRA], f: impl Fn(P) -> BGRA) {
+        for (src, dst) in P::from_raw_slice(src).iter().zip(dst) {
+            *dst = f(*src);
+        }
+    }
+    let rgb = |(r, g, b)| BGRA::new(b, g, r, 255);
+    match format {
+        PixelBufferFormatType::RGB565 => unpack(src, dst, |p: RGB565| rgb(p.to_rgb())),
+        PixelBufferFormatType::RGB555 => unpack(src, dst, |p: RGB555| rgb(p.to_rgb())),
+        PixelBufferFormatType::ARGB2101010 => unpack(src, dst, |p: ARGB2101010| {
+            let (r, g, b, a) = p.to_rgba();
+            BGRA::new(b, g, r, a)
+        }),
+        PixelBufferFormatType::Gray8 => unpack(src, dst, |p: Gray8| {
+            let v = p.0;
+            rgb((v, v, v))
+        }),
+        PixelBufferFormatType::Indexed8 => convert_raw_indexed_row(
+            src,
+            &Indexed8::GRAYSCALE_PALETTE,
+            BGRA::to_raw_slice_mut(dst),
+            PixelBufferFormatType::BGRA,
+        ),
+        PixelBufferFormatType::Gray16 => unpack(src, dst, |p: Gray16| {
+            let v = (p.0 >> 8) as u8;
+            rgb((v, v, v))
+        }),
+        _ => convert_raw_row(
+            src,
+            format,
+            BGRA::to_raw_slice_mut(dst),
+            PixelBufferFormatType::BGRA,
+        ),
+    }
+}
+
+/// Converts a row of BGRA pixels into `format`. The rows must be the same length.
+fn from_bgra(src: &[BGRA], dst: &mut [u8], format: PixelBufferFormatType) {
+    fn pack<P: PixelBufferFormat>(src: &[BGRA], dst: &mut [u8], f: impl Fn(BGRA) -> P) {
+        for (src, dst) in src.iter().zip(P::from_raw_slice_mut(dst)) {
+            *dst = f(*src);
+        }
+    }
+    match format {
+        PixelBufferFormatType::ARGB2101010 => pack(src, dst, |p| {
+            with_alpha(ARGB2101010::from_rgb(p.r, p.g, p.b), p.a)
+        }),
+        PixelBufferFormatType::RGB565 => pack(src, dst, |p| RGB565::from_rgb(p.r, p.g, p.b)),
+        PixelBufferFormatType::RGB555 => pack(src, dst, |p| RGB555::from_rgb(p.r, p.g, p.b)),
+        PixelBufferFormatType::Gray8 => pack(src, dst, |p| Gray8::from_rgb(p.r, p.g, p.b)),
+        PixelBufferFormatType::Gray16 => pack(src, dst, |p| Gray16::from_rgb(p.r, p.g, p.b)),
+        PixelBufferFormatType::Indexed8 => pack(src, dst, |p| Indexed8::from_rgb(p.r, p.g, p.b)),
+        _ => convert_raw_row(
+            BGRA::to_raw_slice(src),
+            PixelBufferFormatType::BGRA,
+            dst,
+            format,
+        ),
+    }
+}
+
+/// Replaces the alpha of `pixel` with the top bits of `alpha`.
+fn with_alpha(pixel: ARGB2101010, alpha: u8) -> ARGB2101010 {
+    ARGB2101010(pixel.0 & !(0b11 << 30) | (alpha as u32 >> 6) << 30)
+}
+
+/// Sets the alpha of every pixel in `row` to `alpha`.
+///
+/// Does nothing if `P` has no alpha channel.
+pub fn fill_alpha<P: PixelBufferFormat>(row: &mut [P], alpha: u8) {
+    fill_raw_alpha(P::to_raw_slice_mut(row), P::FORMAT_TYPE, alpha)
+}
+
+/// Sets the alpha of every pixel in a row of raw `format` pixels to `alpha`.
+///
+/// See [`fill_alpha`].
+pub fn fill_raw_alpha(row: &mut [u8], format: PixelBufferFormatType, alpha: u8) {
+    match format {
+        PixelBufferFormatType::BGRA | PixelBufferFormatType::RGBA => {
+            let len = row.len() / 4 * 4;
+            fill_alpha4(&mut row[..len], alpha);
+        }
+        PixelBufferFormatType::ARGB2101010 => {
+            let len = row.len() / 4 * 4;
+            for pixel in ARGB2101010::from_raw_slice_mut(&mut row[..len]) {
+                *pixel = with_alpha(*pixel, alpha);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{BGR, RGB, RGBA};
+    use PixelBufferFormatType as Format;
+
+    #[test]
+    fn converts_between_every_format() {
+        // Two pixels, with red, green, blue and alpha of 1, 2, 3, 4 and 5, 6, 7, 8.
+        let pixels = |format| match format {
+            Format::BGR => vec![3, 2, 1, 7, 6, 5],
+            Format::BGRA => vec![3, 2, 1, 4, 7, 6, 5, 8],
+            Format::RGB => vec![1, 2, 3, 5, 6, 7],
+            Format::RGBA => vec![1, 2, 3, 4, 5, 6, 7, 8],
+            _ => unreachable!(),
+        };
+        let formats = [Format::BGR, Format::BGRA, Format::RGB, Format::RGBA];
+        for &src_format in &formats {
+            for &dst_format in &formats {
+                let mut dst = vec![0; 2 * dst_format.bytes_per_pixel()];
+                convert_raw_row(&pixels(src_format), src_format, &mut dst, dst_format);
+
+                let mut expected = pixels(dst_format);
+                if dst_format.bytes_per_pixel() > src_format.bytes_per_pixel() {
+                    expected[3] = 255;
+                    expected[7] = 255;
+                }
+                assert_eq!(dst, expected, "{:?} -> {:?}", src_format, dst_format);
+            }
+        }
+    }
+
+    #[test]
+    fn stops_at_shorter_row() {
+        let mut dst = [0; 7];
+        convert_raw_row(
+            &[1, 2, 3, 4, 5, 6, 7, 8],
+            Format::RGBA,
+            &mut dst,
+            Format::BGR,
+        );
+        assert_eq!(dst, [3, 2, 1, 7, 6, 5, 0]);
+
+        let mut dst = [0; 7];
+        convert_raw_row(
+            &[1, 2, 3, 4, 5, 6, 7, 8],
+            Format::RGBA,
+            &mut dst,
+            Format::RGBA,
+        );
+        assert_eq!(dst, [1, 2, 3, 4, 0, 0, 0]);
+    }
+
+    #[test]
+    fn typed() {
+        let src = [RGBA::new(1, 2, 3, 4); 33];
+        let mut dst = [BGRA::DEFAULT; 40];
+        convert_row(&src, &mut dst);
+        assert!(dst[..33].iter().all(|&p| p == BGRA::new(3, 2, 1, 4)));
+        assert_eq!(dst[33], BGRA::DEFAULT);
+
+        fill_alpha(&mut dst, 7);
+        assert!(dst.iter().all(|p| p.a == 7));
+    }
+
+    #[test]
+    fn packed_channels() {
+        assert_eq!(RGB565::from_rgb(255, 128, 7), RGB565(31 << 11 | 32 << 5));
+        assert_eq!(RGB565(31 << 11 | 32 << 5).to_rgb(), (255, 130, 0));
+        assert_eq!(
+            RGB555::from_rgb(255, 128, 8),
+            RGB555(31 << 10 | 16 << 5 | 1)
+        );
+        assert_eq!(RGB555(31 << 10 | 16 << 5 | 1).to_rgb(), (255, 132, 8));
+
+        let argb = ARGB2101010::from_rgb(255, 128, 0);
+        assert_eq!(argb, ARGB2101010::new(0x3ff, 0x202, 0, 0b11));
+        assert_eq!(argb.to_rgba(), (255, 128, 0, 255));
+        assert_eq!(ARGB2101010::new(0, 0, 0, 1).to_rgba(), (0, 0, 0, 85));
+        assert_eq!(ARGB2101010::DEFAULT.to_rgba(), (0, 0, 0, 255));
+
+        assert_eq!(Gray8::from_rgb(255, 255, 255), Gray8(255));
+        assert_eq!(Gray8::from_rgb(100, 100, 100), Gray8(100));
+        assert_eq!(Gray8::from_rgb(255, 0, 0), Gray8(76));
+        assert_eq!(Gray16::from_rgb(255, 255, 255), Gray16(65535));
+        assert_eq!(Gray16::from_rgb(100, 100, 100), Gray16(100 * 257));
+    }
+
+    #[test]
+    fn converts_packed_formats() {
+        let mut dst = [RGB565::DEFAULT; 2];
+        convert_row(
+            &[RGBA::new(255, 128, 7, 0), RGBA::new(0, 0, 255, 0)],
+            &mut dst,
+        );
+        assert_eq!(dst, [RGB565(31 << 11 | 32 << 5), RGB565(31)]);
+
+        let mut dst = [BGR::DEFAULT; 2];
+        convert_row(&[RGB565(31 << 11 | 32 << 5), RGB565(31)], &mut dst);
+        assert_eq!(dst, [BGR::new(0, 130, 255), BGR::new(255, 0, 0)]);
+
+        let mut dst = [ARGB2101010::DEFAULT; 1];
+        convert_row(&[BGRA::new(0, 128, 255, 100)], &mut dst);
+        assert_eq!(dst, [ARGB2101010::new(0x3ff, 0x202, 0, 1)]);
+
+        let mut dst = [Gray16::DEFAULT; 1];
+        convert_row(&[RGB555(0x7fff)], &mut dst);
+        assert_eq!(dst, [Gray16(65535)]);
+    }
+
+    #[test]
+    fn every_format_round_trips_black_and_white() {
+        let formats = [
+            Format::BGR,
+            Format::BGRA,
+            Format::RGB,
+            Format::RGBA,
+            Format::RGB565,
+            Format::RGB555,
+            Format::ARGB2101010,
+            Format::Gray8,
+            Format::Gray16,
+            Format::Indexed8,
+        ];
+        // More pixels than fit in one chunk of the conversion through BGRA.
+        let src: Vec<BGRA> = (0..100)
+            .map(|i| match i % 2 {
+                0 => BGRA::new(0, 0, 0, 255),
+                _ => BGRA::new(255, 255, 255, 255),
+            })
+            .collect();
+        for &src_format in &formats {
+            for &dst_format in &formats {
+                let mut a = vec![0; src.len() * src_format.bytes_per_pixel()];
+                let mut b = vec![0; src.len() * dst_format.bytes_per_pixel()];
+                let mut back = vec![BGRA::DEFAULT; src.len()];
+                convert_raw_row(BGRA::to_raw_slice(&src), Format::BGRA, &mut a, src_format);
+                convert_raw_row(&a, src_format, &mut b, dst_format);
+                convert_raw_row(
+                    &b,
+                    dst_format,
+                    BGRA::to_raw_slice_mut(&mut back),
+                    Format::BGRA,
+                );
+                assert_eq!(back, src, "{:?} -> {:?}", src_format, dst_format);
+            }
+        }
+    }
+
+    #[test]
+    fn converts_indexed() {
+        let mut palette = Indexed8::GRAYSCALE_PALETTE;
+        palette[200] = BGRA::new(1, 2, 3, 4);
+        let src: Vec<Indexed8> = (0..100).map(|i| Indexed8(i as u8 * 2)).collect();
+        let mut rgb565 = [RGB565::DEFAULT; 100];
+        convert_indexed_row(&src, &palette, &mut rgb565);
+        let mut bgra = [BGRA::DEFAULT; 101];
+        convert_indexed_row(&src, &palette, &mut bgra);
+        for (i, (&rgb565, &bgra)) in rgb565.iter().zip(&bgra).enumerate() {
+            let v = i as u8 * 2;
+            let expected = if v == 200 {
+                BGRA::new(1, 2, 3, 4)
+            } else {
+                BGRA::new(v, v, v, 255)
+            };
+            assert_eq!(bgra, expected);
+            assert_eq!(rgb565, RGB565::from_rgb(expected.r, expected.g, expected.b));
+        }
+        assert_eq!(bgra[100], BGRA::DEFAULT);
+
+        // Without a palette, indices are gray levels.
+        let mut gray = [Gray8::DEFAULT; 2];
+        convert_row(&[Indexed8(9), Indexed8(200)], &mut gray);
+        assert_eq!(gray, [Gray8(9), Gray8(200)]);
+        let mut indexed = [Indexed8::DEFAULT; 1];
+        convert_row(&[RGB::new(255, 255, 255)], &mut indexed);
+        assert_eq!(indexed, [Indexed8(255)]);
+    }
+
+    #[test]
+    fn fills_packed_alpha() {
+        let mut row = [ARGB2101010::new(1, 2, 3, 0); 3];
+        fill_alpha(&mut row, 0x80);
+        assert_eq!(row, [ARGB2101010::new(1, 2, 3, 0b10); 3]);
+
+        let mut row = [RGB565(0x1234); 3];
+        fill_alpha(&mut row, 0);
+        assert_eq!(row, [RGB565(0x1234); 3]);
+    }
+
+    /// Checks that `simd` gives the same results as `scalar` for rows of every length up to a
+    /// few SIMD registers' worth of pixels, starting at every alignment.
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64"))]
+    fn assert_matches_scalar(
+        src_bytes_per_pixel: usize,
+        dst_bytes_per_pixel: usize,
+        scalar: impl Fn(&[u8], &mut [u8]),
+        simd: impl Fn(&[u8], &mut [u8]),
+    ) {
+        // 167 is coprime with 256, so every byte value shows up.
+        let bytes: Vec<u8> = (0..2048u32).map(|i| (i * 167 + 13) as u8).collect();
+        for offset in 0..4 {
+            for pixels in 0..=100 {
+                let src = &bytes[offset..][..pixels * src_bytes_per_pixel];
+                let mut expected = vec![0xaa; pixels * dst_bytes_per_pixel];
+                let mut actual = expected.clone();
+                scalar(src, &mut expected);
+                simd(src, &mut actual);
+                assert_eq!(actual, expected, "{} pixels at offset {}", pixels, offset);
+            }
+        }
+    }
+
+    /// Runs `fill_alpha` on a copy of `src`, so it can be checked like the other kernels.
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64"))]
+    fn fill_alpha_copy(fill_alpha: impl Fn(&mut [u8], u8)) -> impl Fn(&[u8], &mut [u8]) {
+        move |src, dst| {
+            dst.copy_from_slice(src);
+            fill_alpha(dst, 0x42);
+        }
+    }
+
+    #[test]
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    fn x86_matches_scalar() {
+        if is_x86_feature_detected!("sse2") {
+            let sse2 = |src: &[u8], dst: &mut [u8]| unsafe { x86::swap_rb4_sse2(src, dst) };
+            assert_matches_scalar(4, 4, scalar::swap_rb4, sse2);
+            let sse2 = |pixels: &mut [u8], alpha| unsafe { x86::fill_alpha_sse2(pixels, alpha) };
+            assert_matches_scalar(
+                4,
+                4,
+                fill_alpha_copy(scalar::fill_alpha),
+                fill_alpha_copy(sse2),
+            );
+        }
+        if is_x86_feature_detected!("avx2") {
+            let avx2 = |src: &[u8], dst: &mut [u8]| unsafe { x86::swap_rb4_avx2(src, dst) };
+            assert_matches_scalar(4, 4, scalar::swap_rb4, avx2);
+            let avx2 = |pixels: &mut [u8], alpha| unsafe { x86::fill_alpha_avx2(pixels, alpha) };
+            assert_matches_scalar(
+                4,
+                4,
+                fill_alpha_copy(scalar::fill_alpha),
+                fill_alpha_copy(avx2),
+            );
+        }
+        if is_x86_feature_detected!("ssse3") {
+            let ssse3 = |src: &[u8], dst: &mut [u8]| unsafe { x86::swap_rb3_ssse3(src, dst) };
+            assert_matches_scalar(3, 3, scalar::swap_rb3, ssse3);
+            for &swap in &[false, true] {
+                assert_matches_scalar(
+                    3,
+                    4,
+                    |src, dst| scalar::expand(src, dst, swap),
+                    |src, dst| unsafe { x86::expand_ssse3(src, dst, swap) },
+                );
+                assert_matches_scalar(
+                    4,
+                    3,
+                    |src, dst| scalar::pack(src, dst, swap),
+                    |src, dst| unsafe { x86::pack_ssse3(src, dst, swap) },
+                );
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(target_arch = "aarch64")]
+    fn neon_matches_scalar() {
+        if !std::arch::is_aarch64_feature_detected!("neon") {
+            return;
+        }
+        let swap_rb4 = |src: &[u8], dst: &mut [u8]| unsafe { neon::swap_rb4(src, dst) };
+        assert_matches_scalar(4, 4, scalar::swap_rb4, swap_rb4);
+        let swap_rb3 = |src: &[u8], dst: &mut [u8]| unsafe { neon::swap_rb3(src, dst) };
+        assert_matches_scalar(3, 3, scalar::swap_rb3, swap_rb3);
+        let fill_alpha = |pixels: &mut [u8], alpha| unsafe { neon::fill_alpha(pixels, alpha) };
+        assert_matches_scalar(
+            4,
+            4,
+            fill_alpha_copy(scalar::fill_alpha),
+            fill_alpha_copy(fill_alpha),
+        );
+        for &swap in &[false, true] {
+            assert_matches_scalar(
+                3,
+                4,
+                |src, dst| scalar::expand(src, dst, swap),
+                |src, dst| unsafe { neon::expand(src, dst, swap) },
+            );
+            assert_matches_scalar(
+                4,
+                3,
+                |src, dst| scalar::pack(src, dst, swap),
+                |src, dst| unsafe { neon::pack(src, dst, swap) },
+            );
+        }
+    }
+}
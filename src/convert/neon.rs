@@ -0,0 +1,69 @@
+use std::arch::aarch64::*;
+
+use super::scalar;
+
+// The interleaved loads and stores split 16 pixels at a time into one register per channel, so
+// every kernel is just a matter of rearranging registers.
+
+#[target_feature(enable = "neon")]
+pub unsafe fn swap_rb4(src: &[u8], dst: &mut [u8]) {
+    let len = src.len() / 64 * 64;
+    for i in (0..len).step_by(64) {
+        let v = vld4q_u8(src.as_ptr().add(i));
+        vst4q_u8(dst.as_mut_ptr().add(i), uint8x16x4_t(v.2, v.1, v.0, v.3));
+    }
+    scalar::swap_rb4(&src[len..], &mut dst[len..]);
+}
+
+#[target_feature(enable = "neon")]
+pub unsafe fn swap_rb3(src: &[u8], dst: &mut [u8]) {
+    let len = src.len() / 48 * 48;
+    for i in (0..len).step_by(48) {
+        let v = vld3q_u8(src.as_ptr().add(i));
+        vst3q_u8(dst.as_mut_ptr().add(i), uint8x16x3_t(v.2, v.1, v.0));
+    }
+    scalar::swap_rb3(&src[len..], &mut dst[len..]);
+}
+
+#[target_feature(enable = "neon")]
+pub unsafe fn expand(src: &[u8], dst: &mut [u8], swap: bool) {
+    let alpha = vdupq_n_u8(255);
+    let pixels = src.len() / 3 / 16 * 16;
+    for i in (0..pixels).step_by(16) {
+        let v = vld3q_u8(src.as_ptr().add(i * 3));
+        let v = if swap {
+            uint8x16x4_t(v.2, v.1, v.0, alpha)
+        } else {
+            uint8x16x4_t(v.0, v.1, v.2, alpha)
+        };
+        vst4q_u8(dst.as_mut_ptr().add(i * 4), v);
+    }
+    scalar::expand(&src[pixels * 3..], &mut dst[pixels * 4..], swap);
+}
+
+#[target_feature(enable = "neon")]
+pub unsafe fn pack(src: &[u8], dst: &mut [u8], swap: bool) {
+    let pixels = src.len() / 4 / 16 * 16;
+    for i in (0..pixels).step_by(16) {
+        let v = vld4q_u8(src.as_ptr().add(i * 4));
+        let v = if swap {
+            uint8x16x3_t(v.2, v.1, v.0)
+        } else {
+            uint8x16x3_t(v.0, v.1, v.2)
+        };
+        vst3q_u8(dst.as_mut_ptr().add(i * 3), v);
+    }
+    scalar::pack(&src[pixels * 4..], &mut dst[pixels * 3..], swap);
+}
+
+#[target_feature(enable = "neon")]
+pub unsafe fn fill_alpha(pixels: &mut [u8], alpha: u8) {
+    let alpha_bits = vdupq_n_u8(alpha);
+    let len = pixels.len() / 64 * 64;
+    for i in (0..len).step_by(64) {
+        let p = pixels.as_mut_ptr().add(i);
+        let v = vld4q_u8(p);
+        vst4q_u8(p, uint8x16x4_t(v.0, v.1, v.2, alpha_bits));
+    }
+    scalar::fill_alpha(&mut pixels[len..], alpha);
+}
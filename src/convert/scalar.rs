@@ -0,0 +1,43 @@
+/// Swaps the red and blue channels of 4-byte pixels.
+pub fn swap_rb4(src: &[u8], dst: &mut [u8]) {
+    for (src, dst) in src.chunks_exact(4).zip(dst.chunks_exact_mut(4)) {
+        dst.copy_from_slice(&[src[2], src[1], src[0], src[3]]);
+    }
+}
+
+/// Swaps the red and blue channels of 3-byte pixels.
+pub fn swap_rb3(src: &[u8], dst: &mut [u8]) {
+    for (src, dst) in src.chunks_exact(3).zip(dst.chunks_exact_mut(3)) {
+        dst.copy_from_slice(&[src[2], src[1], src[0]]);
+    }
+}
+
+/// Expands 3-byte pixels into opaque 4-byte ones, swapping red and blue if `swap` is set.
+pub fn expand(src: &[u8], dst: &mut [u8], swap: bool) {
+    for (src, dst) in src.chunks_exact(3).zip(dst.chunks_exact_mut(4)) {
+        if swap {
+            dst.copy_from_slice(&[src[2], src[1], src[0], 255]);
+        } else {
+            dst.copy_from_slice(&[src[0], src[1], src[2], 255]);
+        }
+    }
+}
+
+/// Packs 4-byte pixels into 3-byte ones by dropping alpha, swapping red and blue if `swap` is
+/// set.
+pub fn pack(src: &[u8], dst: &mut [u8], swap: bool) {
+    for (src, dst) in src.chunks_exact(4).zip(dst.chunks_exact_mut(3)) {
+        if swap {
+            dst.copy_from_slice(&[src[2], src[1], src[0]]);
+        } else {
+            dst.copy_from_slice(&src[..3]);
+        }
+    }
+}
+
+/// Sets the last byte of every 4-byte pixel to `alpha`.
+pub fn fill_alpha(pixels: &mut [u8], alpha: u8) {
+    for pixel in pixels.chunks_exact_mut(4) {
+        pixel[3] = alpha;
+    }
+}
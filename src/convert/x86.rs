@@ -0,0 +1,126 @@
+#[cfg(target_arch = "x86")]
+use std::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+use super::scalar;
+
+// Pixels are loaded as little-endian 32-bit lanes, so the first byte of a 4-byte pixel is its
+// low byte.
+const RED_BLUE_MASK: i32 = 0xff;
+const GREEN_ALPHA_MASK: i32 = 0xff00_ff00_u32 as i32;
+const COLOR_MASK: i32 = 0x00ff_ffff;
+
+/// `pshufb` control bytes that zero the destination byte.
+const Z: i8 = -128;
+
+#[target_feature(enable = "sse2")]
+pub unsafe fn swap_rb4_sse2(src: &[u8], dst: &mut [u8]) {
+    let red_blue = _mm_set1_epi32(RED_BLUE_MASK);
+    let green_alpha = _mm_set1_epi32(GREEN_ALPHA_MASK);
+    let len = src.len() / 16 * 16;
+    for i in (0..len).step_by(16) {
+        let v = _mm_loadu_si128(src.as_ptr().add(i) as *const __m128i);
+        let low = _mm_and_si128(_mm_srli_epi32(v, 16), red_blue);
+        let high = _mm_slli_epi32(_mm_and_si128(v, red_blue), 16);
+        let v = _mm_or_si128(_mm_and_si128(v, green_alpha), _mm_or_si128(low, high));
+        _mm_storeu_si128(dst.as_mut_ptr().add(i) as *mut __m128i, v);
+    }
+    scalar::swap_rb4(&src[len..], &mut dst[len..]);
+}
+
+#[target_feature(enable = "avx2")]
+pub unsafe fn swap_rb4_avx2(src: &[u8], dst: &mut [u8]) {
+    let red_blue = _mm256_set1_epi32(RED_BLUE_MASK);
+    let green_alpha = _mm256_set1_epi32(GREEN_ALPHA_MASK);
+    let len = src.len() / 32 * 32;
+    for i in (0..len).step_by(32) {
+        let v = _mm256_loadu_si256(src.as_ptr().add(i) as *const __m256i);
+        let low = _mm256_and_si256(_mm256_srli_epi32(v, 16), red_blue);
+        let high = _mm256_slli_epi32(_mm256_and_si256(v, red_blue), 16);
+        let v = _mm256_or_si256(_mm256_and_si256(v, green_alpha), _mm256_or_si256(low, high));
+        _mm256_storeu_si256(dst.as_mut_ptr().add(i) as *mut __m256i, v);
+    }
+    scalar::swap_rb4(&src[len..], &mut dst[len..]);
+}
+
+#[target_feature(enable = "sse2")]
+pub unsafe fn fill_alpha_sse2(pixels: &mut [u8], alpha: u8) {
+    let color = _mm_set1_epi32(COLOR_MASK);
+    let alpha_bits = _mm_set1_epi32((alpha as i32) << 24);
+    let len = pixels.len() / 16 * 16;
+    for i in (0..len).step_by(16) {
+        let p = pixels.as_mut_ptr().add(i) as *mut __m128i;
+        let v = _mm_or_si128(_mm_and_si128(_mm_loadu_si128(p), color), alpha_bits);
+        _mm_storeu_si128(p, v);
+    }
+    scalar::fill_alpha(&mut pixels[len..], alpha);
+}
+
+#[target_feature(enable = "avx2")]
+pub unsafe fn fill_alpha_avx2(pixels: &mut [u8], alpha: u8) {
+    let color = _mm256_set1_epi32(COLOR_MASK);
+    let alpha_bits = _mm256_set1_epi32((alpha as i32) << 24);
+    let len = pixels.len() / 32 * 32;
+    for i in (0..len).step_by(32) {
+        let p = pixels.as_mut_ptr().add(i) as *mut __m256i;
+        let v = _mm256_or_si256(_mm256_and_si256(_mm256_loadu_si256(p), color), alpha_bits);
+        _mm256_storeu_si256(p, v);
+    }
+    scalar::fill_alpha(&mut pixels[len..], alpha);
+}
+
+/// Swaps red and blue in five 3-byte pixels at a time.
+///
+/// Each step loads and stores 16 bytes but only advances by 15, so the stray byte at the end gets
+/// overwritten by the next step, or by the scalar tail.
+#[target_feature(enable = "ssse3")]
+pub unsafe fn swap_rb3_ssse3(src: &[u8], dst: &mut [u8]) {
+    let control = _mm_setr_epi8(2, 1, 0, 5, 4, 3, 8, 7, 6, 11, 10, 9, 14, 13, 12, 15);
+    let mut i = 0;
+    while i + 16 <= src.len() {
+        let v = _mm_loadu_si128(src.as_ptr().add(i) as *const __m128i);
+        let v = _mm_shuffle_epi8(v, control);
+        _mm_storeu_si128(dst.as_mut_ptr().add(i) as *mut __m128i, v);
+        i += 15;
+    }
+    scalar::swap_rb3(&src[i..], &mut dst[i..]);
+}
+
+/// Expands four 3-byte pixels at a time, reading 16 bytes of which only the first 12 are used.
+#[target_feature(enable = "ssse3")]
+pub unsafe fn expand_ssse3(src: &[u8], dst: &mut [u8], swap: bool) {
+    let control = if swap {
+        _mm_setr_epi8(2, 1, 0, Z, 5, 4, 3, Z, 8, 7, 6, Z, 11, 10, 9, Z)
+    } else {
+        _mm_setr_epi8(0, 1, 2, Z, 3, 4, 5, Z, 6, 7, 8, Z, 9, 10, 11, Z)
+    };
+    let alpha = _mm_set1_epi32(!COLOR_MASK);
+    let mut pixels = 0;
+    while pixels * 3 + 16 <= src.len() {
+        let v = _mm_loadu_si128(src.as_ptr().add(pixels * 3) as *const __m128i);
+        let v = _mm_or_si128(_mm_shuffle_epi8(v, control), alpha);
+        _mm_storeu_si128(dst.as_mut_ptr().add(pixels * 4) as *mut __m128i, v);
+        pixels += 4;
+    }
+    scalar::expand(&src[pixels * 3..], &mut dst[pixels * 4..], swap);
+}
+
+/// Packs four 4-byte pixels at a time, writing 16 bytes of which the last 4 are overwritten by
+/// the next step, or by the scalar tail.
+#[target_feature(enable = "ssse3")]
+pub unsafe fn pack_ssse3(src: &[u8], dst: &mut [u8], swap: bool) {
+    let control = if swap {
+        _mm_setr_epi8(2, 1, 0, 6, 5, 4, 10, 9, 8, 14, 13, 12, Z, Z, Z, Z)
+    } else {
+        _mm_setr_epi8(0, 1, 2, 4, 5, 6, 8, 9, 10, 12, 13, 14, Z, Z, Z, Z)
+    };
+    let mut pixels = 0;
+    while pixels * 3 + 16 <= dst.len() {
+        let v = _mm_loadu_si128(src.as_ptr().add(pixels * 4) as *const __m128i);
+        let v = _mm_shuffle_epi8(v, control);
+        _mm_storeu_si128(dst.as_mut_ptr().add(pixels * 3) as *mut __m128i, v);
+        pixels += 4;
+    }
+    scalar::pack(&src[pixels * 4..], &mut dst[pixels * 3..], swap);
+}
@@ -0,0 +1,704 @@
+//! Raster drawing primitives.
+//!
+//! Shapes and text can be drawn onto a whole [`PixelBufferTyped`], which only marks the area they
+//! cover as damaged, or onto a [`PixelViewMut`], in which case coordinates are relative to the
+//! view. Everything is clipped, so shapes can hang off the edges or lie completely outside.
+
+use std::slice;
+
+use crate::{
+    composite::{Blend, BlendMode},
+    convert::convert_row,
+    font::{self, TextStyle},
+    PixelBufferFormat, PixelBufferTyped, PixelViewMut, Rect, BGRA,
+};
+
+/// How [`fill_polygon`](PixelBufferTyped::fill_polygon) decides whether a point is inside a
+/// polygon whose edges cross over each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum FillRule {
+    /// A point is inside if a ray from it crosses an odd number of edges, which leaves holes where
+    /// the polygon overlaps itself.
+    #[default]
+    EvenOdd,
+    /// A point is inside if the edges wind around it at all, which fills overlapping parts too.
+    NonZero,
+}
+
+/// A view being drawn on, along with where its top-left corner is in the coordinates shapes are
+/// given in.
+///
+/// Coordinates are `i64`, so translating them can't overflow.
+struct Canvas<'v, 'a, P> {
+    view: &'v mut PixelViewMut<'a, P>,
+    origin: (i64, i64),
+}
+
+impl<P: PixelBufferFormat> Canvas<'_, '_, P> {
+    /// The columns and rows the canvas covers, as `(left, top, right, bottom)`.
+    fn bounds(&self) -> (i64, i64, i64, i64) {
+        let (x, y) = self.origin;
+        let (width, height) = (self.view.width(), self.view.height());
+        (x, y, x + i64::from(width), y + i64::from(height))
+    }
+
+    fn pixel_mut(&mut self, x: i64, y: i64) -> Option<&mut P> {
+        let (x, y) = (x - self.origin.0, y - self.origin.1);
+        if x < 0 || y < 0 || x > i64::from(u32::MAX) || y > i64::from(u32::MAX) {
+            return None;
+        }
+        self.view.get_mut(x as u32, y as u32)
+    }
+
+    fn plot(&mut self, x: i64, y: i64, color: P) {
+        if let Some(pixel) = self.pixel_mut(x, y) {
+            *pixel = color;
+        }
+    }
+
+    /// Blends `color` onto the pixel at `(x, y)`, scaling its alpha by `coverage`.
+    fn plot_blended(&mut self, x: i64, y: i64, color: BGRA, coverage: f32) {
+        if let Some(pixel) = self.pixel_mut(x, y) {
+            let mut dst = [BGRA::DEFAULT];
+            convert_row(slice::from_ref(pixel), &mut dst);
+            let src = BGRA {
+                a: (f32::from(color.a) * coverage + 0.5) as u8,
+                ..color
+            };
+            dst[0] = Blend::new(BlendMode::SourceOver).apply(src, dst[0]);
+            convert_row(&dst, slice::from_mut(pixel));
+        }
+    }
+
+    /// Fills the pixels from `left` up to `right` in row `y`.
+    fn span(&mut self, y: i64, left: i64, right: i64, color: P) {
+        let (min_x, min_y, max_x, max_y) = self.bounds();
+        let (left, right) = (left.max(min_x), right.min(max_x));
+        if y < min_y || y >= max_y || left >= right {
+            return;
+        }
+        let row = self.view.row_mut((y - min_y) as u32).unwrap();
+        row[(left - min_x) as usize..(right - min_x) as usize].fill(color);
+    }
+
+    fn fill_rect(&mut self, rect: Rect, color: P) {
+        let (x, y) = (i64::from(rect.x), i64::from(rect.y));
+        let (right, bottom) = (x + i64::from(rect.width), y + i64::from(rect.height));
+        let (_, min_y, _, max_y) = self.bounds();
+        for y in y.max(min_y)..bottom.min(max_y) {
+            self.span(y, x, right, color);
+        }
+    }
+
+    fn draw_rect(&mut self, rect: Rect, color: P) {
+        if rect.is_empty() {
+            return;
+        }
+        let (x, y) = (i64::from(rect.x), i64::from(rect.y));
+        let (right, bottom) = (x + i64::from(rect.width), y + i64::from(rect.height));
+        self.span(y, x, right, color);
+        self.span(bottom - 1, x, right, color);
+        let (_, min_y, _, max_y) = self.bounds();
+        for y in (y + 1).max(min_y)..(bottom - 1).min(max_y) {
+            self.plot(x, y, color);
+            self.plot(right - 1, y, color);
+        }
+    }
+
+    fn draw_line(&mut self, from: (i64, i64), to: (i64, i64), color: P, anti_alias: bool) {
+        let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+        // Step along whichever axis the line covers more of, one pixel at a time, so the line has
+        // no gaps.
+        let steep = dy.abs() > dx.abs();
+        let swap = |(x, y): (i64, i64)| if steep { (y, x) } else { (x, y) };
+        let (mut from, mut to) = (swap(from), swap(to));
+        if from.0 > to.0 {
+            std::mem::swap(&mut from, &mut to);
+        }
+        let (major_len, minor_len) = (to.0 - from.0, to.1 - from.1);
+        // Only step through the part of the line that can be inside the canvas.
+        let (left, top, right, bottom) = self.bounds();
+        let (min_major, max_major) = if steep { (top, bottom) } else { (left, right) };
+        let mut color_bgra = [BGRA::DEFAULT];
+        convert_row(slice::from_ref(&color), &mut color_bgra);
+        for major in from.0.max(min_major)..=to.0.min(max_major - 1) {
+            let t = major - from.0;
+            let point = |minor| swap((major, minor));
+            if major_len == 0 {
+                let (x, y) = point(from.1);
+                self.plot(x, y, color);
+            } else if anti_alias {
+                // Xiaolin Wu's algorithm: split the pixel between the two rows the line passes
+                // between, in proportion to how close it is to each.
+                let exact = from.1 as f64 + t as f64 * minor_len as f64 / major_len as f64;
+                let minor = exact.floor();
+                let fraction = (exact - minor) as f32;
+                let (x, y) = point(minor as i64);
+                self.plot_blended(x, y, color_bgra[0], 1.0 - fraction);
+                if fraction > 0.0 {
+                    let (x, y) = point(minor as i64 + 1);
+                    self.plot_blended(x, y, color_bgra[0], fraction);
+                }
+            } else {
+                // Round to the nearest pixel, which picks the same pixels as Bresenham's
+                // algorithm without having to start from the first one.
+                let minor = from.1 + div_round(i128::from(t) * i128::from(minor_len), major_len);
+                let (x, y) = point(minor);
+                self.plot(x, y, color);
+            }
+        }
+    }
+
+    fn draw_circle(&mut self, center: (i64, i64), radius: i64, color: P) {
+        let (cx, cy) = center;
+        // The midpoint circle algorithm, which walks one octant and mirrors it into the rest. Each
+        // step of the walk moves `y` one pixel further from the center, and picks `x` by rounding
+        // its distance on the circle, so the steps can be taken in any order. Every pixel a step
+        // plots is `y` rows or columns away from the center, so only the steps that put it on the
+        // canvas are taken.
+        let (left, top, right, bottom) = self.bounds();
+        let mut offsets = [
+            (top - cy, bottom - cy),
+            (cy - bottom + 1, cy - top + 1),
+            (left - cx, right - cx),
+            (cx - right + 1, cx - left + 1),
+        ];
+        offsets.sort_unstable();
+        let mut next = 0;
+        for (start, end) in offsets {
+            for y in start.max(next)..end.min(radius + 1) {
+                let n = i128::from(radius).pow(2) - i128::from(y).pow(2);
+                // The square root of `n`, rounded to the nearest integer.
+                let root = isqrt(n);
+                let x = if n > i128::from(root).pow(2) + i128::from(root) {
+                    root + 1
+                } else {
+                    root
+                };
+                if x < y {
+                    break;
+                }
+                for &(px, py) in &[
+                    (x, y),
+                    (y, x),
+                    (-y, x),
+                    (-x, y),
+                    (-x, -y),
+                    (-y, -x),
+                    (y, -x),
+                    (x, -y),
+                ] {
+                    self.plot(cx + px, cy + py, color);
+                }
+            }
+            next = next.max(end);
+        }
+    }
+
+    fn fill_circle(&mut self, center: (i64, i64), radius: i64, color: P) {
+        let (cx, cy) = center;
+        let (_, min_y, _, max_y) = self.bounds();
+        let limit = i128::from(radius).pow(2) + i128::from(radius);
+        for y in (cy - radius).max(min_y)..(cy + radius + 1).min(max_y) {
+            let dy = i128::from(y - cy);
+            let half_width = isqrt(limit - dy * dy);
+            self.span(y, cx - half_width, cx + half_width + 1, color);
+        }
+    }
+
+    fn fill_polygon(&mut self, points: &[(i64, i64)], color: P, rule: FillRule) {
+        let (_, min_y, _, max_y) = self.bounds();
+        let top = points.iter().map(|p| p.1).min().unwrap_or(0).max(min_y);
+        let bottom = points.iter().map(|p| p.1).max().unwrap_or(0).min(max_y);
+        let edges = points.iter().zip(points.iter().cycle().skip(1));
+        let mut crossings = Vec::new();
+        for y in top..bottom {
+            // Sample each row through the middle of its pixels, which never lands exactly on a
+            // vertex.
+            let center = y as f64 + 0.5;
+            crossings.clear();
+            for (&(x0, y0), &(x1, y1)) in edges.clone() {
+                let (low, high) = (y0.min(y1) as f64, y0.max(y1) as f64);
+                if center < low || center >= high {
+                    continue;
+                }
+                let x = x0 as f64 + (center - y0 as f64) * (x1 - x0) as f64 / (y1 - y0) as f64;
+                crossings.push((x, if y1 > y0 { 1 } else { -1 }));
+            }
+            crossings.sort_by(|a, b| a.0.total_cmp(&b.0));
+            let mut winding = 0;
+            for pair in crossings.windows(2) {
+                winding += pair[0].1;
+                let inside = match rule {
+                    FillRule::EvenOdd => winding % 2 != 0,
+                    FillRule::NonZero => winding != 0,
+                };
+                if inside {
+                    // Fill the pixels whose centers lie between the crossings.
+                    let left = (pair[0].0 - 0.5).ceil() as i64;
+                    let right = (pair[1].0 - 0.5).ceil() as i64;
+                    self.span(y, left, right, color);
+                }
+            }
+        }
+    }
+
+    fn draw_text(&mut self, pos: (i64, i64), text: &str, style: &TextStyle<P>) {
+        let font = style.font;
+        let (width, height) = font.glyph_size();
+        let (left, top, right, bottom) = self.bounds();
+        for (line, text) in font::text_lines(text).enumerate() {
+            let y = pos.1 + line as i64 * i64::from(height);
+            if y >= bottom {
+                break;
+            }
+            if y + i64::from(height) <= top {
+                continue;
+            }
+            for (column, c) in text.chars().enumerate() {
+                let x = pos.0 + column as i64 * i64::from(width);
+                if x >= right {
+                    break;
+                }
+                if x + i64::from(width) <= left {
+                    continue;
+                }
+                let glyph = font.glyph(c);
+                for glyph_y in 0..height {
+                    for glyph_x in 0..width {
+                        let (x, y) = (x + i64::from(glyph_x), y + i64::from(glyph_y));
+                        match glyph {
+                            Some(glyph) if font.is_set(glyph, glyph_x, glyph_y) => {
+                                self.plot(x, y, style.color)
+                            }
+                            _ => {
+                                if let Some(background) = style.background {
+                                    self.plot(x, y, background);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Composites `color` onto the canvas through the masks of `glyphs`, in linear light.
+    #[cfg(feature = "ab_glyph")]
+    fn draw_glyphs(&mut self, glyphs: &[font::PlacedGlyph], color: P) {
+        let mut color_bgra = [BGRA::DEFAULT];
+        convert_row(slice::from_ref(&color), &mut color_bgra);
+        let blend = Blend {
+            gamma_correct: true,
+            ..Blend::new(BlendMode::SourceOver)
+        };
+        let (left, top, right, bottom) = self.bounds();
+        let mut src = Vec::new();
+        for glyph in glyphs {
+            let (x, y) = glyph.pos;
+            let mask = &glyph.mask;
+            let (start, end) = (x.max(left), (x + mask.width as i64).min(right));
+            if start >= end {
+                continue;
+            }
+            for row in y.max(top)..(y + mask.height() as i64).min(bottom) {
+                let coverage = &mask.coverage[(row - y) as usize * mask.width..][..mask.width];
+                src.clear();
+                src.extend(
+                    coverage[(start - x) as usize..(end - x) as usize]
+                        .iter()
+                        .map(|&c| {
+                            let a = (u32::from(color_bgra[0].a) * u32::from(c) + 127) / 255;
+                            BGRA {
+                                a: a as u8,
+                                ..color_bgra[0]
+                            }
+                        }),
+                );
+                let dst = self.view.row_mut((row - top) as u32).unwrap();
+                crate::composite::blend_row(
+                    &src,
+                    &mut dst[(start - left) as usize..(end - left) as usize],
+                    blend,
+                );
+            }
+        }
+    }
+}
+
+/// Divides by a positive `denominator`, rounding to the nearest integer.
+fn div_round(numerator: i128, denominator: i64) -> i64 {
+    let denominator = i128::from(denominator);
+    (2 * numerator + denominator).div_euclid(2 * denominator) as i64
+}
+
+/// The largest integer whose square is at most `n`, or `-1` if `n` is negative.
+fn isqrt(n: i128) -> i64 {
+    if n < 0 {
+        return -1;
+    }
+    let mut root = (n as f64).sqrt() as i64;
+    // Floating point can be off by one for large numbers.
+    while i128::from(root).pow(2) > n {
+        root -= 1;
+    }
+    while i128::from(root + 1).pow(2) <= n {
+        root += 1;
+    }
+    root
+}
+
+fn point((x, y): (i32, i32)) -> (i64, i64) {
+    (i64::from(x), i64::from(y))
+}
+
+/// The columns and rows, as `(left, top, right, bottom)`, that a circle can draw on.
+fn circle_bounds(center: (i32, i32), radius: u32) -> (i64, i64, i64, i64) {
+    let ((x, y), radius) = (point(center), i64::from(radius));
+    (x - radius, y - radius, x + radius + 1, y + radius + 1)
+}
+
+/// Defines the drawing methods on both pixel buffers and views, which need a `draw_within` method
+/// that takes the bounds of a shape, as `(left, top, right, bottom)`, and draws it on a
+/// [`Canvas`].
+macro_rules! drawing_methods {
+    ($self:ident) => {
+        /// Sets every pixel to `color`.
+        pub fn fill(&mut $self, color: P) {
+            for row in $self.rows_mut() {
+                row.fill(color);
+            }
+        }
+
+        /// Sets every pixel in `rect` to `color`.
+        pub fn fill_rect(&mut $self, rect: Rect, color: P) {
+            let bounds = rect_bounds(rect);
+            $self.draw_within(bounds, |canvas| canvas.fill_rect(rect, color));
+        }
+
+        /// Draws the one pixel wide outline of `rect`.
+        pub fn draw_rect(&mut $self, rect: Rect, color: P) {
+            let bounds = rect_bounds(rect);
+            $self.draw_within(bounds, |canvas| canvas.draw_rect(rect, color));
+        }
+
+        /// Draws a one pixel wide line between the centers of two pixels, including both of them.
+        ///
+        /// With `anti_alias`, the line is drawn with Xiaolin Wu's algorithm, which blends it into
+        /// the pixels it passes between rather than snapping it to the nearest ones. The color's
+        /// alpha is respected too, in that case.
+        pub fn draw_line(&mut $self, from: (i32, i32), to: (i32, i32), color: P, anti_alias: bool) {
+            let (from, to) = (point(from), point(to));
+            let bounds = (
+                from.0.min(to.0) - 1,
+                from.1.min(to.1) - 1,
+                from.0.max(to.0) + 2,
+                from.1.max(to.1) + 2,
+            );
+            $self.draw_within(bounds, |canvas| canvas.draw_line(from, to, color, anti_alias));
+        }
+
+        /// Draws the one pixel wide outline of a circle.
+        pub fn draw_circle(&mut $self, center: (i32, i32), radius: u32, color: P) {
+            let bounds = circle_bounds(center, radius);
+            $self.draw_within(bounds, |canvas| {
+                canvas.draw_circle(point(center), i64::from(radius), color)
+            });
+        }
+
+        /// Fills a circle, including the pixels that [`draw_circle`](Self::draw_circle) draws.
+        pub fn fill_circle(&mut $self, center: (i32, i32), radius: u32, color: P) {
+            let bounds = circle_bounds(center, radius);
+            $self.draw_within(bounds, |canvas| {
+                canvas.fill_circle(point(center), i64::from(radius), color)
+            });
+        }
+
+        /// Fills the polygon with the corners in `points`, which is closed automatically.
+        ///
+        /// Pixels are filled if their centers are inside the polygon, according to `rule`.
+        pub fn fill_polygon(&mut $self, points: &[(i32, i32)], color: P, rule: FillRule) {
+            let points: Vec<_> = points.iter().copied().map(point).collect();
+            let bounds = points.iter().fold(
+                (i64::MAX, i64::MAX, i64::MIN, i64::MIN),
+                |(left, top, right, bottom), &(x, y)| {
+                    (left.min(x), top.min(y), right.max(x + 1), bottom.max(y + 1))
+                },
+            );
+            $self.draw_within(bounds, |canvas| canvas.fill_polygon(&points, color, rule));
+        }
+
+        /// Draws `text` in the [built-in font](crate::font::BitmapFont::builtin), with the
+        /// top-left corner of its first character at `pos`.
+        ///
+        /// Each line of the text is drawn below the one before.
+        pub fn draw_text(&mut $self, pos: (i32, i32), text: &str, color: P) {
+            $self.draw_text_styled(pos, text, &TextStyle::new(color));
+        }
+
+        /// Draws `text` in the font and colors in `style`, with the top-left corner of its first
+        /// character at `pos`.
+        ///
+        /// Each line of the text is drawn below the one before. With a background, each
+        /// character's whole cell is filled, but the cells past the end of a line aren't.
+        pub fn draw_text_styled(&mut $self, pos: (i32, i32), text: &str, style: &TextStyle<P>) {
+            let pos = point(pos);
+            let (columns, lines) = font::text_grid(text);
+            let (width, height) = style.font.glyph_size();
+            let bounds = (
+                pos.0,
+                pos.1,
+                pos.0.saturating_add((columns as i64).saturating_mul(i64::from(width))),
+                pos.1.saturating_add((lines as i64).saturating_mul(i64::from(height))),
+            );
+            $self.draw_within(bounds, |canvas| canvas.draw_text(pos, text, style));
+        }
+
+        /// Draws `text` in a TrueType or OpenType font, with the top-left corner of its first line
+        /// at `pos`.
+        ///
+        /// The text is anti-aliased, kerned, and blended in linear light. Each line of the text is
+        /// drawn below the one before.
+        #[cfg(feature = "ab_glyph")]
+        pub fn draw_truetype_text(
+            &mut $self,
+            pos: (i32, i32),
+            text: &str,
+            style: &font::TrueTypeStyle<P>,
+        ) {
+            let glyphs = style.font.layout(point(pos), text, style.size);
+            let bounds = glyphs.iter().fold(
+                (i64::MAX, i64::MAX, i64::MIN, i64::MIN),
+                |(left, top, right, bottom), glyph| {
+                    let (x, y) = glyph.pos;
+                    let (width, height) = (glyph.mask.width as i64, glyph.mask.height() as i64);
+                    (left.min(x), top.min(y), right.max(x + width), bottom.max(y + height))
+                },
+            );
+            $self.draw_within(bounds, |canvas| canvas.draw_glyphs(&glyphs, style.color));
+        }
+    };
+}
+
+fn rect_bounds(rect: Rect) -> (i64, i64, i64, i64) {
+    let (x, y) = (i64::from(rect.x), i64::from(rect.y));
+    (x, y, x + i64::from(rect.width), y + i64::from(rect.height))
+}
+
+/// Drawing onto a pixel buffer only marks the area within the shape's bounds as damaged.
+impl<P: PixelBufferFormat> PixelBufferTyped<P> {
+    drawing_methods!(self);
+
+    /// Clips `bounds`, given as `(left, top, right, bottom)`, to the pixel buffer, and runs `draw`
+    /// on a view of what's left.
+    fn draw_within(&mut self, bounds: (i64, i64, i64, i64), draw: impl FnOnce(&mut Canvas<P>)) {
+        let (left, top) = (bounds.0.max(0), bounds.1.max(0));
+        let right = bounds.2.min(i64::from(self.width()));
+        let bottom = bounds.3.min(i64::from(self.height()));
+        if left >= right || top >= bottom {
+            return;
+        }
+        let rect = Rect::new(
+            left as u32,
+            top as u32,
+            (right - left) as u32,
+            (bottom - top) as u32,
+        );
+        draw(&mut Canvas {
+            view: &mut self.view_mut(rect),
+            origin: (left, top),
+        });
+    }
+}
+
+/// Coordinates are relative to the view's top-left corner.
+impl<P: PixelBufferFormat> PixelViewMut<'_, P> {
+    drawing_methods!(self);
+
+    /// The view is already borrowed, so there's no damage to limit to `bounds`.
+    fn draw_within(&mut self, _bounds: (i64, i64, i64, i64), draw: impl FnOnce(&mut Canvas<P>)) {
+        draw(&mut Canvas {
+            view: self,
+            origin: (0, 0),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{font::BitmapFont, test_util::render, Gray8};
+
+    const ON: Gray8 = Gray8(255);
+
+    fn canvas(width: u32, height: u32) -> PixelBufferTyped<Gray8> {
+        let mut pb = PixelBufferTyped::new_headless(width, height);
+        pb.fill(Gray8(0));
+        pb.clear_damage();
+        pb
+    }
+
+    #[test]
+    fn rects() {
+        let mut pb = canvas(6, 5);
+        pb.fill_rect(Rect::new(4, 3, 10, 10), ON);
+        assert_eq!(pb.damage().rects(), &[Rect::new(4, 3, 2, 2)]);
+        pb.draw_rect(Rect::new(0, 0, 4, 3), ON);
+        pb.draw_rect(Rect::new(5, 0, 0, 3), ON);
+        assert_eq!(
+            render(&pb),
+            ["####..", "#..#..", "####..", "....##", "....##"]
+        );
+        pb.fill(ON);
+        assert!(render(&pb).iter().all(|row| row == "######"));
+    }
+
+    #[test]
+    fn lines() {
+        let mut pb = canvas(7, 4);
+        pb.draw_line((0, 0), (6, 3), ON, false);
+        pb.draw_line((6, 0), (6, 0), ON, false);
+        assert_eq!(render(&pb), ["#.....#", ".##....", "...##..", ".....##"]);
+
+        // Clipped lines pick the same pixels as if they weren't clipped.
+        let mut clipped = canvas(7, 4);
+        clipped.draw_line((-6, -3), (12, 6), ON, false);
+        clipped.draw_line((3, 100), (3, -100), ON, false);
+        assert_eq!(
+            render(&clipped),
+            ["#..#...", ".###...", "...##..", "...#.##"]
+        );
+        clipped.draw_line((i32::MIN, 2), (i32::MAX, 2), ON, false);
+        assert_eq!(render(&clipped)[2], "#######");
+        assert_eq!(clipped.damage().rects(), &[Rect::new(0, 0, 7, 4)]);
+    }
+
+    #[test]
+    fn anti_aliased_lines() {
+        let mut pb = canvas(5, 3);
+        pb.draw_line((0, 0), (4, 2), ON, true);
+        assert_eq!(render(&pb), ["#+...", ".+#+.", "...+#"]);
+        assert_eq!(pb.row(1).unwrap()[1], Gray8(128));
+        // Straight lines don't bleed into their neighbors.
+        let mut pb = canvas(3, 3);
+        pb.draw_line((1, 0), (1, 2), ON, true);
+        assert_eq!(render(&pb), [".#.", ".#.", ".#."]);
+    }
+
+    #[test]
+    fn circles() {
+        let mut pb = canvas(9, 9);
+        pb.draw_circle((4, 4), 3, ON);
+        assert_eq!(
+            render(&pb),
+            [
+                ".........",
+                "...###...",
+                "..#...#..",
+                ".#.....#.",
+                ".#.....#.",
+                ".#.....#.",
+                "..#...#..",
+                "...###...",
+                ".........",
+            ]
+        );
+        pb.fill_circle((4, 4), 3, ON);
+        assert_eq!(
+            render(&pb),
+            [
+                ".........",
+                "...###...",
+                "..#####..",
+                ".#######.",
+                ".#######.",
+                ".#######.",
+                "..#####..",
+                "...###...",
+                ".........",
+            ]
+        );
+        pb.fill_circle((-100, -100), 3, Gray8(0));
+        pb.draw_circle((0, 0), 0, Gray8(0));
+        assert_eq!(render(&pb)[0], ".........");
+
+        // Huge circles only take as long to draw as the part of them on the canvas.
+        let mut pb = canvas(9, 9);
+        pb.draw_circle((4, -1_000_000_000), 1_000_000_004, ON);
+        assert_eq!(
+            render(&pb),
+            [
+                ".........",
+                ".........",
+                ".........",
+                ".........",
+                "#########",
+                ".........",
+                ".........",
+                ".........",
+                ".........",
+            ]
+        );
+        pb.fill_circle((0, 0), u32::MAX, ON);
+        assert!(render(&pb).iter().all(|row| row == "#########"));
+        pb.draw_circle((i32::MAX, i32::MAX), u32::MAX, Gray8(0));
+        pb.draw_circle((i32::MIN, i32::MIN), u32::MAX, Gray8(0));
+        assert!(render(&pb).iter().all(|row| row == "#########"));
+    }
+
+    #[test]
+    fn text() {
+        let bdf = "STARTFONT 2.1\nFONTBOUNDINGBOX 2 2 0 0\nSTARTCHAR L\nENCODING 76\nBITMAP\n80\nC0\nENDCHAR\n";
+        let font = BitmapFont::from_bdf(bdf).unwrap();
+        let style = TextStyle {
+            font: &font,
+            color: ON,
+            background: Some(Gray8(100)),
+        };
+        let mut pb = canvas(5, 5);
+        // 'x' has no glyph, so only its background is drawn.
+        pb.draw_text_styled((-1, 0), "LL\r\nxL\nL", &style);
+        assert_eq!(render(&pb), ["+#+..", "###..", "+#+..", "+##..", "+...."]);
+        assert_eq!(pb.damage().rects(), &[Rect::new(0, 0, 3, 5)]);
+
+        let mut pb = canvas(8, 13);
+        pb.draw_text((0, 0), "A", ON);
+        assert_eq!(render(&pb)[7], ".######.");
+        pb.draw_text((0, 13), "A", ON);
+        pb.draw_text((i32::MIN, i32::MAX), "A\nA", ON);
+        assert_eq!(pb.damage().rects(), &[Rect::new(0, 0, 8, 13)]);
+    }
+
+    #[test]
+    fn polygons() {
+        // A pentagram, whose middle is only inside with the non-zero rule.
+        let star = [(5, 0), (8, 10), (0, 4), (10, 4), (2, 10)];
+        let mut even_odd = canvas(10, 10);
+        even_odd.fill_polygon(&star, ON, FillRule::EvenOdd);
+        let mut non_zero = canvas(10, 10);
+        non_zero.fill_polygon(&star, ON, FillRule::NonZero);
+        assert_eq!(render(&even_odd)[5], "..#....#..");
+        assert_eq!(render(&non_zero)[5], "..######..");
+        assert_eq!(render(&even_odd)[6], "..........");
+        assert_eq!(render(&non_zero)[6], "...####...");
+        // Outside the overlap, both rules agree.
+        for y in [2, 3, 7, 8, 9] {
+            assert_eq!(render(&even_odd)[y], render(&non_zero)[y]);
+        }
+
+        let mut pb = canvas(4, 4);
+        pb.fill_polygon(&[(1, 1), (3, 1), (3, 3), (1, 3)], ON, FillRule::EvenOdd);
+        pb.fill_polygon(&[], ON, FillRule::EvenOdd);
+        assert_eq!(render(&pb), ["....", ".##.", ".##.", "...."]);
+        assert_eq!(pb.damage().rects(), &[Rect::new(1, 1, 3, 3)]);
+    }
+
+    #[test]
+    fn draws_on_views() {
+        let mut pb = canvas(6, 4);
+        let mut view = pb.view_mut(Rect::new(2, 1, 3, 2));
+        view.draw_line((-5, 0), (5, 0), ON, false);
+        view.fill_rect(Rect::new(1, 1, 9, 9), Gray8(128));
+        assert_eq!(render(&pb), ["......", "..###.", "...++.", "......"]);
+    }
+}
@@ -0,0 +1,727 @@
+//! Fixed-size bitmap fonts for drawing text.
+//!
+//! Every glyph in a [`BitmapFont`] fills a cell of the same size, and is one bit per pixel, so
+//! text is laid out on a grid and drawn without any blending. That's meant for debug overlays and
+//! labels rather than typesetting. A font with the printable ASCII characters is built in, and
+//! others can be loaded from PSF (the Linux console format) or BDF (the X11 format) files.
+//!
+//! With the `ab_glyph` feature, `TrueTypeFont` draws anti-aliased, proportionally spaced text
+//! from TrueType and OpenType fonts instead.
+
+use std::{
+    collections::HashMap,
+    convert::{TryFrom, TryInto},
+    error::Error,
+    fmt, str,
+    sync::OnceLock,
+};
+
+#[cfg(feature = "ab_glyph")]
+mod truetype;
+#[cfg(feature = "ab_glyph")]
+pub(crate) use truetype::PlacedGlyph;
+#[cfg(feature = "ab_glyph")]
+pub use truetype::{TrueTypeFont, TrueTypeStyle};
+
+/// An error that can occur while loading a font.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FontError {
+    /// The data isn't in the format it was loaded as.
+    UnrecognizedFormat,
+    /// The data ends before all of the glyphs it declares.
+    Truncated,
+    /// The font declares glyphs that are zero pixels wide or high.
+    EmptyGlyphs,
+    /// A line of a BDF font couldn't be parsed.
+    Malformed {
+        /// The line number, starting from 1.
+        line: usize,
+        /// What's wrong with it.
+        message: String,
+    },
+}
+
+impl fmt::Display for FontError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnrecognizedFormat => write!(f, "the font isn't in a recognized format"),
+            Self::Truncated => write!(f, "the font data ends unexpectedly"),
+            Self::EmptyGlyphs => write!(f, "the font's glyphs have no pixels"),
+            Self::Malformed { line, message } => {
+                write!(f, "malformed font on line {}: {}", line, message)
+            }
+        }
+    }
+}
+
+impl Error for FontError {}
+
+/// How [`draw_text_styled`](crate::PixelBufferTyped::draw_text_styled) draws text.
+#[derive(Debug, Clone, Copy)]
+pub struct TextStyle<'f, P> {
+    /// The font to draw the text in.
+    pub font: &'f BitmapFont,
+    /// The color of the glyphs' pixels.
+    pub color: P,
+    /// The color to fill the rest of each character's cell with, if any. Otherwise, whatever's
+    /// behind the text shows through.
+    pub background: Option<P>,
+}
+
+impl<P> TextStyle<'static, P> {
+    /// Draws text in `color`, in the [built-in font](BitmapFont::builtin), with no background.
+    pub fn new(color: P) -> Self {
+        TextStyle {
+            font: BitmapFont::builtin(),
+            color,
+            background: None,
+        }
+    }
+}
+
+/// A font whose glyphs are all bitmaps of the same size.
+///
+/// Characters the font has no glyph for are drawn with its fallback glyph, if it has one, and are
+/// left blank otherwise.
+#[derive(Clone)]
+pub struct BitmapFont {
+    width: u32,
+    height: u32,
+    /// The glyphs' bits, one glyph after another. Each row of a glyph starts on a new byte, with
+    /// the leftmost pixel in the most significant bit.
+    bits: Vec<u8>,
+    glyphs: HashMap<char, usize>,
+    fallback: Option<usize>,
+}
+
+impl fmt::Debug for BitmapFont {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BitmapFont")
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("glyphs", &self.glyphs.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl BitmapFont {
+    /// The built-in font, which has 8x13 pixel glyphs for the printable ASCII characters and draws
+    /// everything else as a dotted box.
+    ///
+    /// It's the public domain "fixed" font from X11.
+    pub fn builtin() -> &'static BitmapFont {
+        static BUILTIN: OnceLock<BitmapFont> = OnceLock::new();
+        BUILTIN.get_or_init(|| {
+            let mut font = BitmapFont::new(8, 13).unwrap();
+            font.fallback = Some(font.push_glyph(&BUILTIN_GLYPHS[0]));
+            for (c, bits) in (' '..='~').zip(&BUILTIN_GLYPHS[1..]) {
+                let glyph = font.push_glyph(bits);
+                font.glyphs.insert(c, glyph);
+            }
+            font
+        })
+    }
+
+    /// Loads a PSF font, as used by the Linux console. Both versions 1 and 2 of the format are
+    /// supported.
+    ///
+    /// Glyphs are mapped to characters through the font's Unicode table. Fonts without one are
+    /// assumed to have the glyph for each code point at that index. `?` is used as the fallback
+    /// glyph.
+    pub fn from_psf(data: &[u8]) -> Result<BitmapFont, FontError> {
+        let u32_at = |offset: usize| {
+            data.get(offset..offset + 4)
+                .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+                .ok_or(FontError::Truncated)
+        };
+        let (width, height, count, glyphs_start, unicode) = match data {
+            [0x36, 0x04, mode, height, ..] => {
+                let count = if mode & 0x01 != 0 { 512 } else { 256 };
+                (8, u32::from(*height), count, 4, mode & 0x06 != 0)
+            }
+            [0x72, 0xb5, 0x4a, 0x86, ..] => {
+                let (flags, count) = (u32_at(12)?, u32_at(16)?);
+                let (width, height) = (u32_at(28)?, u32_at(24)?);
+                let stride = (width as usize).div_ceil(8);
+                if u32_at(20)? as usize != stride * height as usize {
+                    return Err(FontError::UnrecognizedFormat);
+                }
+                (
+                    width,
+                    height,
+                    count as usize,
+                    u32_at(8)? as usize,
+                    flags & 0x01 != 0,
+                )
+            }
+            _ => return Err(FontError::UnrecognizedFormat),
+        };
+
+        let mut font = BitmapFont::new(width, height)?;
+        let glyphs_end = count
+            .checked_mul(font.glyph_len())
+            .and_then(|len| len.checked_add(glyphs_start))
+            .ok_or(FontError::Truncated)?;
+        let glyphs = data
+            .get(glyphs_start..glyphs_end)
+            .ok_or(FontError::Truncated)?;
+        for bits in glyphs.chunks_exact(font.glyph_len()) {
+            font.push_glyph(bits);
+        }
+
+        if !unicode {
+            for glyph in 0..count {
+                if let Some(c) = char::from_u32(glyph as u32) {
+                    font.glyphs.insert(c, glyph);
+                }
+            }
+        } else if data[0] == 0x36 {
+            // Each glyph's entry is a list of UCS-2 code points ending in 0xFFFF. Sequences of
+            // combining characters, after 0xFFFE, can't be drawn in a single cell, so they're
+            // skipped.
+            let mut table = data[glyphs_end..]
+                .chunks_exact(2)
+                .map(|unit| u16::from_le_bytes([unit[0], unit[1]]));
+            for glyph in 0..count {
+                let mut sequence = false;
+                for unit in table.by_ref().take_while(|&unit| unit != 0xFFFF) {
+                    sequence |= unit == 0xFFFE;
+                    if let (false, Some(c)) = (sequence, char::from_u32(u32::from(unit))) {
+                        font.glyphs.entry(c).or_insert(glyph);
+                    }
+                }
+            }
+        } else {
+            // The same, except in UTF-8, with 0xFF ending each entry and 0xFE starting sequences.
+            let mut entries = data[glyphs_end..].split(|&byte| byte == 0xFF);
+            for (glyph, entry) in (0..count).zip(entries.by_ref()) {
+                let single = entry.split(|&byte| byte == 0xFE).next().unwrap();
+                for c in String::from_utf8_lossy(single).chars() {
+                    if c != char::REPLACEMENT_CHARACTER {
+                        font.glyphs.entry(c).or_insert(glyph);
+                    }
+                }
+            }
+        }
+
+        font.fallback = font.glyphs.get(&'?').copied();
+        Ok(font)
+    }
+
+    /// Loads a BDF font, as used by X11.
+    ///
+    /// The glyphs are placed in cells the size of the font's bounding box, lined up on the same
+    /// baseline, so proportional fonts come out monospaced. Glyphs without a Unicode encoding are
+    /// skipped. The font's `DEFAULT_CHAR` is used as the fallback glyph, if it has one. Bounding
+    /// boxes over 64KiB per glyph, e.g. 512x1024 pixels, are rejected.
+    pub fn from_bdf(text: &str) -> Result<BitmapFont, FontError> {
+        if !text.starts_with("STARTFONT") {
+            return Err(FontError::UnrecognizedFormat);
+        }
+        let mut font: Option<BitmapFont> = None;
+        // The font's bounding box's offset from the origin, which is where the glyphs' bounding
+        // boxes are measured from too.
+        let mut origin = (0, 0);
+        let mut default_char = None;
+        let mut lines = text.lines().enumerate().map(|(i, line)| (i + 1, line));
+        while let Some((number, line)) = lines.next() {
+            let malformed = |message: &str| FontError::Malformed {
+                line: number,
+                message: message.to_owned(),
+            };
+            let (keyword, args) = line.split_once(' ').unwrap_or((line, ""));
+            match keyword {
+                "FONTBOUNDINGBOX" => {
+                    let [width, height, x, y] = numbers(args).ok_or_else(|| {
+                        malformed("FONTBOUNDINGBOX needs a width, height, and offset")
+                    })?;
+                    let (width, height) = (u32::try_from(width), u32::try_from(height));
+                    let (Ok(width), Ok(height)) = (width, height) else {
+                        return Err(malformed("the bounding box can't have a negative size"));
+                    };
+                    // Every glyph gets a whole cell, however few rows its bitmap has.
+                    if u64::from(width).div_ceil(8) * u64::from(height) > MAX_BDF_GLYPH_LEN {
+                        return Err(malformed("the bounding box is too large"));
+                    }
+                    font = Some(BitmapFont::new(width, height)?);
+                    origin = (x, y + height as i64);
+                }
+                "DEFAULT_CHAR" => {
+                    let [c] = numbers(args)
+                        .ok_or_else(|| malformed("DEFAULT_CHAR needs a code point"))?;
+                    default_char = u32::try_from(c).ok().and_then(char::from_u32);
+                }
+                "STARTCHAR" => {
+                    let font = font
+                        .as_mut()
+                        .ok_or_else(|| malformed("STARTCHAR comes before FONTBOUNDINGBOX"))?;
+                    if let (Some(c), bits) = read_bdf_char(font, origin, &mut lines)? {
+                        let glyph = font.push_glyph(&bits);
+                        font.glyphs.entry(c).or_insert(glyph);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut font = font.ok_or(FontError::UnrecognizedFormat)?;
+        font.fallback = default_char.and_then(|c| font.glyphs.get(&c).copied());
+        Ok(font)
+    }
+
+    /// The width and height of every glyph, in pixels.
+    pub fn glyph_size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Whether the font has a glyph for `c`, rather than drawing it with the fallback glyph.
+    pub fn has_glyph(&self, c: char) -> bool {
+        self.glyphs.contains_key(&c)
+    }
+
+    /// The width and height, in pixels, of `text` drawn in this font, with each line of the text
+    /// below the one before.
+    ///
+    /// This saturates at `u32::MAX` rather than overflowing.
+    pub fn text_size(&self, text: &str) -> (u32, u32) {
+        let (columns, lines) = text_grid(text);
+        let size = |cells: usize, cell: u32| {
+            u32::try_from(cells).map_or(u32::MAX, |cells| cells.saturating_mul(cell))
+        };
+        (size(columns, self.width), size(lines, self.height))
+    }
+
+    /// The bits of the glyph used to draw `c`, if there is one.
+    pub(crate) fn glyph(&self, c: char) -> Option<&[u8]> {
+        let glyph = self.glyphs.get(&c).copied().or(self.fallback)?;
+        let len = self.glyph_len();
+        Some(&self.bits[glyph * len..(glyph + 1) * len])
+    }
+
+    /// Whether the pixel at `(x, y)` is set in a glyph returned by [`glyph`](Self::glyph).
+    pub(crate) fn is_set(&self, glyph: &[u8], x: u32, y: u32) -> bool {
+        let byte = glyph[y as usize * self.stride() + x as usize / 8];
+        byte & (0x80 >> (x % 8)) != 0
+    }
+
+    fn new(width: u32, height: u32) -> Result<BitmapFont, FontError> {
+        if width == 0 || height == 0 {
+            return Err(FontError::EmptyGlyphs);
+        }
+        Ok(BitmapFont {
+            width,
+            height,
+            bits: Vec::new(),
+            glyphs: HashMap::new(),
+            fallback: None,
+        })
+    }
+
+    /// The number of bytes in each row of a glyph.
+    fn stride(&self) -> usize {
+        (self.width as usize).div_ceil(8)
+    }
+
+    /// The number of bytes in each glyph.
+    fn glyph_len(&self) -> usize {
+        self.stride() * self.height as usize
+    }
+
+    fn glyph_count(&self) -> usize {
+        self.bits.len() / self.glyph_len()
+    }
+
+    /// Adds a glyph without mapping any characters to it, and returns its index.
+    fn push_glyph(&mut self, bits: &[u8]) -> usize {
+        debug_assert_eq!(bits.len(), self.glyph_len());
+        self.bits.extend_from_slice(bits);
+        self.glyph_count() - 1
+    }
+}
+
+/// Reads a glyph from a BDF font, up to and including its `ENDCHAR` line.
+///
+/// Returns the character the glyph is for, if it has one, and the glyph's bits laid out in one of
+/// `font`'s cells.
+fn read_bdf_char<'a>(
+    font: &BitmapFont,
+    origin: (i64, i64),
+    lines: &mut impl Iterator<Item = (usize, &'a str)>,
+) -> Result<(Option<char>, Vec<u8>), FontError> {
+    let mut c = None;
+    let mut bits = vec![0; font.glyph_len()];
+    let (cell_width, cell_height) = (i64::from(font.width), i64::from(font.height));
+    // The glyph's bounding box, which defaults to the font's.
+    let (mut width, mut height) = (cell_width, cell_height);
+    let (mut x, mut y) = (origin.0, origin.1 - cell_height);
+    let mut bitmap_row = None;
+    for (number, line) in lines {
+        let malformed = |message: &str| FontError::Malformed {
+            line: number,
+            message: message.to_owned(),
+        };
+        let line = line.trim_end();
+        if line == "ENDCHAR" {
+            return Ok((c, bits));
+        }
+        if let Some(row) = bitmap_row.as_mut() {
+            let cell_y = origin.1 - (y + height) + *row;
+            *row += 1;
+            if *row > height || !(0..cell_height).contains(&cell_y) {
+                continue;
+            }
+            for (i, hex) in line.as_bytes().chunks(2).enumerate() {
+                let byte = str::from_utf8(hex)
+                    .ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or_else(|| malformed("bitmap rows must be hexadecimal"))?;
+                for bit in 0..8 {
+                    let column = i as i64 * 8 + bit;
+                    let cell_x = x - origin.0 + column;
+                    if byte & (0x80 >> bit) != 0
+                        && column < width
+                        && (0..cell_width).contains(&cell_x)
+                    {
+                        let index = cell_y as usize * font.stride() + cell_x as usize / 8;
+                        bits[index] |= 0x80 >> (cell_x % 8);
+                    }
+                }
+            }
+            continue;
+        }
+        let (keyword, args) = line.split_once(' ').unwrap_or((line, ""));
+        match keyword {
+            "ENCODING" => {
+                // A negative encoding means the glyph isn't in the font's character set, although
+                // it can be followed by the code point it stands for anyway.
+                let mut codes = args.split_whitespace().map(str::parse::<i64>);
+                let code = codes
+                    .find(|code| !matches!(code, Ok(code) if *code < 0))
+                    .transpose()
+                    .map_err(|_| malformed("ENCODING needs a code point"))?;
+                c = code
+                    .and_then(|code| u32::try_from(code).ok())
+                    .and_then(char::from_u32);
+            }
+            "BBX" => {
+                [width, height, x, y] = numbers(args)
+                    .ok_or_else(|| malformed("BBX needs a width, height, and offset"))?;
+            }
+            "BITMAP" => bitmap_row = Some(0),
+            _ => {}
+        }
+    }
+    Err(FontError::Truncated)
+}
+
+/// Parses exactly `N` whitespace-separated integers.
+///
+/// They have to fit in an `i32`, which keeps sums and differences of a few of them from
+/// overflowing the `i64`s they're returned as.
+fn numbers<const N: usize>(text: &str) -> Option<[i64; N]> {
+    let mut numbers = [0; N];
+    let mut words = text.split_whitespace();
+    for number in &mut numbers {
+        *number = words.next()?.parse::<i32>().ok()?.into();
+    }
+    words.next().is_none().then_some(numbers)
+}
+
+/// The number of characters in the longest line of `text`, and the number of lines.
+pub(crate) fn text_grid(text: &str) -> (usize, usize) {
+    text_lines(text).fold((0, 0), |(columns, lines), line| {
+        (columns.max(line.chars().count()), lines + 1)
+    })
+}
+
+/// Splits `text` into lines, at either `\n` or `\r\n`.
+pub(crate) fn text_lines(text: &str) -> impl Iterator<Item = &str> {
+    text.split('\n')
+        .map(|line| line.strip_suffix('\r').unwrap_or(line))
+}
+
+/// The most bytes each glyph of a BDF font can take up.
+const MAX_BDF_GLYPH_LEN: u64 = 64 * 1024;
+
+/// The built-in font's fallback glyph, followed by the glyphs for `' '..='~'`.
+#[rustfmt::skip]
+const BUILTIN_GLYPHS: [[u8; 13]; 96] = [
+    [0x00, 0x00, 0xaa, 0x00, 0x82, 0x00, 0x82, 0x00, 0x82, 0x00, 0xaa, 0x00, 0x00], // fallback
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x00, 0x00, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x00, 0x10, 0x00, 0x00], // '!'
+    [0x00, 0x00, 0x24, 0x24, 0x24, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '"'
+    [0x00, 0x00, 0x00, 0x24, 0x24, 0x7e, 0x24, 0x7e, 0x24, 0x24, 0x00, 0x00, 0x00], // '#'
+    [0x00, 0x00, 0x10, 0x3c, 0x50, 0x50, 0x38, 0x14, 0x14, 0x78, 0x10, 0x00, 0x00], // '$'
+    [0x00, 0x00, 0x22, 0x52, 0x24, 0x08, 0x08, 0x10, 0x24, 0x2a, 0x44, 0x00, 0x00], // '%'
+    [0x00, 0x00, 0x00, 0x00, 0x30, 0x48, 0x48, 0x30, 0x4a, 0x44, 0x3a, 0x00, 0x00], // '&'
+    [0x00, 0x00, 0x10, 0x10, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // "'"
+    [0x00, 0x00, 0x04, 0x08, 0x08, 0x10, 0x10, 0x10, 0x08, 0x08, 0x04, 0x00, 0x00], // '('
+    [0x00, 0x00, 0x20, 0x10, 0x10, 0x08, 0x08, 0x08, 0x10, 0x10, 0x20, 0x00, 0x00], // ')'
+    [0x00, 0x00, 0x24, 0x18, 0x7e, 0x18, 0x24, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '*'
+    [0x00, 0x00, 0x00, 0x00, 0x10, 0x10, 0x7c, 0x10, 0x10, 0x00, 0x00, 0x00, 0x00], // '+'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x38, 0x30, 0x40, 0x00], // ','
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x7c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '-'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x38, 0x10, 0x00], // '.'
+    [0x00, 0x00, 0x02, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80, 0x80, 0x00, 0x00], // '/'
+    [0x00, 0x00, 0x18, 0x24, 0x42, 0x42, 0x42, 0x42, 0x42, 0x24, 0x18, 0x00, 0x00], // '0'
+    [0x00, 0x00, 0x10, 0x30, 0x50, 0x10, 0x10, 0x10, 0x10, 0x10, 0x7c, 0x00, 0x00], // '1'
+    [0x00, 0x00, 0x3c, 0x42, 0x42, 0x02, 0x04, 0x18, 0x20, 0x40, 0x7e, 0x00, 0x00], // '2'
+    [0x00, 0x00, 0x7e, 0x02, 0x04, 0x08, 0x1c, 0x02, 0x02, 0x42, 0x3c, 0x00, 0x00], // '3'
+    [0x00, 0x00, 0x04, 0x0c, 0x14, 0x24, 0x44, 0x44, 0x7e, 0x04, 0x04, 0x00, 0x00], // '4'
+    [0x00, 0x00, 0x7e, 0x40, 0x40, 0x5c, 0x62, 0x02, 0x02, 0x42, 0x3c, 0x00, 0x00], // '5'
+    [0x00, 0x00, 0x1c, 0x20, 0x40, 0x40, 0x5c, 0x62, 0x42, 0x42, 0x3c, 0x00, 0x00], // '6'
+    [0x00, 0x00, 0x7e, 0x02, 0x04, 0x08, 0x08, 0x10, 0x10, 0x20, 0x20, 0x00, 0x00], // '7'
+    [0x00, 0x00, 0x3c, 0x42, 0x42, 0x42, 0x3c, 0x42, 0x42, 0x42, 0x3c, 0x00, 0x00], // '8'
+    [0x00, 0x00, 0x3c, 0x42, 0x42, 0x46, 0x3a, 0x02, 0x02, 0x04, 0x38, 0x00, 0x00], // '9'
+    [0x00, 0x00, 0x00, 0x00, 0x10, 0x38, 0x10, 0x00, 0x00, 0x10, 0x38, 0x10, 0x00], // ':'
+    [0x00, 0x00, 0x00, 0x00, 0x10, 0x38, 0x10, 0x00, 0x00, 0x38, 0x30, 0x40, 0x00], // ';'
+    [0x00, 0x00, 0x02, 0x04, 0x08, 0x10, 0x20, 0x10, 0x08, 0x04, 0x02, 0x00, 0x00], // '<'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x7e, 0x00, 0x00, 0x7e, 0x00, 0x00, 0x00, 0x00], // '='
+    [0x00, 0x00, 0x40, 0x20, 0x10, 0x08, 0x04, 0x08, 0x10, 0x20, 0x40, 0x00, 0x00], // '>'
+    [0x00, 0x00, 0x3c, 0x42, 0x42, 0x02, 0x04, 0x08, 0x08, 0x00, 0x08, 0x00, 0x00], // '?'
+    [0x00, 0x00, 0x3c, 0x42, 0x42, 0x4e, 0x52, 0x56, 0x4a, 0x40, 0x3c, 0x00, 0x00], // '@'
+    [0x00, 0x00, 0x18, 0x24, 0x42, 0x42, 0x42, 0x7e, 0x42, 0x42, 0x42, 0x00, 0x00], // 'A'
+    [0x00, 0x00, 0x78, 0x44, 0x42, 0x44, 0x78, 0x44, 0x42, 0x44, 0x78, 0x00, 0x00], // 'B'
+    [0x00, 0x00, 0x3c, 0x42, 0x40, 0x40, 0x40, 0x40, 0x40, 0x42, 0x3c, 0x00, 0x00], // 'C'
+    [0x00, 0x00, 0x78, 0x44, 0x42, 0x42, 0x42, 0x42, 0x42, 0x44, 0x78, 0x00, 0x00], // 'D'
+    [0x00, 0x00, 0x7e, 0x40, 0x40, 0x40, 0x78, 0x40, 0x40, 0x40, 0x7e, 0x00, 0x00], // 'E'
+    [0x00, 0x00, 0x7e, 0x40, 0x40, 0x40, 0x78, 0x40, 0x40, 0x40, 0x40, 0x00, 0x00], // 'F'
+    [0x00, 0x00, 0x3c, 0x42, 0x40, 0x40, 0x40, 0x4e, 0x42, 0x46, 0x3a, 0x00, 0x00], // 'G'
+    [0x00, 0x00, 0x42, 0x42, 0x42, 0x42, 0x7e, 0x42, 0x42, 0x42, 0x42, 0x00, 0x00], // 'H'
+    [0x00, 0x00, 0x7c, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x7c, 0x00, 0x00], // 'I'
+    [0x00, 0x00, 0x1f, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04, 0x44, 0x38, 0x00, 0x00], // 'J'
+    [0x00, 0x00, 0x42, 0x44, 0x48, 0x50, 0x60, 0x50, 0x48, 0x44, 0x42, 0x00, 0x00], // 'K'
+    [0x00, 0x00, 0x40, 0x40, 0x40, 0x40, 0x40, 0x40, 0x40, 0x40, 0x7e, 0x00, 0x00], // 'L'
+    [0x00, 0x00, 0x82, 0x82, 0xc6, 0xaa, 0x92, 0x92, 0x82, 0x82, 0x82, 0x00, 0x00], // 'M'
+    [0x00, 0x00, 0x42, 0x42, 0x62, 0x52, 0x4a, 0x46, 0x42, 0x42, 0x42, 0x00, 0x00], // 'N'
+    [0x00, 0x00, 0x3c, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x3c, 0x00, 0x00], // 'O'
+    [0x00, 0x00, 0x7c, 0x42, 0x42, 0x42, 0x7c, 0x40, 0x40, 0x40, 0x40, 0x00, 0x00], // 'P'
+    [0x00, 0x00, 0x3c, 0x42, 0x42, 0x42, 0x42, 0x42, 0x52, 0x4a, 0x3c, 0x02, 0x00], // 'Q'
+    [0x00, 0x00, 0x7c, 0x42, 0x42, 0x42, 0x7c, 0x50, 0x48, 0x44, 0x42, 0x00, 0x00], // 'R'
+    [0x00, 0x00, 0x3c, 0x42, 0x40, 0x40, 0x3c, 0x02, 0x02, 0x42, 0x3c, 0x00, 0x00], // 'S'
+    [0x00, 0x00, 0xfe, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x00, 0x00], // 'T'
+    [0x00, 0x00, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x3c, 0x00, 0x00], // 'U'
+    [0x00, 0x00, 0x82, 0x82, 0x44, 0x44, 0x44, 0x28, 0x28, 0x28, 0x10, 0x00, 0x00], // 'V'
+    [0x00, 0x00, 0x82, 0x82, 0x82, 0x82, 0x92, 0x92, 0x92, 0xaa, 0x44, 0x00, 0x00], // 'W'
+    [0x00, 0x00, 0x82, 0x82, 0x44, 0x28, 0x10, 0x28, 0x44, 0x82, 0x82, 0x00, 0x00], // 'X'
+    [0x00, 0x00, 0x82, 0x82, 0x44, 0x28, 0x10, 0x10, 0x10, 0x10, 0x10, 0x00, 0x00], // 'Y'
+    [0x00, 0x00, 0x7e, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x40, 0x7e, 0x00, 0x00], // 'Z'
+    [0x00, 0x00, 0x3c, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x3c, 0x00, 0x00], // '['
+    [0x00, 0x00, 0x80, 0x80, 0x40, 0x20, 0x10, 0x08, 0x04, 0x02, 0x02, 0x00, 0x00], // '\\'
+    [0x00, 0x00, 0x78, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x78, 0x00, 0x00], // ']'
+    [0x00, 0x00, 0x10, 0x28, 0x44, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '^'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xfe, 0x00], // '_'
+    [0x00, 0x10, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '`'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x3c, 0x02, 0x3e, 0x42, 0x46, 0x3a, 0x00, 0x00], // 'a'
+    [0x00, 0x00, 0x40, 0x40, 0x40, 0x5c, 0x62, 0x42, 0x42, 0x62, 0x5c, 0x00, 0x00], // 'b'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x3c, 0x42, 0x40, 0x40, 0x42, 0x3c, 0x00, 0x00], // 'c'
+    [0x00, 0x00, 0x02, 0x02, 0x02, 0x3a, 0x46, 0x42, 0x42, 0x46, 0x3a, 0x00, 0x00], // 'd'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x3c, 0x42, 0x7e, 0x40, 0x42, 0x3c, 0x00, 0x00], // 'e'
+    [0x00, 0x00, 0x1c, 0x22, 0x20, 0x20, 0x7c, 0x20, 0x20, 0x20, 0x20, 0x00, 0x00], // 'f'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x3a, 0x44, 0x44, 0x38, 0x40, 0x3c, 0x42, 0x3c], // 'g'
+    [0x00, 0x00, 0x40, 0x40, 0x40, 0x5c, 0x62, 0x42, 0x42, 0x42, 0x42, 0x00, 0x00], // 'h'
+    [0x00, 0x00, 0x00, 0x10, 0x00, 0x30, 0x10, 0x10, 0x10, 0x10, 0x7c, 0x00, 0x00], // 'i'
+    [0x00, 0x00, 0x00, 0x04, 0x00, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x44, 0x44, 0x38], // 'j'
+    [0x00, 0x00, 0x40, 0x40, 0x40, 0x44, 0x48, 0x70, 0x48, 0x44, 0x42, 0x00, 0x00], // 'k'
+    [0x00, 0x00, 0x30, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x7c, 0x00, 0x00], // 'l'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0xec, 0x92, 0x92, 0x92, 0x92, 0x82, 0x00, 0x00], // 'm'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x5c, 0x62, 0x42, 0x42, 0x42, 0x42, 0x00, 0x00], // 'n'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x3c, 0x42, 0x42, 0x42, 0x42, 0x3c, 0x00, 0x00], // 'o'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x5c, 0x62, 0x42, 0x62, 0x5c, 0x40, 0x40, 0x40], // 'p'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x3a, 0x46, 0x42, 0x46, 0x3a, 0x02, 0x02, 0x02], // 'q'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x5c, 0x22, 0x20, 0x20, 0x20, 0x20, 0x00, 0x00], // 'r'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x3c, 0x42, 0x30, 0x0c, 0x42, 0x3c, 0x00, 0x00], // 's'
+    [0x00, 0x00, 0x00, 0x20, 0x20, 0x7c, 0x20, 0x20, 0x20, 0x22, 0x1c, 0x00, 0x00], // 't'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x44, 0x44, 0x44, 0x44, 0x44, 0x3a, 0x00, 0x00], // 'u'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x44, 0x44, 0x44, 0x28, 0x28, 0x10, 0x00, 0x00], // 'v'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x82, 0x82, 0x92, 0x92, 0xaa, 0x44, 0x00, 0x00], // 'w'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x42, 0x24, 0x18, 0x18, 0x24, 0x42, 0x00, 0x00], // 'x'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x42, 0x42, 0x42, 0x46, 0x3a, 0x02, 0x42, 0x3c], // 'y'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x7e, 0x04, 0x08, 0x10, 0x20, 0x7e, 0x00, 0x00], // 'z'
+    [0x00, 0x00, 0x0e, 0x10, 0x10, 0x08, 0x30, 0x08, 0x10, 0x10, 0x0e, 0x00, 0x00], // '{'
+    [0x00, 0x00, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x00, 0x00], // '|'
+    [0x00, 0x00, 0x70, 0x08, 0x08, 0x10, 0x0c, 0x10, 0x08, 0x08, 0x70, 0x00, 0x00], // '}'
+    [0x00, 0x00, 0x24, 0x54, 0x48, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '~'
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Draws a glyph as text: `#` for set pixels and `.` for the rest.
+    fn render(font: &BitmapFont, c: char) -> Vec<String> {
+        let glyph = font.glyph(c).unwrap();
+        let (width, height) = font.glyph_size();
+        (0..height)
+            .map(|y| {
+                (0..width)
+                    .map(|x| if font.is_set(glyph, x, y) { '#' } else { '.' })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn builtin() {
+        let font = BitmapFont::builtin();
+        assert_eq!(font.glyph_size(), (8, 13));
+        assert!(font.has_glyph('~') && !font.has_glyph('é'));
+        assert_eq!(render(font, 'é'), render(font, '\0'));
+        assert_ne!(render(font, 'é'), render(font, ' '));
+        assert_eq!(
+            render(font, 'A')[2..10],
+            [
+                "...##...", "..#..#..", ".#....#.", ".#....#.", ".#....#.", ".######.", ".#....#.",
+                ".#....#.",
+            ]
+        );
+        assert_eq!(font.text_size("FPS: 60\n\nframe"), (56, 39));
+        assert_eq!(font.text_size(""), (0, 13));
+    }
+
+    #[test]
+    fn psf1() {
+        let mut data = vec![0x36, 0x04, 0x02, 2];
+        for glyph in 0..256 {
+            data.extend_from_slice(&[glyph as u8, !(glyph as u8)]);
+        }
+        // Glyph 0 is for 'A' and 'Ä', glyph 1 for 'A' followed by a combining ring, and the rest
+        // for nothing.
+        for unit in [0x41, 0xC4, 0xFFFF, 0xFFFE, 0x41, 0x30A, 0xFFFF] {
+            data.extend_from_slice(&u16::to_le_bytes(unit));
+        }
+        data.resize(data.len() + 254 * 2, 0xFF);
+
+        let font = BitmapFont::from_psf(&data).unwrap();
+        assert_eq!(font.glyph_size(), (8, 2));
+        assert_eq!(font.glyph('A'), Some(&[0x00, 0xFF][..]));
+        assert_eq!(font.glyph('Ä'), font.glyph('A'));
+        assert!(!font.has_glyph('\u{30A}') && font.glyph('?').is_none());
+
+        assert_eq!(
+            BitmapFont::from_psf(&data[..200]).unwrap_err(),
+            FontError::Truncated
+        );
+        assert_eq!(
+            BitmapFont::from_psf(&data[1..]).unwrap_err(),
+            FontError::UnrecognizedFormat
+        );
+    }
+
+    #[test]
+    fn psf2() {
+        let mut data = vec![0x72, 0xb5, 0x4a, 0x86];
+        for field in [0, 32, 1, 3, 4, 2, 10] {
+            data.extend_from_slice(&u32::to_le_bytes(field));
+        }
+        data.extend_from_slice(&[0x80, 0x00, 0x40, 0x00]);
+        data.extend_from_slice(&[0xFF, 0xC0, 0xFF, 0xC0]);
+        data.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+        // Glyph 0 is for '?' and 'e' with a combining acute accent, glyph 1 for 'é', and glyph 2
+        // for nothing.
+        for entry in [&b"?\xFEe\xCC\x81\xFF"[..], "é".as_bytes(), b"\xFF\xFF"] {
+            data.extend_from_slice(entry);
+        }
+
+        let font = BitmapFont::from_psf(&data).unwrap();
+        assert_eq!(render(&font, '?'), ["#.........", ".#........"]);
+        assert_eq!(render(&font, 'é'), ["##########", "##########"]);
+        // Characters without glyphs fall back on '?'.
+        assert_eq!(render(&font, 'e'), render(&font, '?'));
+    }
+
+    #[test]
+    fn bdf() {
+        let bdf = "STARTFONT 2.1
+FONTBOUNDINGBOX 4 5 0 -1
+STARTPROPERTIES 1
+DEFAULT_CHAR 0
+ENDPROPERTIES
+CHARS 3
+STARTCHAR box
+ENCODING 0
+BITMAP
+F0
+90
+90
+90
+F0
+ENDCHAR
+STARTCHAR j
+ENCODING 106
+BBX 3 4 -1 -1
+BITMAP
+20
+00
+20
+C0
+ENDCHAR
+STARTCHAR unencoded
+ENCODING -1
+BBX 1 1 0 0
+BITMAP
+80
+ENDCHAR
+ENDFONT
+";
+        let font = BitmapFont::from_bdf(bdf).unwrap();
+        assert_eq!(font.glyph_size(), (4, 5));
+        assert_eq!(render(&font, 'j'), ["....", ".#..", "....", ".#..", "#..."]);
+        assert_eq!(render(&font, 'x'), ["####", "#..#", "#..#", "#..#", "####"]);
+        assert_eq!(font.glyphs.len(), 2);
+
+        let truncated = &bdf[..bdf.find("ENDCHAR").unwrap()];
+        assert_eq!(
+            BitmapFont::from_bdf(truncated).unwrap_err(),
+            FontError::Truncated
+        );
+        assert_eq!(
+            BitmapFont::from_bdf(&bdf.replace("BBX 3 4", "BBX 3")).unwrap_err(),
+            FontError::Malformed {
+                line: 18,
+                message: "BBX needs a width, height, and offset".to_owned(),
+            }
+        );
+        assert_eq!(
+            BitmapFont::from_bdf(&bdf.replace("FONTBOUNDINGBOX 4", "FONTBOUNDINGBOX 0"))
+                .unwrap_err(),
+            FontError::EmptyGlyphs
+        );
+
+        // Offsets big enough to overflow are rejected, and ones that only just fit are clipped.
+        let huge = bdf.replace("0 -1", "0 9223372036854775807");
+        assert_eq!(
+            BitmapFont::from_bdf(&huge).unwrap_err(),
+            FontError::Malformed {
+                line: 2,
+                message: "FONTBOUNDINGBOX needs a width, height, and offset".to_owned(),
+            }
+        );
+        let huge = bdf.replace("BBX 3 4 -1", "BBX 3 4 9223372036854775807");
+        assert!(matches!(
+            BitmapFont::from_bdf(&huge).unwrap_err(),
+            FontError::Malformed { line: 18, .. }
+        ));
+        let edge = bdf
+            .replace("0 -1", "2147483647 2147483647")
+            .replace("BBX 3 4 -1 -1", "BBX 3 4 -2147483648 -2147483648");
+        let font = BitmapFont::from_bdf(&edge).unwrap();
+        assert_eq!(render(&font, 'j'), ["...."; 5]);
+
+        // So are bounding boxes too big to allocate a cell of for every glyph.
+        let huge = bdf.replace(
+            "FONTBOUNDINGBOX 4 5 0 -1",
+            "FONTBOUNDINGBOX 2000000000 2000000000 0 0",
+        );
+        assert_eq!(
+            BitmapFont::from_bdf(&huge).unwrap_err(),
+            FontError::Malformed {
+                line: 2,
+                message: "the bounding box is too large".to_owned(),
+            }
+        );
+        let big = bdf.replace("FONTBOUNDINGBOX 4 5", "FONTBOUNDINGBOX 512 1024");
+        assert!(BitmapFont::from_bdf(&big).is_ok());
+    }
+}
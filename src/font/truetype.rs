@@ -0,0 +1,423 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use ab_glyph::{point, Font, FontArc, GlyphId, PxScale, ScaleFont};
+
+use super::{text_lines, FontError};
+
+/// How many positions between two pixels a glyph can be rasterized at.
+///
+/// Glyphs are placed at fractions of a pixel horizontally, so the spacing between them stays even,
+/// but only at this many fractions, so they can be cached.
+const SUBPIXEL_STEPS: u8 = 4;
+
+/// The most pixels a rasterized glyph can cover, which keeps huge sizes from allocating huge
+/// masks.
+const MAX_GLYPH_PIXELS: f32 = 4096.0 * 4096.0;
+
+/// A TrueType or OpenType font, which draws anti-aliased, proportionally spaced text.
+///
+/// Glyphs are rasterized the first time they're drawn at each size, and kept in a cache that's
+/// shared by everything drawing with the font. Drawing at many different sizes fills the cache
+/// up, so it can be emptied with [`clear_cache`](Self::clear_cache).
+pub struct TrueTypeFont {
+    font: FontArc,
+    cache: Mutex<HashMap<GlyphKey, Arc<GlyphMask>>>,
+}
+
+/// How [`draw_truetype_text`](crate::PixelBufferTyped::draw_truetype_text) draws text.
+#[derive(Debug, Clone, Copy)]
+pub struct TrueTypeStyle<'f, P> {
+    /// The font to draw the text in.
+    pub font: &'f TrueTypeFont,
+    /// The height of the font, from its lowest descender to its highest ascender, in pixels.
+    ///
+    /// Nothing is drawn at sizes that aren't finite and positive, and glyphs covering more than
+    /// 4096x4096 pixels are left out.
+    pub size: f32,
+    /// The color of the text. Its alpha is respected, and scaled by how much of each pixel the
+    /// glyphs cover.
+    pub color: P,
+}
+
+impl<'f, P> TrueTypeStyle<'f, P> {
+    /// Draws text in `font`, `size` pixels high, in `color`.
+    pub fn new(font: &'f TrueTypeFont, size: f32, color: P) -> Self {
+        TrueTypeStyle { font, size, color }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct GlyphKey {
+    id: GlyphId,
+    /// The bits of the `f32` size, which is always finite.
+    size: u32,
+    subpixel: u8,
+}
+
+/// How much of each pixel a rasterized glyph covers.
+pub(crate) struct GlyphMask {
+    /// The offset of the mask's top-left corner from the point on the baseline the glyph is drawn
+    /// at.
+    pub(crate) offset: (i64, i64),
+    pub(crate) width: usize,
+    /// The coverage of each pixel, from 0 to 255, one row after another.
+    pub(crate) coverage: Vec<u8>,
+}
+
+impl GlyphMask {
+    pub(crate) fn height(&self) -> usize {
+        self.coverage.len().checked_div(self.width).unwrap_or(0)
+    }
+}
+
+/// A glyph laid out for drawing, with the top-left corner of its mask at `pos`.
+pub(crate) struct PlacedGlyph {
+    pub(crate) pos: (i64, i64),
+    pub(crate) mask: Arc<GlyphMask>,
+}
+
+impl fmt::Debug for TrueTypeFont {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TrueTypeFont")
+            .field("glyphs", &self.font.glyph_count())
+            .finish_non_exhaustive()
+    }
+}
+
+impl TrueTypeFont {
+    /// Loads a TrueType or OpenType font from the contents of its file.
+    pub fn from_vec(data: Vec<u8>) -> Result<TrueTypeFont, FontError> {
+        let font = FontArc::try_from_vec(data).map_err(|_| FontError::UnrecognizedFormat)?;
+        Ok(TrueTypeFont::new(font))
+    }
+
+    /// Loads a TrueType or OpenType font without copying it, such as one embedded with
+    /// [`include_bytes!`].
+    pub fn from_static(data: &'static [u8]) -> Result<TrueTypeFont, FontError> {
+        let font = FontArc::try_from_slice(data).map_err(|_| FontError::UnrecognizedFormat)?;
+        Ok(TrueTypeFont::new(font))
+    }
+
+    /// The distance between the baselines of two lines of text at `size`, in pixels.
+    pub fn line_height(&self, size: f32) -> f32 {
+        let font = self.font.as_scaled(PxScale::from(size));
+        font.height() + font.line_gap()
+    }
+
+    /// The width and height, in pixels, of the boxes that the characters of `text` drawn at
+    /// `size` are laid out in, with each line of the text below the one before.
+    ///
+    /// Parts of some glyphs can stick out of their boxes, like the tails of italic letters.
+    pub fn text_size(&self, text: &str, size: f32) -> (u32, u32) {
+        let font = self.font.as_scaled(PxScale::from(size));
+        let (width, lines) = text_lines(text).fold((0.0f32, 0), |(width, lines), line| {
+            let mut pen = 0.0;
+            let mut previous = None;
+            for c in line.chars() {
+                let id = font.glyph_id(c);
+                pen += previous.map_or(0.0, |previous| font.kern(previous, id));
+                pen += font.h_advance(id);
+                previous = Some(id);
+            }
+            (width.max(pen), lines + 1)
+        });
+        let height = lines as f32 * font.height() + (lines - 1) as f32 * font.line_gap();
+        (width.ceil() as u32, height.ceil() as u32)
+    }
+
+    /// Empties the cache of rasterized glyphs.
+    pub fn clear_cache(&self) {
+        self.cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+
+    fn new(font: FontArc) -> TrueTypeFont {
+        TrueTypeFont {
+            font,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Lays out `text` at `size`, with the top-left corner of its first line at `pos`, and
+    /// rasterizes any glyphs that aren't cached yet.
+    ///
+    /// Glyphs that don't cover any pixels, like spaces, are left out.
+    pub(crate) fn layout(&self, pos: (i64, i64), text: &str, size: f32) -> Vec<PlacedGlyph> {
+        let mut glyphs = Vec::new();
+        if !size.is_finite() || size <= 0.0 {
+            return glyphs;
+        }
+        let font = self.font.as_scaled(PxScale::from(size));
+        let line_height = font.height() + font.line_gap();
+        for (line, text) in text_lines(text).enumerate() {
+            let baseline = pos.1 + (font.ascent() + line as f32 * line_height).round() as i64;
+            let mut pen = 0.0;
+            let mut previous = None;
+            for c in text.chars() {
+                let id = font.glyph_id(c);
+                pen += previous.map_or(0.0, |previous| font.kern(previous, id));
+                previous = Some(id);
+                // Round to the nearest subpixel step, which might be the next pixel.
+                let steps = (pen * f32::from(SUBPIXEL_STEPS)).round() as i64;
+                let subpixel = steps.rem_euclid(i64::from(SUBPIXEL_STEPS)) as u8;
+                let x = pos.0 + steps.div_euclid(i64::from(SUBPIXEL_STEPS));
+                pen += font.h_advance(id);
+
+                let mask = self.rasterize(GlyphKey {
+                    id,
+                    size: size.to_bits(),
+                    subpixel,
+                });
+                if !mask.coverage.is_empty() {
+                    glyphs.push(PlacedGlyph {
+                        pos: (x + mask.offset.0, baseline + mask.offset.1),
+                        mask,
+                    });
+                }
+            }
+        }
+        glyphs
+    }
+
+    fn rasterize(&self, key: GlyphKey) -> Arc<GlyphMask> {
+        let cached = self
+            .cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&key)
+            .cloned();
+        if let Some(mask) = cached {
+            return mask;
+        }
+
+        let position = point(f32::from(key.subpixel) / f32::from(SUBPIXEL_STEPS), 0.0);
+        let glyph = key
+            .id
+            .with_scale_and_position(f32::from_bits(key.size), position);
+        let outline = self.font.outline_glyph(glyph).filter(|outline| {
+            outline.px_bounds().width() * outline.px_bounds().height() <= MAX_GLYPH_PIXELS
+        });
+        let mask = match outline {
+            Some(outline) => {
+                let bounds = outline.px_bounds();
+                let (width, height) = (bounds.width() as usize, bounds.height() as usize);
+                let mut coverage = vec![0; width * height];
+                outline.draw(|x, y, c| {
+                    coverage[y as usize * width + x as usize] =
+                        (c.clamp(0.0, 1.0) * 255.0 + 0.5) as u8;
+                });
+                GlyphMask {
+                    offset: (bounds.min.x as i64, bounds.min.y as i64),
+                    width,
+                    coverage,
+                }
+            }
+            None => GlyphMask {
+                offset: (0, 0),
+                width: 0,
+                coverage: Vec::new(),
+            },
+        };
+        let mask = Arc::new(mask);
+        self.cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(key, mask.clone());
+        mask
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util::render, Gray8, PixelBufferTyped, Rect, BGRA};
+
+    /// Builds a font with 1000 units per em and no descent, so 10 pixel text has 100 units per
+    /// pixel. `I` is a 5x8 pixel block that advances 6 pixels, `V` a 2x2 block a pixel to the right
+    /// of its origin that advances 4, and a space advances 3. `V` is kerned 2 pixels closer to `I`.
+    fn test_font() -> TrueTypeFont {
+        fn be16(data: &mut Vec<u8>, values: &[i32]) {
+            for &value in values {
+                data.extend_from_slice(&(value as u16).to_be_bytes());
+            }
+        }
+        fn be32(data: &mut Vec<u8>, values: &[u32]) {
+            for &value in values {
+                data.extend_from_slice(&value.to_be_bytes());
+            }
+        }
+
+        // (character, advance, rectangle as (left, bottom, right, top)) for each glyph after
+        // .notdef.
+        let glyphs = [
+            ('I', 600, Some((0, 0, 500, 800))),
+            ('V', 400, Some((100, 0, 300, 200))),
+            (' ', 300, None),
+        ];
+        let (mut glyf, mut loca, mut hmtx) = (Vec::new(), Vec::new(), Vec::new());
+        be32(&mut loca, &[0, 0]);
+        be16(&mut hmtx, &[500, 0]);
+        for &(_, advance, rect) in &glyphs {
+            if let Some((left, bottom, right, top)) = rect {
+                // One contour of four on-curve points, with no instructions, and each point
+                // given relative to the one before.
+                be16(&mut glyf, &[1, left, bottom, right, top, 3, 0]);
+                glyf.extend_from_slice(&[0x01; 4]);
+                be16(&mut glyf, &[left, 0, right - left, 0]);
+                be16(&mut glyf, &[bottom, top - bottom, 0, bottom - top]);
+            }
+            be32(&mut loca, &[glyf.len() as u32]);
+            be16(&mut hmtx, &[advance, rect.map_or(0, |rect| rect.0)]);
+        }
+
+        // Version, revision, checksum adjustment, and magic number, then flags, units per em,
+        // dates, bounding box, style, smallest size, direction hint, long `loca` offsets, and the
+        // glyph format.
+        let mut head = Vec::new();
+        be32(&mut head, &[0x10000, 0x10000, 0, 0x5F0F3CF5]);
+        be16(
+            &mut head,
+            &[
+                0, 1000, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 500, 800, 0, 3, 2, 1, 0,
+            ],
+        );
+        // Version, ascender, descender, line gap, widest advance, bearings, extent, caret slope
+        // and offset, reserved fields, metric format, and the number of metrics.
+        let mut hhea = Vec::new();
+        be32(&mut hhea, &[0x10000]);
+        be16(
+            &mut hhea,
+            &[1000, 0, 0, 600, 0, 0, 500, 1, 0, 0, 0, 0, 0, 0, 0, 4],
+        );
+        // Version 0.5, which only has the number of glyphs.
+        let mut maxp = Vec::new();
+        be32(&mut maxp, &[0x5000]);
+        be16(&mut maxp, &[4]);
+        // A single format 12 subtable, for Unicode on Windows.
+        let mut cmap = Vec::new();
+        be16(&mut cmap, &[0, 1, 3, 10]);
+        be32(&mut cmap, &[12]);
+        be16(&mut cmap, &[12, 0]);
+        be32(
+            &mut cmap,
+            &[16 + 12 * glyphs.len() as u32, 0, glyphs.len() as u32],
+        );
+        // The groups have to be sorted by character.
+        let mut groups: Vec<_> = (1..)
+            .zip(&glyphs)
+            .map(|(id, &(c, _, _))| (c as u32, id))
+            .collect();
+        groups.sort_unstable();
+        for (c, id) in groups {
+            be32(&mut cmap, &[c, c, id]);
+        }
+        // One horizontal format 0 subtable, with one pair: `I` then `V`, 200 units closer.
+        let mut kern = Vec::new();
+        be16(&mut kern, &[0, 1, 0, 20, 1, 1, 6, 0, 0, 1, 2, -200]);
+
+        let mut tables = [
+            (*b"cmap", cmap),
+            (*b"glyf", glyf),
+            (*b"head", head),
+            (*b"hhea", hhea),
+            (*b"hmtx", hmtx),
+            (*b"kern", kern),
+            (*b"loca", loca),
+            (*b"maxp", maxp),
+        ];
+        // The table directory, sorted by tag, without checksums since nothing checks them.
+        let mut font = Vec::new();
+        be32(&mut font, &[0x10000]);
+        be16(&mut font, &[tables.len() as i32, 128, 3, 0]);
+        let mut offset = 12 + 16 * tables.len();
+        for (tag, table) in &mut tables {
+            font.extend_from_slice(tag);
+            be32(&mut font, &[0, offset as u32, table.len() as u32]);
+            table.resize((table.len() + 3) & !3, 0);
+            offset += table.len();
+        }
+        for (_, table) in &tables {
+            font.extend_from_slice(table);
+        }
+        TrueTypeFont::from_vec(font).unwrap()
+    }
+
+    #[test]
+    fn measures() {
+        let font = test_font();
+        assert_eq!(font.line_height(10.0), 10.0);
+        assert_eq!(font.text_size("I V", 10.0), (13, 10));
+        assert_eq!(font.text_size("IV\n\nI", 10.0), (8, 30));
+        assert_eq!(font.text_size("", 10.0), (0, 10));
+    }
+
+    #[test]
+    fn draws_kerned_text() {
+        let font = test_font();
+        let mut pb = PixelBufferTyped::new_headless(12, 10);
+        pb.fill(Gray8(0));
+        pb.clear_damage();
+        pb.draw_truetype_text((1, -2), "IV", &TrueTypeStyle::new(&font, 10.0, Gray8(255)));
+        assert_eq!(
+            render(&pb),
+            [
+                ".#####......",
+                ".#####......",
+                ".#####......",
+                ".#####......",
+                ".#####......",
+                ".#####......",
+                ".#######....",
+                ".#######....",
+                "............",
+                "............",
+            ]
+        );
+        assert_eq!(pb.damage().rects(), &[Rect::new(1, 0, 7, 8)]);
+
+        // Text that lands between pixels covers them partially.
+        font.clear_cache();
+        let mut pb = PixelBufferTyped::new_headless(6, 1);
+        pb.fill(Gray8(0));
+        pb.draw_truetype_text((0, -2), " VV", &TrueTypeStyle::new(&font, 2.5, Gray8(255)));
+        assert_eq!(render(&pb), [".++...".to_owned()]);
+    }
+
+    #[test]
+    fn blends_in_linear_light() {
+        let font = test_font();
+        let mut pb = PixelBufferTyped::new_headless(5, 8);
+        pb.fill(BGRA::new(0, 0, 0, 255));
+        let red = BGRA::new(0, 0, 255, 128);
+        pb.draw_truetype_text((0, -2), "I", &TrueTypeStyle::new(&font, 10.0, red));
+        assert!(pb
+            .rows()
+            .flatten()
+            .all(|&pixel| pixel == BGRA::new(0, 0, 188, 255)));
+    }
+
+    #[test]
+    fn skips_unrasterizable_sizes() {
+        let font = test_font();
+        let mut pb = PixelBufferTyped::new_headless(4, 4);
+        pb.fill(Gray8(0));
+        for size in [f32::INFINITY, f32::NAN, -1.0, 0.0, 1e30, 100_000.0] {
+            pb.draw_truetype_text((0, 0), "IV", &TrueTypeStyle::new(&font, size, Gray8(255)));
+        }
+        assert!(pb.rows().flatten().all(|&pixel| pixel == Gray8(0)));
+
+        // Big glyphs that fit under the limit are still drawn.
+        pb.draw_truetype_text(
+            (0, -500),
+            "I",
+            &TrueTypeStyle::new(&font, 1_000.0, Gray8(255)),
+        );
+        assert!(pb.rows().flatten().all(|&pixel| pixel == Gray8(255)));
+    }
+}
@@ -0,0 +1,421 @@
+//! Floating-point pixel buffers for high dynamic range rendering.
+//!
+//! An [`HdrPixelBuffer`] holds linear light that can go past `1.0`. It's scaled by an exposure,
+//! squeezed into the displayable range by a [`ToneMap`], and sRGB encoded on its way into the
+//! [`NativeFormat`] pixel buffer that gets blitted.
+
+use std::io;
+
+use raw_window_handle::{HasDisplayHandle, HasWindowHandle};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+use crate::{
+    color, convert::convert_row, platform_impl::stride, NativeFormat, PixelBufferCreationError,
+    PixelBufferTyped, Rect, BGRA,
+};
+
+/// A floating-point channel type that [`HdrPixelBuffer`]s can store.
+///
+/// Implemented for `f32`, and for `half::f16` with the `half` feature.
+pub trait HdrChannel: Copy + Send + Sync + 'static {
+    const ZERO: Self;
+
+    fn to_f32(self) -> f32;
+    fn from_f32(value: f32) -> Self;
+}
+
+impl HdrChannel for f32 {
+    const ZERO: f32 = 0.0;
+
+    fn to_f32(self) -> f32 {
+        self
+    }
+    fn from_f32(value: f32) -> f32 {
+        value
+    }
+}
+
+#[cfg(feature = "half")]
+impl HdrChannel for half::f16 {
+    const ZERO: half::f16 = half::f16::ZERO;
+
+    fn to_f32(self) -> f32 {
+        half::f16::to_f32(self)
+    }
+    fn from_f32(value: f32) -> half::f16 {
+        half::f16::from_f32(value)
+    }
+}
+
+/// How [`HdrPixelBuffer`] squeezes unbounded linear light into the displayable range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ToneMap {
+    /// Anything brighter than `1.0` is shown as `1.0`.
+    #[default]
+    Clamp,
+    /// `x / (1 + x)`, which never quite reaches white.
+    Reinhard,
+    /// Krzysztof Narkowicz's fit of the ACES filmic curve, which rolls highlights off gently and
+    /// adds a little contrast.
+    Aces,
+}
+
+impl ToneMap {
+    /// Maps a linear channel value into `0.0..=1.0`.
+    pub fn apply(self, x: f32) -> f32 {
+        // `max` also turns NaN into zero.
+        let x = x.max(0.0);
+        if x == f32::INFINITY {
+            return 1.0;
+        }
+        let mapped = match self {
+            ToneMap::Clamp => x,
+            ToneMap::Reinhard => x / (1.0 + x),
+            ToneMap::Aces => (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14),
+        };
+        mapped.clamp(0.0, 1.0)
+    }
+}
+
+/// Encodes a linear value in `0.0..=1.0` with the sRGB transfer function.
+fn srgb_encode(linear: f32) -> u8 {
+    color::linear_to_srgb((linear * f32::from(u16::MAX) + 0.5) as u16)
+}
+
+/// A buffer of linear-light, floating-point RGBA pixels that gets tone mapped into a
+/// [`NativeFormat`] pixel buffer when it's presented.
+///
+/// Renderers can keep accumulating into the floating-point pixels across frames, and call
+/// [`blit`](Self::blit) whenever they want to show the current state. Only color channels are
+/// tone mapped and sRGB encoded; alpha is clamped into `0.0..=1.0` and stored linearly.
+pub struct HdrPixelBuffer<C: HdrChannel = f32> {
+    width: u32,
+    height: u32,
+    pixels: Vec<[C; 4]>,
+    tone_map: ToneMap,
+    exposure: f32,
+    target: PixelBufferTyped<NativeFormat>,
+}
+
+impl<C: HdrChannel> HdrPixelBuffer<C> {
+    /// Initialize a new HDR pixel buffer, with every pixel transparent black.
+    pub fn new<H: HasWindowHandle, D: HasDisplayHandle>(
+        width: u32,
+        height: u32,
+        window: &H,
+        display: &D,
+    ) -> Result<HdrPixelBuffer<C>, PixelBufferCreationError> {
+        let target = PixelBufferTyped::new(width, height, window, display)?;
+        Ok(Self::from_target(target))
+    }
+
+    /// Initialize a new HDR pixel buffer that isn't attached to a window.
+    ///
+    /// See [`PixelBuffer::new_headless`](crate::PixelBuffer::new_headless).
+    pub fn new_headless(width: u32, height: u32) -> HdrPixelBuffer<C> {
+        Self::from_target(PixelBufferTyped::new_headless(width, height))
+    }
+
+    fn from_target(target: PixelBufferTyped<NativeFormat>) -> HdrPixelBuffer<C> {
+        let (width, height) = (target.width(), target.height());
+        HdrPixelBuffer {
+            width,
+            height,
+            pixels: vec![[C::ZERO; 4]; width as usize * height as usize],
+            tone_map: ToneMap::default(),
+            exposure: 1.0,
+            target,
+        }
+    }
+
+    /// The tone map applied when presenting.
+    pub fn tone_map(&self) -> ToneMap {
+        self.tone_map
+    }
+
+    /// Changes the tone map applied when presenting.
+    pub fn set_tone_map(&mut self, tone_map: ToneMap) {
+        self.tone_map = tone_map;
+    }
+
+    /// The factor that color channels are multiplied by before they're tone mapped.
+    pub fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    /// Changes the factor that color channels are multiplied by before they're tone mapped.
+    ///
+    /// Progressive renderers that accumulate a sum of samples can set this to `1.0 / samples`
+    /// rather than dividing every pixel themselves. Defaults to `1.0`.
+    pub fn set_exposure(&mut self, exposure: f32) {
+        self.exposure = exposure;
+    }
+
+    /// Tone maps the whole buffer into the native pixel buffer, without blitting it.
+    pub fn present(&mut self) {
+        self.present_rect(Rect::new(0, 0, self.width, self.height));
+    }
+
+    /// Tone maps the part of the buffer in `rect` into the native pixel buffer, without blitting
+    /// it.
+    ///
+    /// The area is marked as damaged in the native pixel buffer, so it's presented by the next
+    /// [`blit_damage`](Self::blit_damage).
+    pub fn present_rect(&mut self, rect: Rect) {
+        let rect = rect.intersection(&Rect::new(0, 0, self.width, self.height));
+        if rect.is_empty() {
+            return;
+        }
+        let (tone_map, exposure) = (self.tone_map, self.exposure);
+        let (start, len) = (rect.x as usize, rect.width as usize);
+        let row_len = self.width as usize;
+        let src_rows = self.pixels[rect.y as usize * row_len..]
+            .chunks(row_len)
+            .take(rect.height as usize);
+        for (y, src) in (rect.y..).zip(src_rows) {
+            let dst = self.target.row_mut_undamaged(y).unwrap();
+            present_row(
+                &src[start..][..len],
+                &mut dst[start..][..len],
+                tone_map,
+                exposure,
+            );
+        }
+        self.target.mark_dirty(rect);
+    }
+
+    /// Tone maps the whole buffer in parallel, without blitting it.
+    ///
+    /// See [`present`](Self::present).
+    #[cfg(feature = "rayon")]
+    pub fn par_present(&mut self) {
+        let (tone_map, exposure) = (self.tone_map, self.exposure);
+        let row_len = self.width as usize;
+        if row_len == 0 {
+            return;
+        }
+        self.pixels
+            .par_chunks(row_len)
+            .zip(self.target.par_rows_mut())
+            .for_each(|(src, dst)| present_row(src, dst, tone_map, exposure));
+    }
+
+    /// Tone maps the whole buffer and blits it onto `window`.
+    ///
+    /// # Errors
+    /// Returns an error if the platform fails to blit. The `window` passed to this function must be
+    /// the same `window` passed to `new`, or an error may be returned.
+    pub fn blit<H: HasWindowHandle>(&mut self, window: &H) -> io::Result<()> {
+        self.present();
+        self.target.clear_damage();
+        self.target.blit(window)
+    }
+
+    /// Blits whatever parts of the native pixel buffer have been presented since they were last
+    /// blitted.
+    ///
+    /// See [`PixelBuffer::blit_damage`](crate::PixelBuffer::blit_damage).
+    pub fn blit_damage<H: HasWindowHandle>(&mut self, window: &H) -> io::Result<()> {
+        self.target.blit_damage(window)
+    }
+
+    /// Changes the dimensions of the buffer, keeping the pixels that are in both the old and new
+    /// dimensions. New pixels are transparent black.
+    pub fn resize(&mut self, width: u32, height: u32) -> Result<(), PixelBufferCreationError> {
+        self.target.resize(width, height)?;
+        let mut pixels = vec![[C::ZERO; 4]; width as usize * height as usize];
+        let copy_len = self.width.min(width) as usize;
+        if copy_len > 0 {
+            let old_rows = self.pixels.chunks(self.width as usize);
+            for (old, new) in old_rows.zip(pixels.chunks_mut(width as usize)) {
+                new[..copy_len].copy_from_slice(&old[..copy_len]);
+            }
+        }
+        self.pixels = pixels;
+        self.width = width;
+        self.height = height;
+        Ok(())
+    }
+
+    /// The native pixel buffer that the buffer is presented into.
+    pub fn target(&self) -> &PixelBufferTyped<NativeFormat> {
+        &self.target
+    }
+
+    /// The width of the buffer, in pixels.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// The height of the buffer, in pixels.
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Gets the row at the particular height.
+    pub fn row(&self, row: u32) -> Option<&[[C; 4]]> {
+        self.pixels
+            .chunks(stride(self.width as usize))
+            .nth(row as usize)
+    }
+
+    /// Mutably gets the row at the particular height.
+    pub fn row_mut(&mut self, row: u32) -> Option<&mut [[C; 4]]> {
+        let stride = stride(self.width as usize);
+        self.pixels.chunks_mut(stride).nth(row as usize)
+    }
+
+    /// Iterate through all rows in the buffer.
+    pub fn rows(&self) -> impl ExactSizeIterator + DoubleEndedIterator<Item = &[[C; 4]]> {
+        self.pixels.chunks(stride(self.width as usize))
+    }
+
+    /// Mutably iterate through all rows in the buffer.
+    pub fn rows_mut(
+        &mut self,
+    ) -> impl ExactSizeIterator + DoubleEndedIterator<Item = &mut [[C; 4]]> {
+        let stride = stride(self.width as usize);
+        self.pixels.chunks_mut(stride)
+    }
+
+    /// Iterate through all rows in the buffer.
+    #[cfg(feature = "rayon")]
+    pub fn par_rows(&self) -> impl IndexedParallelIterator<Item = &[[C; 4]]> {
+        self.pixels.par_chunks(stride(self.width as usize))
+    }
+
+    /// Mutably iterate through all rows in the buffer.
+    #[cfg(feature = "rayon")]
+    pub fn par_rows_mut(&mut self) -> impl IndexedParallelIterator<Item = &mut [[C; 4]]> {
+        let stride = stride(self.width as usize);
+        self.pixels.par_chunks_mut(stride)
+    }
+}
+
+/// Tone maps and encodes a row of HDR pixels into a row of native pixels.
+fn present_row<C: HdrChannel>(
+    src: &[[C; 4]],
+    dst: &mut [NativeFormat],
+    tone_map: ToneMap,
+    exposure: f32,
+) {
+    let mut bgra = [BGRA::DEFAULT; 64];
+    for (src, dst) in src.chunks(bgra.len()).zip(dst.chunks_mut(bgra.len())) {
+        for (&[r, g, b, a], bgra) in src.iter().zip(&mut bgra) {
+            let encode = |c: C| srgb_encode(tone_map.apply(c.to_f32() * exposure));
+            let alpha = (a.to_f32().clamp(0.0, 1.0) * 255.0 + 0.5) as u8;
+            *bgra = BGRA::new(encode(b), encode(g), encode(r), alpha);
+        }
+        convert_row(&bgra[..src.len()], dst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::HeadlessWindow;
+
+    /// The presented pixel at `(x, y)`, as BGRA.
+    fn presented<C: HdrChannel>(pb: &HdrPixelBuffer<C>, x: usize, y: u32) -> BGRA {
+        let surface = pb.target().headless_surface().unwrap();
+        let mut bgra = [BGRA::DEFAULT];
+        convert_row(
+            &NativeFormat::from_raw_slice(surface.row(y).unwrap())[x..][..1],
+            &mut bgra,
+        );
+        bgra[0]
+    }
+
+    #[test]
+    fn tone_maps() {
+        assert_eq!(ToneMap::Clamp.apply(4.0), 1.0);
+        assert_eq!(ToneMap::Clamp.apply(-1.0), 0.0);
+        assert_eq!(ToneMap::Clamp.apply(f32::NAN), 0.0);
+        assert_eq!(ToneMap::Reinhard.apply(1.0), 0.5);
+        assert_eq!(ToneMap::Reinhard.apply(3.0), 0.75);
+        assert!(ToneMap::Aces.apply(0.18) > 0.18 && ToneMap::Aces.apply(0.18) < 0.3);
+        assert_eq!(ToneMap::Aces.apply(100.0), 1.0);
+        assert_eq!(ToneMap::Reinhard.apply(f32::INFINITY), 1.0);
+        for tone_map in [ToneMap::Clamp, ToneMap::Reinhard, ToneMap::Aces].iter() {
+            assert_eq!(tone_map.apply(0.0), 0.0);
+        }
+    }
+
+    #[test]
+    fn srgb() {
+        assert_eq!(srgb_encode(0.0), 0);
+        assert_eq!(srgb_encode(1.0), 255);
+        assert_eq!(srgb_encode(0.5), 188);
+        assert_eq!(srgb_encode(0.002), 7);
+    }
+
+    #[test]
+    fn blit_presents() {
+        let mut pb = HdrPixelBuffer::<f32>::new_headless(3, 2);
+        pb.row_mut(1).unwrap()[1] = [4.0, 1.0, 0.25, 0.5];
+        pb.row_mut(1).unwrap()[2] = [2.0, 2.0, 2.0, 2.0];
+        pb.blit(&HeadlessWindow).unwrap();
+        assert_eq!(presented(&pb, 0, 0), BGRA::new(0, 0, 0, 0));
+        assert_eq!(presented(&pb, 1, 1), BGRA::new(137, 255, 255, 128));
+        assert_eq!(presented(&pb, 2, 1), BGRA::new(255, 255, 255, 255));
+
+        pb.set_tone_map(ToneMap::Reinhard);
+        pb.set_exposure(0.5);
+        pb.row_mut(0).unwrap()[0] = [2.0, 0.0, 0.0, 1.0];
+        pb.present_rect(Rect::new(0, 0, 1, 1));
+        assert_eq!(pb.target().damage().rects(), &[Rect::new(0, 0, 1, 1)]);
+        pb.blit_damage(&HeadlessWindow).unwrap();
+        assert_eq!(presented(&pb, 0, 0), BGRA::new(0, 0, 188, 255));
+        // Only the presented area changed.
+        assert_eq!(presented(&pb, 2, 1), BGRA::new(255, 255, 255, 255));
+    }
+
+    #[test]
+    fn resize_keeps_overlap() {
+        let mut pb = HdrPixelBuffer::<f32>::new_headless(2, 2);
+        pb.row_mut(0).unwrap()[1] = [1.0; 4];
+        pb.row_mut(1).unwrap()[0] = [2.0; 4];
+        pb.resize(3, 1).unwrap();
+        assert_eq!(pb.rows().len(), 1);
+        assert_eq!(pb.row(0).unwrap(), &[[0.0; 4], [1.0; 4], [0.0; 4]]);
+        pb.blit(&HeadlessWindow).unwrap();
+        assert_eq!(presented(&pb, 1, 0), BGRA::new(255, 255, 255, 255));
+
+        pb.resize(0, 0).unwrap();
+        assert_eq!(pb.rows().len(), 0);
+        pb.blit(&HeadlessWindow).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "half")]
+    fn half() {
+        use half::f16;
+
+        let mut pb = HdrPixelBuffer::<f16>::new_headless(1, 1);
+        pb.row_mut(0).unwrap()[0] = [f16::from_f32(0.5), f16::ZERO, f16::ONE, f16::ONE];
+        pb.blit(&HeadlessWindow).unwrap();
+        assert_eq!(presented(&pb, 0, 0).r, 188);
+        assert_eq!(presented(&pb, 0, 0).b, 255);
+    }
+
+    #[test]
+    #[cfg(feature = "rayon")]
+    fn par_present_matches_present() {
+        let buffer = || {
+            let mut pb = HdrPixelBuffer::<f32>::new_headless(70, 5);
+            pb.par_rows_mut().enumerate().for_each(|(y, row)| {
+                for (x, pixel) in row.iter_mut().enumerate() {
+                    *pixel = [x as f32 / 20.0, y as f32, 0.5, 1.0];
+                }
+            });
+            pb.set_tone_map(ToneMap::Aces);
+            pb
+        };
+        let (mut a, mut b) = (buffer(), buffer());
+        a.present();
+        b.par_present();
+        assert!(a.target().rows().eq(b.target().rows()));
+    }
+}
@@ -0,0 +1,916 @@
+//! Loading and saving image files.
+//!
+//! PNG, BMP, PPM, and TGA files can be loaded into a [`PixelBufferTyped`] of any format, and
+//! pixel buffers or [views](PixelView) of them can be saved as PNG or PPM. Images are converted
+//! through 8 bits per channel either way.
+
+use std::{
+    error::Error,
+    fmt, fs,
+    io::{self, BufWriter, Cursor, Write},
+    path::Path,
+};
+
+use crate::{
+    convert::convert_row, Gray8, PixelBufferFormat, PixelBufferFormatType, PixelBufferTyped,
+    PixelView, Rect, RGB, RGBA,
+};
+
+/// An image file format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ImageFormat {
+    /// Portable Network Graphics, in any color type and bit depth.
+    Png,
+    /// Windows bitmaps, uncompressed, with 1 to 32 bits per pixel.
+    Bmp,
+    /// Netpbm's portable pixmaps and graymaps, in either their binary or text forms.
+    Ppm,
+    /// Truevision TGA, with or without run-length encoding.
+    Tga,
+}
+
+impl ImageFormat {
+    /// Guesses the format of a file from its extension.
+    pub fn from_path(path: impl AsRef<Path>) -> Option<ImageFormat> {
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match &*extension {
+            "png" => Some(ImageFormat::Png),
+            "bmp" | "dib" => Some(ImageFormat::Bmp),
+            "ppm" | "pgm" | "pnm" => Some(ImageFormat::Ppm),
+            "tga" => Some(ImageFormat::Tga),
+            _ => None,
+        }
+    }
+
+    /// Recognizes the format of an image from the first few bytes of its data.
+    ///
+    /// TGA files don't start with anything recognizable, so they're never detected.
+    pub fn detect(data: &[u8]) -> Option<ImageFormat> {
+        match data {
+            [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n', ..] => Some(ImageFormat::Png),
+            [b'B', b'M', ..] => Some(ImageFormat::Bmp),
+            [b'P', b'2' | b'3' | b'5' | b'6', ..] => Some(ImageFormat::Ppm),
+            _ => None,
+        }
+    }
+}
+
+/// An error that can occur while loading or saving an image.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ImageError {
+    /// Reading or writing the image failed.
+    Io(io::Error),
+    /// The image's format couldn't be recognized.
+    UnrecognizedFormat,
+    /// The image uses a feature of its format that isn't supported, or can't be saved in the
+    /// requested format.
+    Unsupported(String),
+    /// The image's data is corrupt.
+    Malformed(String),
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "failed to read or write image: {}", e),
+            Self::UnrecognizedFormat => write!(f, "the image isn't in a recognized format"),
+            Self::Unsupported(e) => write!(f, "unsupported image: {}", e),
+            Self::Malformed(e) => write!(f, "malformed image: {}", e),
+        }
+    }
+}
+
+impl Error for ImageError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ImageError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<png::DecodingError> for ImageError {
+    fn from(e: png::DecodingError) -> Self {
+        match e {
+            png::DecodingError::IoError(e) => Self::Io(e),
+            e => Self::Malformed(e.to_string()),
+        }
+    }
+}
+
+impl From<png::EncodingError> for ImageError {
+    fn from(e: png::EncodingError) -> Self {
+        match e {
+            png::EncodingError::IoError(e) => Self::Io(e),
+            e => Self::Unsupported(e.to_string()),
+        }
+    }
+}
+
+fn malformed<T>(message: &str) -> Result<T, ImageError> {
+    Err(ImageError::Malformed(message.to_owned()))
+}
+
+fn unsupported<T>(message: &str) -> Result<T, ImageError> {
+    Err(ImageError::Unsupported(message.to_owned()))
+}
+
+/// A decoded image, stored top row first.
+struct Image {
+    width: u32,
+    height: u32,
+    pixels: Vec<RGBA>,
+}
+
+impl Image {
+    /// Checks the image's dimensions, given that each pixel takes up at least `min_bits_per_pixel`
+    /// of the `len` bytes left in the file, so a corrupt header can't make the image allocate far
+    /// more memory than its data could fill.
+    fn new(
+        width: u32,
+        height: u32,
+        len: usize,
+        min_bits_per_pixel: f64,
+    ) -> Result<Image, ImageError> {
+        if width == 0 || height == 0 {
+            return malformed("the image has no pixels");
+        }
+        let pixels = u64::from(width) * u64::from(height);
+        if pixels as f64 * min_bits_per_pixel > len as f64 * 8.0 {
+            return malformed("the image's data is truncated");
+        }
+        Ok(Image {
+            width,
+            height,
+            pixels: Vec::with_capacity(pixels as usize),
+        })
+    }
+}
+
+/// A cursor over the bytes of a file.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], ImageError> {
+        if len > self.data.len() {
+            return malformed("the image's data is truncated");
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, len: usize) -> Result<(), ImageError> {
+        self.bytes(len).map(drop)
+    }
+
+    fn u8(&mut self) -> Result<u8, ImageError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ImageError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, ImageError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+/// Scales a `bits`-bit channel to 8 bits, where a channel with no bits is always 0.
+fn scale(value: u32, bits: u32) -> u8 {
+    match bits {
+        0 => 0,
+        _ => {
+            let max = (1u64 << bits) - 1;
+            ((u64::from(value) * 255 + max / 2) / max) as u8
+        }
+    }
+}
+
+fn decode(data: &[u8], format: ImageFormat) -> Result<Image, ImageError> {
+    match format {
+        ImageFormat::Png => decode_png(data),
+        ImageFormat::Bmp => decode_bmp(data),
+        ImageFormat::Ppm => decode_ppm(data),
+        ImageFormat::Tga => decode_tga(data),
+    }
+}
+
+fn decode_png(data: &[u8]) -> Result<Image, ImageError> {
+    let mut decoder = png::Decoder::new(Cursor::new(data));
+    // Expand palettes and low bit depths, and strip 16-bit channels down to 8 bits.
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    // Deflate can't expand data more than 1032 times, so a header claiming more than that is
+    // corrupt, and we'd rather not find out by allocating the whole image first.
+    let info = reader.info();
+    let raw_len = u64::from(info.height).checked_mul(info.raw_row_length() as u64);
+    if raw_len.is_none_or(|raw_len| raw_len / 1032 > data.len() as u64) {
+        return malformed("the image's data is truncated");
+    }
+    let len = reader
+        .output_buffer_size()
+        .ok_or_else(|| ImageError::Unsupported("the image is too large".to_owned()))?;
+    let mut buf = vec![0; len];
+    let info = reader.next_frame(&mut buf)?;
+    let mut image = Image::new(info.width, info.height, len, 8.0)?;
+    let samples = info.color_type.samples();
+    let rows = buf.chunks_exact(info.line_size).take(info.height as usize);
+    for row in rows {
+        let row = row[..info.width as usize * samples].chunks_exact(samples);
+        image.pixels.extend(row.map(|p| match *p {
+            [l] => RGBA::new(l, l, l, 255),
+            [l, a] => RGBA::new(l, l, l, a),
+            [r, g, b] => RGBA::new(r, g, b, 255),
+            [r, g, b, a] => RGBA::new(r, g, b, a),
+            _ => unreachable!(),
+        }));
+    }
+    Ok(image)
+}
+
+fn decode_bmp(data: &[u8]) -> Result<Image, ImageError> {
+    let mut file = Reader { data };
+    if file.bytes(2)? != b"BM" {
+        return Err(ImageError::UnrecognizedFormat);
+    }
+    file.skip(8)?;
+    let pixels_start = file.u32()? as usize;
+    let header_start = data.len() - file.data.len();
+    let header_len = file.u32()? as usize;
+    let (width, height, bits, compression) = if header_len == 12 {
+        // The original OS/2 header, with 16-bit dimensions.
+        let (width, height) = (i32::from(file.u16()?), i32::from(file.u16()?));
+        file.skip(2)?;
+        (width, height, file.u16()?, 0)
+    } else if header_len >= 40 {
+        let (width, height) = (file.u32()? as i32, file.u32()? as i32);
+        file.skip(2)?;
+        (width, height, file.u16()?, file.u32()?)
+    } else {
+        return unsupported("unknown BMP header");
+    };
+    if !matches!(bits, 1 | 2 | 4 | 8 | 16 | 24 | 32) {
+        return unsupported("unsupported BMP bit depth");
+    }
+    // Positive heights are stored bottom row first, as Windows does, and negative heights top row
+    // first.
+    let bottom_up = height > 0;
+    let (width, height) = (width.unsigned_abs(), height.unsigned_abs());
+
+    // Where each channel is in the pixels of 16 and 32-bit images, as (mask, shift, bits).
+    let masks = match (compression, bits) {
+        // BI_RGB
+        (0, 16) => [0x7C00, 0x03E0, 0x001F, 0],
+        (0, 32) => [0x00FF_0000, 0x0000_FF00, 0x0000_00FF, 0],
+        (0, _) => [0; 4],
+        // BI_BITFIELDS and BI_ALPHABITFIELDS, whose masks come after the basic header if they're
+        // not part of it.
+        (3 | 6, 16 | 32) => {
+            let mut masks = Reader {
+                data: data.get(header_start + 40..).unwrap_or_default(),
+            };
+            let alpha = compression == 6 || header_len >= 56;
+            [
+                masks.u32()?,
+                masks.u32()?,
+                masks.u32()?,
+                if alpha { masks.u32()? } else { 0 },
+            ]
+        }
+        (1 | 2, _) => return unsupported("run-length encoded BMPs aren't supported"),
+        _ => return unsupported("unknown BMP compression"),
+    };
+    let channels = masks.map(|mask| (mask, mask.trailing_zeros() % 32, mask.count_ones()));
+
+    let palette: Vec<RGBA> = if bits <= 8 {
+        let mut header = Reader {
+            data: data.get(header_start + header_len..).unwrap_or_default(),
+        };
+        let entry_len = if header_len == 12 { 3 } else { 4 };
+        let used = if header_len >= 40 {
+            let mut used = Reader {
+                data: data.get(header_start + 32..).unwrap_or_default(),
+            };
+            used.u32()?
+        } else {
+            0
+        };
+        let count = if used == 0 { 1 << bits } else { used.min(256) };
+        (0..count)
+            .map(|_| {
+                let entry = header.bytes(entry_len)?;
+                Ok(RGBA::new(entry[2], entry[1], entry[0], 255))
+            })
+            .collect::<Result<_, ImageError>>()?
+    } else {
+        Vec::new()
+    };
+
+    let stride = (u64::from(width) * u64::from(bits)).div_ceil(32) * 4;
+    let pixels = data.get(pixels_start..).unwrap_or_default();
+    if stride * u64::from(height) > pixels.len() as u64 {
+        return malformed("the image's data is truncated");
+    }
+    let mut image = Image::new(width, height, pixels.len(), 1.0)?;
+    let rows = pixels.chunks(stride as usize).take(height as usize);
+    let rows: Vec<_> = if bottom_up {
+        rows.rev().collect()
+    } else {
+        rows.collect()
+    };
+    for row in rows {
+        for x in 0..width as usize {
+            let pixel = match bits {
+                1 | 2 | 4 | 8 => {
+                    let bit = x * bits as usize;
+                    let index = (row[bit / 8] >> (8 - bits as usize - bit % 8)) & ((1 << bits) - 1);
+                    *palette.get(index as usize).ok_or_else(|| {
+                        ImageError::Malformed("palette index out of range".to_owned())
+                    })?
+                }
+                24 => RGBA::new(row[x * 3 + 2], row[x * 3 + 1], row[x * 3], 255),
+                16 | 32 => {
+                    let bytes = &row[x * bits as usize / 8..][..bits as usize / 8];
+                    let value = bytes
+                        .iter()
+                        .rev()
+                        .fold(0, |value, &byte| value << 8 | u32::from(byte));
+                    let [r, g, b, a] =
+                        channels.map(|(mask, shift, bits)| scale((value & mask) >> shift, bits));
+                    // Images without an alpha channel are opaque.
+                    let a = if masks[3] == 0 { 255 } else { a };
+                    RGBA::new(r, g, b, a)
+                }
+                _ => unreachable!(),
+            };
+            image.pixels.push(pixel);
+        }
+    }
+    Ok(image)
+}
+
+/// Reads the next number in a PPM header, skipping whitespace and comments.
+fn ppm_number(rest: &mut &[u8]) -> Result<u32, ImageError> {
+    loop {
+        match *rest {
+            [b'#', ..] => {
+                let end = rest.iter().position(|&b| b == b'\n').unwrap_or(rest.len());
+                *rest = &rest[end..];
+            }
+            [b, ref tail @ ..] if b.is_ascii_whitespace() => *rest = tail,
+            _ => break,
+        }
+    }
+    let len = rest.iter().take_while(|b| b.is_ascii_digit()).count();
+    let (digits, tail) = rest.split_at(len);
+    *rest = tail;
+    std::str::from_utf8(digits)
+        .ok()
+        .and_then(|digits| digits.parse().ok())
+        .ok_or_else(|| ImageError::Malformed("expected a number".to_owned()))
+}
+
+fn decode_ppm(data: &[u8]) -> Result<Image, ImageError> {
+    let (binary, color) = match data {
+        [b'P', b'2', ..] => (false, false),
+        [b'P', b'3', ..] => (false, true),
+        [b'P', b'5', ..] => (true, false),
+        [b'P', b'6', ..] => (true, true),
+        _ => return Err(ImageError::UnrecognizedFormat),
+    };
+    let mut rest = &data[2..];
+    let (width, height) = (ppm_number(&mut rest)?, ppm_number(&mut rest)?);
+    let max = ppm_number(&mut rest)?;
+    if max == 0 || max > 0xFFFF {
+        return malformed("the maximum value must be between 1 and 65535");
+    }
+    let samples = if color { 3 } else { 1 };
+    let sample_len = if max > 0xFF { 2 } else { 1 };
+    // Text samples take at least a digit and a space each.
+    let min_bits = 8.0 * (samples * if binary { sample_len } else { 2 }) as f64;
+    let image = Image::new(width, height, rest.len(), min_bits)?;
+    let len = image.width as usize * image.height as usize * samples;
+
+    let to_u8 = |value: u32| -> Result<u8, ImageError> {
+        if value > max {
+            return malformed("a sample is larger than the maximum value");
+        }
+        Ok(((u64::from(value) * 255 + u64::from(max) / 2) / u64::from(max)) as u8)
+    };
+    let mut values = Vec::with_capacity(len);
+    if binary {
+        // A single whitespace character separates the header from the samples.
+        let data = rest
+            .get(1..1 + len * sample_len)
+            .ok_or_else(|| ImageError::Malformed("the image's data is truncated".to_owned()))?;
+        for sample in data.chunks_exact(sample_len) {
+            let value = sample
+                .iter()
+                .fold(0, |value, &byte| value << 8 | u32::from(byte));
+            values.push(to_u8(value)?);
+        }
+    } else {
+        for _ in 0..len {
+            values.push(to_u8(ppm_number(&mut rest)?)?);
+        }
+    }
+    let mut image = image;
+    image
+        .pixels
+        .extend(values.chunks_exact(samples).map(|p| match *p {
+            [l] => RGBA::new(l, l, l, 255),
+            [r, g, b] => RGBA::new(r, g, b, 255),
+            _ => unreachable!(),
+        }));
+    Ok(image)
+}
+
+fn decode_tga(data: &[u8]) -> Result<Image, ImageError> {
+    let mut file = Reader { data };
+    let id_len = file.u8()?;
+    let has_palette = file.u8()? != 0;
+    let image_type = file.u8()?;
+    let (palette_start, palette_len, palette_bits) = (file.u16()?, file.u16()?, file.u8()?);
+    file.skip(4)?;
+    let (width, height, bits, descriptor) = (file.u16()?, file.u16()?, file.u8()?, file.u8()?);
+    let alpha_bits = descriptor & 0x0F;
+    file.skip(usize::from(id_len))?;
+
+    let (kind, rle) = (image_type & 0x07, image_type & 0x08 != 0);
+    if !matches!(kind, 1..=3) || image_type & !0x0B != 0 {
+        return unsupported("unknown TGA image type");
+    }
+    // Reads one pixel from `bytes`, as a color if `gray` is false.
+    let read_color = |bytes: &[u8], gray: bool| -> RGBA {
+        match (bytes.len(), gray) {
+            (1, true) => RGBA::new(bytes[0], bytes[0], bytes[0], 255),
+            (2, true) => RGBA::new(bytes[0], bytes[0], bytes[0], bytes[1]),
+            (2, false) => {
+                let value = u16::from_le_bytes([bytes[0], bytes[1]]);
+                let channel = |shift: u16| scale(u32::from(value >> shift & 0x1F), 5);
+                let a = if alpha_bits > 0 && value & 0x8000 == 0 {
+                    0
+                } else {
+                    255
+                };
+                RGBA::new(channel(10), channel(5), channel(0), a)
+            }
+            (3, false) => RGBA::new(bytes[2], bytes[1], bytes[0], 255),
+            (_, _) => RGBA::new(
+                bytes[2],
+                bytes[1],
+                bytes[0],
+                if alpha_bits > 0 { bytes[3] } else { 255 },
+            ),
+        }
+    };
+
+    let mut palette = Vec::new();
+    if has_palette {
+        let entry_len = usize::from(palette_bits).div_ceil(8);
+        if !matches!(entry_len, 2..=4) {
+            return unsupported("unsupported TGA palette entry size");
+        }
+        for _ in 0..palette_len {
+            palette.push(read_color(file.bytes(entry_len)?, false));
+        }
+    }
+    let pixel_len = usize::from(bits).div_ceil(8);
+    let valid = match kind {
+        1 => has_palette && pixel_len == 1,
+        2 => matches!(pixel_len, 2..=4),
+        _ => matches!(pixel_len, 1 | 2),
+    };
+    if !valid {
+        return unsupported("unsupported TGA pixel depth");
+    }
+    let decode_pixel = |bytes: &[u8]| -> Result<RGBA, ImageError> {
+        match kind {
+            1 => {
+                let index = usize::from(bytes[0]).wrapping_sub(usize::from(palette_start));
+                palette
+                    .get(index)
+                    .copied()
+                    .ok_or_else(|| ImageError::Malformed("palette index out of range".to_owned()))
+            }
+            _ => Ok(read_color(bytes, kind == 3)),
+        }
+    };
+
+    // Each run-length packet expands to at most 128 pixels.
+    let min_bits = if rle {
+        8.0 * (1 + pixel_len) as f64 / 128.0
+    } else {
+        8.0 * pixel_len as f64
+    };
+    let mut image = Image::new(
+        u32::from(width),
+        u32::from(height),
+        file.data.len(),
+        min_bits,
+    )?;
+    let count = usize::from(width) * usize::from(height);
+    while image.pixels.len() < count {
+        let (repeat, len) = if rle {
+            let header = file.u8()?;
+            (header & 0x80 != 0, usize::from(header & 0x7F) + 1)
+        } else {
+            (false, count)
+        };
+        let len = len.min(count - image.pixels.len());
+        if repeat {
+            let pixel = decode_pixel(file.bytes(pixel_len)?)?;
+            image.pixels.extend(std::iter::repeat_n(pixel, len));
+        } else {
+            for bytes in file.bytes(len * pixel_len)?.chunks_exact(pixel_len) {
+                image.pixels.push(decode_pixel(bytes)?);
+            }
+        }
+    }
+
+    // TGAs are stored bottom row first, unless the descriptor says otherwise.
+    let width = usize::from(width);
+    if descriptor & 0x20 == 0 {
+        let rows: Vec<_> = image
+            .pixels
+            .chunks_exact(width)
+            .rev()
+            .flatten()
+            .copied()
+            .collect();
+        image.pixels = rows;
+    }
+    if descriptor & 0x10 != 0 {
+        for row in image.pixels.chunks_exact_mut(width) {
+            row.reverse();
+        }
+    }
+    Ok(image)
+}
+
+fn check_encodable(format: ImageFormat) -> Result<(), ImageError> {
+    match format {
+        ImageFormat::Png | ImageFormat::Ppm => Ok(()),
+        _ => unsupported("images can only be saved as PNG or PPM"),
+    }
+}
+
+impl<P: PixelBufferFormat> PixelBufferTyped<P> {
+    /// Decodes an image into a new headless pixel buffer.
+    ///
+    /// If `format` is `None`, it's detected from the image's data, which works for everything but
+    /// TGA.
+    pub fn decode_image(
+        data: &[u8],
+        format: Option<ImageFormat>,
+    ) -> Result<PixelBufferTyped<P>, ImageError> {
+        let format = format
+            .or_else(|| ImageFormat::detect(data))
+            .ok_or(ImageError::UnrecognizedFormat)?;
+        let image = decode(data, format)?;
+        let mut pb = PixelBufferTyped::new_headless(image.width, image.height);
+        let src_rows = image.pixels.chunks_exact(image.width as usize);
+        for (src, dst) in src_rows.zip(pb.rows_mut()) {
+            convert_row(src, dst);
+        }
+        Ok(pb)
+    }
+
+    /// Loads an image file into a new headless pixel buffer.
+    ///
+    /// The format is detected from the file's data, or from its extension if that fails.
+    pub fn open_image(path: impl AsRef<Path>) -> Result<PixelBufferTyped<P>, ImageError> {
+        let data = fs::read(path.as_ref())?;
+        let format = ImageFormat::detect(&data).or_else(|| ImageFormat::from_path(path));
+        PixelBufferTyped::decode_image(&data, format)
+    }
+
+    /// Encodes the pixel buffer's contents as a PNG or PPM image.
+    ///
+    /// To encode part of the pixel buffer, encode a [`view`](Self::view) of it instead.
+    pub fn encode_image(&self, writer: impl Write, format: ImageFormat) -> Result<(), ImageError> {
+        self.view(Rect::new(0, 0, self.width(), self.height()))
+            .encode_image(writer, format)
+    }
+
+    /// Saves the pixel buffer's contents as a PNG or PPM file, depending on the path's extension.
+    ///
+    /// Files with a `.pgm` extension are saved as grayscale. To save part of the pixel buffer, save a [`view`](Self::view) of it instead.
+    pub fn save_image(&self, path: impl AsRef<Path>) -> Result<(), ImageError> {
+        self.view(Rect::new(0, 0, self.width(), self.height()))
+            .save_image(path)
+    }
+}
+
+impl<P: PixelBufferFormat> PixelView<'_, P> {
+    /// Encodes the view's pixels as a PNG or PPM image.
+    ///
+    /// PNGs keep the alpha channel of formats that have one, and are grayscale for grayscale
+    /// formats. PPMs are always RGB.
+    pub fn encode_image(&self, writer: impl Write, format: ImageFormat) -> Result<(), ImageError> {
+        check_encodable(format)?;
+        match format {
+            ImageFormat::Png => self.encode_png(writer),
+            _ => self.encode_ppm(writer),
+        }
+    }
+
+    /// Saves the view's pixels as a PNG or PPM file, depending on the path's extension.
+    ///
+    /// Files with a `.pgm` extension are saved as grayscale.
+    pub fn save_image(&self, path: impl AsRef<Path>) -> Result<(), ImageError> {
+        let path = path.as_ref();
+        let format = ImageFormat::from_path(path).ok_or(ImageError::UnrecognizedFormat)?;
+        // Check before creating the file, so nothing is left behind.
+        check_encodable(format)?;
+        let graymap = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("pgm"));
+        let mut writer = BufWriter::new(fs::File::create(path)?);
+        if graymap {
+            self.encode_pgm(&mut writer)?;
+        } else {
+            self.encode_image(&mut writer, format)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn encode_png(&self, writer: impl Write) -> Result<(), ImageError> {
+        let mut encoder = png::Encoder::new(writer, self.width(), self.height());
+        let data = match P::FORMAT_TYPE {
+            PixelBufferFormatType::BGRA
+            | PixelBufferFormatType::RGBA
+            | PixelBufferFormatType::ARGB2101010 => {
+                encoder.set_color(png::ColorType::Rgba);
+                self.to_bytes::<RGBA>()
+            }
+            PixelBufferFormatType::Gray8 | PixelBufferFormatType::Gray16 => {
+                encoder.set_color(png::ColorType::Grayscale);
+                self.to_bytes::<Gray8>()
+            }
+            _ => {
+                encoder.set_color(png::ColorType::Rgb);
+                self.to_bytes::<RGB>()
+            }
+        };
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&data)?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    fn encode_ppm(&self, mut writer: impl Write) -> Result<(), ImageError> {
+        write!(writer, "P6\n{} {}\n255\n", self.width(), self.height())?;
+        writer.write_all(&self.to_bytes::<RGB>())?;
+        Ok(())
+    }
+
+    fn encode_pgm(&self, mut writer: impl Write) -> Result<(), ImageError> {
+        write!(writer, "P5\n{} {}\n255\n", self.width(), self.height())?;
+        writer.write_all(&self.to_bytes::<Gray8>())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util::pixels, Indexed8, BGRA};
+
+    fn decode_rgba(data: &[u8], format: Option<ImageFormat>) -> Vec<RGBA> {
+        pixels(&PixelBufferTyped::<RGBA>::decode_image(data, format).unwrap())
+    }
+
+    fn bmp(header: &[u32], bits: u16, extra: &[u8], rows: &[u8]) -> Vec<u8> {
+        let pixels_start = 14 + 40 + extra.len() as u32;
+        let mut data = b"BM".to_vec();
+        for value in [pixels_start + rows.len() as u32, 0, pixels_start, 40] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(&header[0].to_le_bytes());
+        data.extend_from_slice(&header[1].to_le_bytes());
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&bits.to_le_bytes());
+        for value in [header[2], 0, 0, 0, header[3], 0] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(extra);
+        data.extend_from_slice(rows);
+        data
+    }
+
+    #[test]
+    fn round_trips_png() {
+        let mut pb = PixelBufferTyped::new_headless(3, 2);
+        for (x, y, pixel) in pb.enumerate_pixels_mut() {
+            *pixel = BGRA::new(x as u8 * 50, y as u8 * 100, 7, 255 - x as u8 * 60);
+        }
+        let mut png = Vec::new();
+        pb.encode_image(&mut png, ImageFormat::Png).unwrap();
+        assert_eq!(ImageFormat::detect(&png), Some(ImageFormat::Png));
+        let decoded = PixelBufferTyped::<BGRA>::decode_image(&png, None).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (3, 2));
+        assert_eq!(pixels(&decoded), pixels(&pb));
+
+        // Views save just their part of the buffer.
+        let mut gray = PixelBufferTyped::new_headless(4, 4);
+        for (x, y, pixel) in gray.enumerate_pixels_mut() {
+            *pixel = Gray8((x * 10 + y) as u8);
+        }
+        let mut png = Vec::new();
+        gray.view(Rect::new(1, 2, 3, 2))
+            .encode_image(&mut png, ImageFormat::Png)
+            .unwrap();
+        let decoded = PixelBufferTyped::<Gray8>::decode_image(&png, None).unwrap();
+        assert_eq!(
+            pixels(&decoded),
+            [12, 22, 32, 13, 23, 33].map(Gray8).to_vec()
+        );
+    }
+
+    #[test]
+    fn png_with_huge_header() {
+        fn crc32(data: &[u8]) -> u32 {
+            let mut crc = !0u32;
+            for &byte in data {
+                crc ^= u32::from(byte);
+                for _ in 0..8 {
+                    crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+                }
+            }
+            !crc
+        }
+
+        // A single pixel, with an IHDR chunk claiming it's 60000x60000.
+        let mut png = Vec::new();
+        PixelBufferTyped::<RGBA>::new_headless(1, 1)
+            .encode_image(&mut png, ImageFormat::Png)
+            .unwrap();
+        png[16..20].copy_from_slice(&60000u32.to_be_bytes());
+        png[20..24].copy_from_slice(&60000u32.to_be_bytes());
+        let crc = crc32(&png[12..29]);
+        png[29..33].copy_from_slice(&crc.to_be_bytes());
+        assert!(matches!(
+            PixelBufferTyped::<RGBA>::decode_image(&png, None),
+            Err(ImageError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn ppm() {
+        let mut pb = PixelBufferTyped::new_headless(2, 1);
+        pb[(0, 0)] = RGB::new(1, 2, 3);
+        pb[(1, 0)] = RGB::new(4, 5, 6);
+        let mut ppm = Vec::new();
+        pb.encode_image(&mut ppm, ImageFormat::Ppm).unwrap();
+        assert_eq!(ppm, b"P6\n2 1\n255\n\x01\x02\x03\x04\x05\x06");
+        assert_eq!(
+            pixels(&PixelBufferTyped::<RGB>::decode_image(&ppm, None).unwrap()),
+            pixels(&pb)
+        );
+
+        let text = b"P3 # a comment\n2 1\n# another\n15\n15 0 0  0 15 5\n";
+        assert_eq!(
+            decode_rgba(text, None),
+            [RGBA::new(255, 0, 0, 255), RGBA::new(0, 255, 85, 255)]
+        );
+        let wide = b"P5 1 1 65535\n\x80\x00";
+        assert_eq!(decode_rgba(wide, None), [RGBA::new(128, 128, 128, 255)]);
+        assert!(matches!(
+            PixelBufferTyped::<RGB>::decode_image(b"P6 2 1 255\n\x01\x02\x03", None),
+            Err(ImageError::Malformed(_))
+        ));
+        assert!(matches!(
+            PixelBufferTyped::<RGB>::decode_image(b"P6 100000 100000 255\n", None),
+            Err(ImageError::Malformed(_))
+        ));
+
+        // Indices are saved as the colors they stand for in the pixel buffer's own palette.
+        let mut pb = PixelBufferTyped::new_headless(2, 1);
+        pb.palette_mut().unwrap()[1] = BGRA::new(0, 0, 255, 255);
+        pb[(1, 0)] = Indexed8(1);
+        let mut ppm = Vec::new();
+        pb.view(Rect::new(1, 0, 1, 1))
+            .encode_image(&mut ppm, ImageFormat::Ppm)
+            .unwrap();
+        assert_eq!(ppm, b"P6\n1 1\n255\n\xff\x00\x00");
+
+        // Graymaps are saved as graymaps.
+        let mut pb = PixelBufferTyped::new_headless(2, 1);
+        pb[(0, 0)] = Gray8(10);
+        pb[(1, 0)] = Gray8(200);
+        let path = std::env::temp_dir().join(format!("winit-blit-{}.pgm", std::process::id()));
+        pb.save_image(&path).unwrap();
+        let pgm = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(pgm, b"P5\n2 1\n255\n\x0a\xc8");
+    }
+
+    #[test]
+    fn bmp_rows() {
+        // 24-bit, bottom row first, with rows padded to 4 bytes.
+        let rows = [3, 2, 1, 6, 5, 4, 0, 0, 9, 8, 7, 12, 11, 10, 0, 0];
+        let data = bmp(&[2, 2, 0, 0], 24, &[], &rows);
+        assert_eq!(
+            decode_rgba(&data, None),
+            [
+                RGBA::new(7, 8, 9, 255),
+                RGBA::new(10, 11, 12, 255),
+                RGBA::new(1, 2, 3, 255),
+                RGBA::new(4, 5, 6, 255),
+            ]
+        );
+
+        // 4-bit palette, top row first.
+        let palette = [0, 0, 0, 0, 255, 0, 0, 0, 0, 0, 255, 0];
+        let data = bmp(&[3, -1i32 as u32, 0, 3], 4, &palette, &[0x12, 0x00, 0, 0]);
+        let (blue, red) = (RGBA::new(0, 0, 255, 255), RGBA::new(255, 0, 0, 255));
+        assert_eq!(
+            decode_rgba(&data, None),
+            [blue, red, RGBA::new(0, 0, 0, 255)]
+        );
+        let bad_index = bmp(&[1, 1, 0, 3], 4, &palette, &[0xF0, 0, 0, 0]);
+        assert!(PixelBufferTyped::<RGBA>::decode_image(&bad_index, None).is_err());
+        let no_bits = bmp(&[1, 1, 0, 0], 0, &[], &[0, 0, 0, 0]);
+        assert!(matches!(
+            PixelBufferTyped::<RGBA>::decode_image(&no_bits, None),
+            Err(ImageError::Unsupported(_))
+        ));
+
+        // 16-bit 5-6-5, with bit fields.
+        let masks = [0xF800u32, 0x07E0, 0x001F].map(u32::to_le_bytes).concat();
+        let data = bmp(&[1, 1, 3, 0], 16, &masks, &[0x1F, 0xF8, 0, 0]);
+        assert_eq!(decode_rgba(&data, None), [RGBA::new(255, 0, 255, 255)]);
+        // Channels without a mask are black.
+        let masks = [0xF800u32, 0, 0x001F].map(u32::to_le_bytes).concat();
+        let no_green = bmp(&[1, 1, 3, 0], 16, &masks, &[0xFF, 0xFF, 0, 0]);
+        assert_eq!(decode_rgba(&no_green, None), [RGBA::new(255, 0, 255, 255)]);
+
+        let truncated = &data[..data.len() - 1];
+        assert!(matches!(
+            PixelBufferTyped::<RGBA>::decode_image(truncated, None),
+            Err(ImageError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn tga() {
+        // Run-length encoded 32-bit, bottom row first, with an 8-bit alpha channel.
+        let mut data = vec![0, 0, 10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3, 0, 2, 0, 32, 8];
+        data.extend_from_slice(&[0x82, 1, 2, 3, 4]);
+        data.extend_from_slice(&[0x02, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16]);
+        assert_eq!(ImageFormat::detect(&data), None);
+        let (a, b, c) = (
+            RGBA::new(3, 2, 1, 4),
+            RGBA::new(11, 10, 9, 12),
+            RGBA::new(15, 14, 13, 16),
+        );
+        assert_eq!(
+            decode_rgba(&data, Some(ImageFormat::Tga)),
+            [RGBA::new(7, 6, 5, 8), b, c, a, a, a]
+        );
+
+        // Uncompressed grayscale, top row first and right to left.
+        let data = [
+            0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 1, 0, 8, 0x30, 10, 20,
+        ];
+        assert_eq!(
+            decode_rgba(&data, Some(ImageFormat::Tga)),
+            [RGBA::new(20, 20, 20, 255), RGBA::new(10, 10, 10, 255)]
+        );
+        assert!(matches!(
+            PixelBufferTyped::<RGBA>::decode_image(&data, None),
+            Err(ImageError::UnrecognizedFormat)
+        ));
+    }
+
+    #[test]
+    fn saves_files() {
+        let path = std::env::temp_dir().join(format!("winit-blit-{}.png", std::process::id()));
+        let mut pb = PixelBufferTyped::new_headless(2, 2);
+        pb.fill(RGBA::new(1, 2, 3, 4));
+        pb.save_image(&path).unwrap();
+        let loaded = PixelBufferTyped::<RGBA>::open_image(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(pixels(&loaded.unwrap()), pixels(&pb));
+        assert!(matches!(
+            pb.save_image("image.tga"),
+            Err(ImageError::Unsupported(_))
+        ));
+    }
+}
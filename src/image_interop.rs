@@ -0,0 +1,300 @@
+//! Conversions to and from the [`image`] crate's images.
+//!
+//! Images with 8 bits per channel are copied into pixel buffers of any format, converting as they
+//! go, and [`DynamicImage`]s with deeper channels go through [`DynamicImage::to_rgba8`] first.
+//! Pixel buffers and [views](PixelView) are copied back out as a `DynamicImage` in whichever of
+//! its layouts fits their format.
+//!
+//! Pixel buffers in an [`ImagePixel`] format can also be borrowed as an [`ImageBuffer`] without
+//! copying, as long as the backend stores their rows top-to-bottom with no padding. See
+//! [`PixelBuffer::contiguous_bytes`](crate::PixelBuffer::contiguous_bytes).
+
+use std::ops::Deref;
+
+use image::{DynamicImage, GrayImage, ImageBuffer, Luma, Pixel, Rgb, RgbImage, Rgba, RgbaImage};
+
+use crate::{
+    convert::{convert_raw_row, convert_row},
+    Gray16, Gray8, PixelBufferFormat, PixelBufferFormatType, PixelBufferTyped, PixelView, Rect,
+    RGB, RGBA,
+};
+
+/// A pixel format laid out exactly like one of the `image` crate's pixel types.
+pub trait ImagePixel: PixelBufferFormat {
+    /// The `image` pixel type with the same channels in the same order.
+    type Pixel: Pixel<Subpixel = u8>;
+}
+
+impl ImagePixel for RGBA {
+    type Pixel = Rgba<u8>;
+}
+impl ImagePixel for RGB {
+    type Pixel = Rgb<u8>;
+}
+impl ImagePixel for Gray8 {
+    type Pixel = Luma<u8>;
+}
+
+/// The pixel buffer format laid out like `Px`, if there is one.
+fn format_of<Px: Pixel<Subpixel = u8>>() -> Option<PixelBufferFormatType> {
+    match (Px::CHANNEL_COUNT, Px::COLOR_MODEL) {
+        (4, "RGBA") => Some(PixelBufferFormatType::RGBA),
+        (3, "RGB") => Some(PixelBufferFormatType::RGB),
+        (1, "Y") => Some(PixelBufferFormatType::Gray8),
+        _ => None,
+    }
+}
+
+/// Converts `src`, a row of `Px` pixels' channels, into `dst`, stopping at the end of whichever
+/// row is shorter.
+fn convert_image_row<Px: Pixel<Subpixel = u8>, P: PixelBufferFormat>(src: &[u8], dst: &mut [P]) {
+    match format_of::<Px>() {
+        Some(format) => convert_raw_row(src, format, P::to_raw_slice_mut(dst), P::FORMAT_TYPE),
+        None => {
+            let src: Vec<RGBA> = src
+                .chunks_exact(Px::CHANNEL_COUNT as usize)
+                .take(dst.len())
+                .map(|channels| {
+                    let [r, g, b, a] = Px::from_slice(channels).to_rgba().0;
+                    RGBA::new(r, g, b, a)
+                })
+                .collect();
+            convert_row(&src, dst);
+        }
+    }
+}
+
+impl<P: PixelBufferFormat> PixelBufferTyped<P> {
+    /// Copies `image` into the pixel buffer with its top-left corner at `pos`, clipped to the
+    /// pixel buffer.
+    ///
+    /// The area copied to is marked as damaged.
+    pub fn copy_from_image<Px, C>(&mut self, image: &ImageBuffer<Px, C>, pos: (u32, u32))
+    where
+        Px: Pixel<Subpixel = u8>,
+        C: Deref<Target = [u8]>,
+    {
+        let rect = Rect::new(pos.0, pos.1, image.width(), image.height());
+        let mut view = self.view_mut(rect);
+        if view.width() == 0 {
+            return;
+        }
+        // The container may be longer than the image, but never shorter.
+        let row_len = image.width() as usize * Px::CHANNEL_COUNT as usize;
+        for (src, dst) in image.as_raw().chunks_exact(row_len).zip(view.rows_mut()) {
+            convert_image_row::<Px, P>(src, dst);
+        }
+    }
+
+    /// Copies `image` into the pixel buffer with its top-left corner at `pos`, clipped to the
+    /// pixel buffer.
+    ///
+    /// Images with more than 8 bits per channel are reduced to 8-bit RGBA first. The area copied
+    /// to is marked as damaged.
+    pub fn copy_from_dynamic_image(&mut self, image: &DynamicImage, pos: (u32, u32)) {
+        match image {
+            DynamicImage::ImageLuma8(image) => self.copy_from_image(image, pos),
+            DynamicImage::ImageLumaA8(image) => self.copy_from_image(image, pos),
+            DynamicImage::ImageRgb8(image) => self.copy_from_image(image, pos),
+            DynamicImage::ImageRgba8(image) => self.copy_from_image(image, pos),
+            image => self.copy_from_image(&image.to_rgba8(), pos),
+        }
+    }
+
+    /// Borrows the pixel buffer as an [`ImageBuffer`] without copying it.
+    ///
+    /// Returns `None` if the backend doesn't store the rows top-to-bottom with no padding, in
+    /// which case [`to_image`](Self::to_image) makes a copy instead.
+    ///
+    /// ```
+    /// use image::Rgba;
+    /// use winit_blit::{PixelBufferTyped, RGBA};
+    ///
+    /// let mut pb = PixelBufferTyped::<RGBA>::new_headless(4, 4);
+    /// pb[(1, 2)] = RGBA::new(1, 2, 3, 4);
+    /// let image = pb.as_image_buffer().unwrap();
+    /// assert_eq!(image.get_pixel(1, 2), &Rgba([1, 2, 3, 4]));
+    /// ```
+    pub fn as_image_buffer(&self) -> Option<ImageBuffer<P::Pixel, &[u8]>>
+    where
+        P: ImagePixel,
+    {
+        ImageBuffer::from_raw(self.width(), self.height(), self.p.contiguous_bytes()?)
+    }
+
+    /// Mutably borrows the pixel buffer as an [`ImageBuffer`] without copying it.
+    ///
+    /// Returns `None` if the backend doesn't store the rows top-to-bottom with no padding.
+    /// Otherwise, the whole pixel buffer is marked as damaged.
+    pub fn as_image_buffer_mut(&mut self) -> Option<ImageBuffer<P::Pixel, &mut [u8]>>
+    where
+        P: ImagePixel,
+    {
+        let (width, height) = (self.width(), self.height());
+        ImageBuffer::from_raw(width, height, self.p.contiguous_bytes_mut()?)
+    }
+
+    /// Copies the pixel buffer's contents into a new [`DynamicImage`].
+    ///
+    /// See [`PixelView::to_image`] for which layout the image has.
+    pub fn to_image(&self) -> DynamicImage {
+        self.view(Rect::new(0, 0, self.width(), self.height()))
+            .to_image()
+    }
+}
+
+impl<P: PixelBufferFormat> PixelView<'_, P> {
+    /// Copies the view's pixels into a new [`DynamicImage`].
+    ///
+    /// The image is RGBA for formats with an alpha channel, 8- or 16-bit grayscale for grayscale
+    /// formats, and RGB otherwise. [`Indexed8`](crate::Indexed8) pixels are looked up in the
+    /// view's [`palette`](PixelView::palette), and keep its alpha.
+    pub fn to_image(&self) -> DynamicImage {
+        let (width, height) = (self.width(), self.height());
+        // `to_bytes` always returns exactly enough bytes for the view.
+        match P::FORMAT_TYPE {
+            PixelBufferFormatType::BGRA
+            | PixelBufferFormatType::RGBA
+            | PixelBufferFormatType::ARGB2101010
+            | PixelBufferFormatType::Indexed8 => DynamicImage::ImageRgba8(
+                RgbaImage::from_raw(width, height, self.to_bytes::<RGBA>()).unwrap(),
+            ),
+            PixelBufferFormatType::Gray8 => DynamicImage::ImageLuma8(
+                GrayImage::from_raw(width, height, self.to_bytes::<Gray8>()).unwrap(),
+            ),
+            PixelBufferFormatType::Gray16 => {
+                let pixels = self
+                    .to_bytes::<Gray16>()
+                    .chunks_exact(2)
+                    .map(|bytes| u16::from_ne_bytes([bytes[0], bytes[1]]))
+                    .collect();
+                DynamicImage::ImageLuma16(ImageBuffer::from_raw(width, height, pixels).unwrap())
+            }
+            _ => DynamicImage::ImageRgb8(
+                RgbImage::from_raw(width, height, self.to_bytes::<RGB>()).unwrap(),
+            ),
+        }
+    }
+}
+
+impl<P, Px, C> From<&ImageBuffer<Px, C>> for PixelBufferTyped<P>
+where
+    P: PixelBufferFormat,
+    Px: Pixel<Subpixel = u8>,
+    C: Deref<Target = [u8]>,
+{
+    /// Copies `image` into a new headless pixel buffer.
+    fn from(image: &ImageBuffer<Px, C>) -> PixelBufferTyped<P> {
+        let mut pb = PixelBufferTyped::new_headless(image.width(), image.height());
+        pb.copy_from_image(image, (0, 0));
+        pb
+    }
+}
+
+impl<P: PixelBufferFormat> From<&DynamicImage> for PixelBufferTyped<P> {
+    /// Copies `image` into a new headless pixel buffer.
+    fn from(image: &DynamicImage) -> PixelBufferTyped<P> {
+        let mut pb = PixelBufferTyped::new_headless(image.width(), image.height());
+        pb.copy_from_dynamic_image(image, (0, 0));
+        pb
+    }
+}
+
+impl<P: PixelBufferFormat> From<&PixelBufferTyped<P>> for DynamicImage {
+    fn from(pb: &PixelBufferTyped<P>) -> DynamicImage {
+        pb.to_image()
+    }
+}
+
+impl<P: PixelBufferFormat> From<&PixelView<'_, P>> for DynamicImage {
+    fn from(view: &PixelView<'_, P>) -> DynamicImage {
+        view.to_image()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayAlphaImage, LumaA, Rgb32FImage};
+
+    use super::*;
+    use crate::{test_util::pixels, Indexed8, BGRA, RGB565};
+
+    #[test]
+    fn copies_images_in() {
+        let image = RgbaImage::from_fn(3, 2, |x, y| Rgba([x as u8, y as u8, 7, 200]));
+        let pb = PixelBufferTyped::<BGRA>::from(&image);
+        assert_eq!((pb.width(), pb.height()), (3, 2));
+        assert_eq!(pb[(2, 1)], BGRA::new(7, 1, 2, 200));
+
+        // Layouts without a matching format go through RGBA.
+        let image = GrayAlphaImage::from_pixel(2, 2, LumaA([90, 10]));
+        let pb = PixelBufferTyped::<RGBA>::from(&DynamicImage::ImageLumaA8(image));
+        assert_eq!(pixels(&pb), [RGBA::new(90, 90, 90, 10); 4]);
+
+        let image = Rgb32FImage::from_pixel(1, 1, Rgb([1.0, 0.0, 0.5]));
+        let pb = PixelBufferTyped::<RGB>::from(&DynamicImage::ImageRgb32F(image));
+        assert_eq!(pb[(0, 0)], RGB::new(255, 0, 128));
+
+        // Copies are clipped to the pixel buffer, and damage what they cover.
+        let mut pb = PixelBufferTyped::<Gray8>::new_headless(4, 4);
+        pb.clear_damage();
+        pb.copy_from_image(&GrayImage::from_pixel(3, 3, Luma([50])), (2, 3));
+        assert_eq!(pb[(3, 3)], Gray8(50));
+        assert_eq!(pb[(1, 3)], Gray8(255));
+        assert_eq!(pb.damage().rects(), &[Rect::new(2, 3, 2, 1)]);
+        pb.copy_from_image(&GrayImage::new(3, 3), (9, 9));
+        pb.copy_from_image(&GrayImage::new(0, 3), (0, 0));
+        pb.copy_from_image(&GrayImage::new(3, 3), (u32::MAX - 1, u32::MAX));
+    }
+
+    #[test]
+    fn copies_images_out() {
+        let mut pb = PixelBufferTyped::<BGRA>::new_headless(3, 2);
+        pb[(1, 1)] = BGRA::new(1, 2, 3, 4);
+        let image = DynamicImage::from(&pb);
+        assert_eq!(
+            image.as_rgba8().unwrap().get_pixel(1, 1),
+            &Rgba([3, 2, 1, 4])
+        );
+
+        let view = pb.view(Rect::new(1, 1, 5, 5));
+        let image = view.to_image().into_rgba8();
+        assert_eq!(image.dimensions(), (2, 1));
+        assert_eq!(image.get_pixel(0, 0), &Rgba([3, 2, 1, 4]));
+
+        let mut pb = PixelBufferTyped::<Gray16>::new_headless(2, 1);
+        pb[(1, 0)] = Gray16(1000);
+        let image = pb.to_image();
+        assert_eq!(image.as_luma16().unwrap().as_raw(), &[65535, 1000]);
+
+        let pb = PixelBufferTyped::<RGB565>::new_headless(2, 2);
+        assert!(pb.to_image().as_rgb8().is_some());
+
+        let mut pb = PixelBufferTyped::<Indexed8>::new_headless(2, 1);
+        pb.palette_mut().unwrap()[7] = BGRA::new(30, 20, 10, 128);
+        pb[(1, 0)] = Indexed8(7);
+        let image = DynamicImage::from(&pb.view(Rect::new(0, 0, 2, 1)));
+        assert_eq!(
+            image.as_rgba8().unwrap().as_raw(),
+            &[255, 255, 255, 255, 10, 20, 30, 128]
+        );
+    }
+
+    #[test]
+    fn borrows_without_copying() {
+        let mut pb = PixelBufferTyped::<RGB>::new_headless(3, 2);
+        pb.clear_damage();
+        {
+            let mut image = pb.as_image_buffer_mut().unwrap();
+            image.put_pixel(2, 1, Rgb([4, 5, 6]));
+        }
+        assert_eq!(pb[(2, 1)], RGB::new(4, 5, 6));
+        assert_eq!(pb.damage().rects(), &[Rect::new(0, 0, 3, 2)]);
+        let image = pb.as_image_buffer().unwrap();
+        assert_eq!(image.dimensions(), (3, 2));
+        assert_eq!(image.get_pixel(2, 1), &Rgb([4, 5, 6]));
+
+        let pb = PixelBufferTyped::<Gray8>::new_headless(0, 0);
+        assert_eq!(pb.as_image_buffer().unwrap().dimensions(), (0, 0));
+    }
+}
@@ -1,25 +1,99 @@
+pub mod color;
+pub mod composite;
+pub mod convert;
+pub mod draw;
+pub mod font;
+mod hdr;
+#[cfg(feature = "images")]
+pub mod image_file;
+#[cfg(feature = "image")]
+pub mod image_interop;
 mod platform_impl;
+mod region;
+mod swapchain;
+#[cfg(test)]
+mod test_util;
+#[cfg(feature = "rayon")]
+mod tile;
+mod view;
 use std::{
     borrow::{Borrow, BorrowMut},
-    fmt::Debug,
+    cell::Ref,
+    error::Error,
+    fmt::{self, Debug},
     io,
     marker::PhantomData,
+    ops::{Index, IndexMut},
 };
 
-use raw_window_handle::{HasDisplayHandle, HasWindowHandle};
+use raw_window_handle::{HandleError, HasDisplayHandle, HasWindowHandle};
 #[cfg(feature = "rayon")]
 use rayon::prelude::*;
 
+use composite::Blend;
+pub use hdr::{HdrChannel, HdrPixelBuffer, ToneMap};
+pub use platform_impl::{HeadlessSurface, HeadlessWindow};
+pub use region::{Rect, Region};
+pub use swapchain::Swapchain;
+#[cfg(feature = "rayon")]
+pub use tile::TileMut;
+pub use view::{PixelView, PixelViewMut};
+
+/// An error that can occur while creating a pixel buffer.
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum PixelBufferCreationError {
+    /// The platform doesn't support the requested pixel buffer format.
     FormatNotSupported,
+    /// A system library needed to talk to the display server couldn't be loaded.
+    LibraryNotFound(String),
+    /// The window or display handle is of a kind this platform can't blit to.
+    HandleNotSupported,
+    /// No canvas in the document matches the window's handle.
+    CanvasNotFound,
+    /// The platform failed to allocate a resource needed by the pixel buffer.
+    AllocationFailed(String),
+    /// The window or display handle couldn't be retrieved.
+    Handle(HandleError),
+}
+
+impl fmt::Display for PixelBufferCreationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::FormatNotSupported => write!(f, "the pixel buffer format isn't supported"),
+            Self::LibraryNotFound(e) => write!(f, "failed to load system library: {}", e),
+            Self::HandleNotSupported => write!(f, "the window or display handle isn't supported"),
+            Self::CanvasNotFound => write!(f, "couldn't find the canvas for the window handle"),
+            Self::AllocationFailed(e) => write!(f, "failed to allocate pixel buffer: {}", e),
+            Self::Handle(e) => write!(f, "failed to get window or display handle: {}", e),
+        }
+    }
+}
+
+impl Error for PixelBufferCreationError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Handle(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<HandleError> for PixelBufferCreationError {
+    fn from(e: HandleError) -> Self {
+        Self::Handle(e)
+    }
 }
 
 /// A buffer of pixels that can be blitted onto a window.
 ///
 /// The pixel buffer's origin is in the top-left corner of the image.
+///
+/// The pixel buffer keeps track of which parts of it have been written to since they were last
+/// presented with [`blit_damage`](Self::blit_damage).
 pub struct PixelBuffer {
     p: platform_impl::PixelBuffer,
+    damage: Region,
 }
 
 /// A buffer of pixels with a statically-checked pixel format.
@@ -33,12 +107,28 @@ pub struct PixelBufferTyped<P: PixelBufferFormat> {
 impl PixelBufferFormatType {
     /// The native pixel buffer format for the current plaform.
     pub const NATIVE: PixelBufferFormatType = NativeFormat::FORMAT_TYPE;
+
+    /// The total number of bytes in an individual pixel of this format.
+    pub const fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelBufferFormatType::Gray8 | PixelBufferFormatType::Indexed8 => 1,
+            PixelBufferFormatType::RGB565
+            | PixelBufferFormatType::RGB555
+            | PixelBufferFormatType::Gray16 => 2,
+            PixelBufferFormatType::BGR | PixelBufferFormatType::RGB => 3,
+            PixelBufferFormatType::BGRA
+            | PixelBufferFormatType::RGBA
+            | PixelBufferFormatType::ARGB2101010 => 4,
+        }
+    }
 }
 
 impl PixelBuffer {
     /// Initialize a new pixel buffer.
     ///
-    /// Can return `Err` if the platform doesn't support the requested pixel buffer type.
+    /// Any format can be used, but ones the platform can't blit directly are converted to
+    /// [`NativeFormat`] over the blitted area whenever the pixel buffer is blitted. Use
+    /// [`NativeFormat`] or one of the [`supported_formats`](Self::supported_formats) to avoid that.
     pub fn new<H: HasWindowHandle, D: HasDisplayHandle>(
         width: u32,
         height: u32,
@@ -51,35 +141,64 @@ impl PixelBuffer {
                 width,
                 height,
                 format,
-                window.window_handle().expect("failed to get window handle"),
-                display
-                    .display_handle()
-                    .expect("failed to get display handle"),
+                window.window_handle()?,
+                display.display_handle()?,
             )
-            .map(|p| PixelBuffer { p })
+            .map(|p| PixelBuffer {
+                p,
+                damage: Region::new(),
+            })
         }
     }
 
-    /// Blits the pixel buffer's contents onto `window`.
+    /// The pixel buffer formats that can be blitted onto `window` without being converted to
+    /// [`NativeFormat`] first, cheapest to blit first.
     ///
-    /// # Panics
-    /// The `window` passed to this function must be the same `window` passed to `new`. Failing to
-    /// do so will result in a panic.
-    pub fn blit<H: HasWindowHandle>(&self, window: &H) -> io::Result<()> {
+    /// On X11 this depends on the window's visual. Formats laid out exactly like the visual are
+    /// blitted as-is, and [`BGRA`] is supported on any other TrueColor visual (such as 16-bit and
+    /// 30-bit ones) by converting it on every blit. Palette-based visuals support no formats.
+    pub fn supported_formats<H: HasWindowHandle, D: HasDisplayHandle>(
+        window: &H,
+        display: &D,
+    ) -> Result<Vec<PixelBufferFormatType>, PixelBufferCreationError> {
         unsafe {
-            self.p.blit(
-                window
-                    .window_handle()
-                    .expect("failed to get raw window handle"),
+            platform_impl::PixelBuffer::supported_formats(
+                window.window_handle()?,
+                display.display_handle()?,
             )
         }
     }
 
+    /// Initialize a new pixel buffer that isn't attached to a window.
+    ///
+    /// Blitting a headless pixel buffer copies its contents into an in-memory
+    /// [`HeadlessSurface`], which can be read back with
+    /// [`headless_surface`](Self::headless_surface). Any window can be passed to the blitting
+    /// functions, including [`HeadlessWindow`]. All pixel buffer formats are supported.
+    ///
+    /// Like a window's pixel buffer, both the buffer and its surface start out with every byte set
+    /// to 255.
+    pub fn new_headless(width: u32, height: u32, format: PixelBufferFormatType) -> PixelBuffer {
+        PixelBuffer {
+            p: platform_impl::PixelBuffer::new_headless(width, height, format),
+            damage: Region::new(),
+        }
+    }
+
+    /// Blits the pixel buffer's contents onto `window`.
+    ///
+    /// # Errors
+    /// Returns an error if the platform fails to blit. The `window` passed to this function must be
+    /// the same `window` passed to `new`, or an error may be returned.
+    pub fn blit<H: HasWindowHandle>(&self, window: &H) -> io::Result<()> {
+        unsafe { self.p.blit(window) }
+    }
+
     /// Blits a subsection of the pixel buffer's contents onto `window`.
     ///
-    /// # Panics
-    /// The `window` passed to this function must be the same `window` passed to `new`. Failing to
-    /// do so will result in a panic.
+    /// # Errors
+    /// Returns an error if the platform fails to blit. The `window` passed to this function must be
+    /// the same `window` passed to `new`, or an error may be returned.
     pub fn blit_rect<H: HasWindowHandle>(
         &self,
         src_pos: (u32, u32),
@@ -87,16 +206,89 @@ impl PixelBuffer {
         blit_size: (u32, u32),
         window: &H,
     ) -> io::Result<()> {
-        unsafe {
-            self.p.blit_rect(
-                src_pos,
-                dst_pos,
-                blit_size,
-                window
-                    .window_handle()
-                    .expect("failed to get raw window handle"),
-            )
+        unsafe { self.p.blit_rect(src_pos, dst_pos, blit_size, window) }
+    }
+
+    /// Fills `rect` with what's currently shown in the same part of `window`.
+    ///
+    /// The window's pixels are converted to the pixel buffer's format. Parts of `rect` that lie
+    /// outside the pixel buffer or the window are left alone.
+    ///
+    /// Not every platform can read back a window's contents; Wayland returns an error of kind
+    /// [`Unsupported`](io::ErrorKind::Unsupported). On X11 the window must be mapped, and parts
+    /// of it that are covered by other windows may not be captured correctly.
+    ///
+    /// # Errors
+    /// Returns an error if the window's contents can't be read. The `window` passed to this
+    /// function must be the same `window` passed to `new`, or an error may be returned.
+    pub fn capture_from<H: HasWindowHandle>(&mut self, window: &H, rect: Rect) -> io::Result<()> {
+        let rect = rect.intersection(&Rect::new(0, 0, self.width(), self.height()));
+        if rect.is_empty() {
+            return Ok(());
         }
+        unsafe { self.p.capture_from(rect, window) }
+    }
+
+    /// Changes the dimensions of the pixel buffer.
+    ///
+    /// The existing allocation and platform image are reused where possible, so it's cheap to
+    /// call this whenever the window is resized. The contents of the pixel buffer are unspecified
+    /// afterwards; use [`resize_preserving`](Self::resize_preserving) to keep them.
+    pub fn resize(&mut self, width: u32, height: u32) -> Result<(), PixelBufferCreationError> {
+        unsafe { self.p.resize(width, height, false)? };
+        self.damage_all();
+        Ok(())
+    }
+
+    /// Changes the dimensions of the pixel buffer, keeping the contents of the region that's in
+    /// both the old and new dimensions.
+    ///
+    /// Pixels outside that region are unspecified.
+    pub fn resize_preserving(
+        &mut self,
+        width: u32,
+        height: u32,
+    ) -> Result<(), PixelBufferCreationError> {
+        unsafe { self.p.resize(width, height, true)? };
+        self.damage_all();
+        Ok(())
+    }
+
+    /// The surface that a headless pixel buffer has been blitted onto.
+    ///
+    /// Returns `None` if the pixel buffer isn't headless.
+    pub fn headless_surface(&self) -> Option<Ref<'_, HeadlessSurface>> {
+        self.p.headless_surface()
+    }
+
+    /// The colors that the pixel buffer's [`Indexed8`] pixels index into.
+    ///
+    /// Indices are only looked up when the pixel buffer is blitted, and only over the blitted
+    /// area. Palettes start out as [`Indexed8::GRAYSCALE_PALETTE`].
+    ///
+    /// Returns `None` if the pixel buffer's format isn't [`Indexed8`](PixelBufferFormatType::Indexed8).
+    pub fn palette(&self) -> Option<&[BGRA; 256]> {
+        self.p.palette()
+    }
+
+    /// Mutably gets the colors that the pixel buffer's [`Indexed8`] pixels index into.
+    ///
+    /// Changing the palette changes the color of every pixel, so the whole pixel buffer is marked
+    /// as damaged. Palette cycling is just a matter of rotating part of the palette and blitting:
+    ///
+    /// ```
+    /// # use winit_blit::{HeadlessWindow, PixelBuffer, PixelBufferFormatType};
+    /// let mut pb = PixelBuffer::new_headless(320, 200, PixelBufferFormatType::Indexed8);
+    /// pb.palette_mut().unwrap()[16..32].rotate_left(1);
+    /// pb.blit_damage(&HeadlessWindow)?;
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    ///
+    /// Returns `None` if the pixel buffer's format isn't [`Indexed8`](PixelBufferFormatType::Indexed8).
+    pub fn palette_mut(&mut self) -> Option<&mut [BGRA; 256]> {
+        self.p.palette()?;
+        self.damage_all();
+        self.p.palette_mut()
     }
 
     /// The total number of bits in an individual pixel.
@@ -132,39 +324,145 @@ impl PixelBuffer {
     }
 
     /// Mutably gets the row at the particular height.
+    ///
+    /// The row is marked as damaged.
     pub fn row_mut(&mut self, row: u32) -> Option<&mut [u8]> {
+        if row < self.height() {
+            self.damage.add(Rect::new(0, row, self.width(), 1));
+        }
         self.p.row_mut(row)
     }
 
     /// Iterate through all rows in the pixel buffer.
-    pub fn rows<'a>(&'a self) -> impl ExactSizeIterator + DoubleEndedIterator<Item = &'a [u8]> {
+    pub fn rows(&self) -> impl ExactSizeIterator + DoubleEndedIterator<Item = &[u8]> {
         self.p.rows()
     }
 
     /// Mutably iterate through all rows in the pixel buffer.
-    pub fn rows_mut<'a>(
-        &'a mut self,
-    ) -> impl ExactSizeIterator + DoubleEndedIterator<Item = &'a mut [u8]> {
+    ///
+    /// The whole pixel buffer is marked as damaged.
+    pub fn rows_mut(&mut self) -> impl ExactSizeIterator + DoubleEndedIterator<Item = &mut [u8]> {
+        self.damage_all();
         self.p.rows_mut()
     }
 
+    /// Borrows all of the pixel buffer's bytes as one slice, if its rows are stored top-to-bottom
+    /// with no padding between them.
+    ///
+    /// That's the case for every backend but Windows' native one, which stores its rows
+    /// bottom-to-top and pads them to a multiple of 4 bytes.
+    pub fn contiguous_bytes(&self) -> Option<&[u8]> {
+        self.p.contiguous_bytes()
+    }
+
+    /// Mutably borrows all of the pixel buffer's bytes as one slice, if its rows are stored
+    /// top-to-bottom with no padding between them.
+    ///
+    /// The whole pixel buffer is marked as damaged if the slice is returned.
+    pub fn contiguous_bytes_mut(&mut self) -> Option<&mut [u8]> {
+        self.p.contiguous_bytes()?;
+        self.damage_all();
+        self.p.contiguous_bytes_mut()
+    }
+
     /// Iterate through all rows in the pixel buffer.
     #[cfg(feature = "rayon")]
-    pub fn par_rows<'a>(&'a self) -> impl IndexedParallelIterator<Item = &'a [u8]> {
+    pub fn par_rows(&self) -> impl IndexedParallelIterator<Item = &[u8]> {
         self.p.par_rows()
     }
 
     /// Mutably iterate through all rows in the pixel buffer.
+    ///
+    /// The whole pixel buffer is marked as damaged.
     #[cfg(feature = "rayon")]
-    pub fn par_rows_mut<'a>(&'a mut self) -> impl IndexedParallelIterator<Item = &'a mut [u8]> {
+    pub fn par_rows_mut(&mut self) -> impl IndexedParallelIterator<Item = &mut [u8]> {
+        self.damage_all();
         self.p.par_rows_mut()
     }
+
+    /// Mutably iterate through the pixel buffer in `tile_width`x`tile_height` tiles.
+    ///
+    /// Tiles along the right and bottom edges are smaller if the pixel buffer's dimensions aren't
+    /// a multiple of the tile size. The whole pixel buffer is marked as damaged.
+    ///
+    /// # Panics
+    /// Panics if `tile_width` or `tile_height` is zero.
+    #[cfg(feature = "rayon")]
+    pub fn par_tiles_mut(
+        &mut self,
+        tile_width: u32,
+        tile_height: u32,
+    ) -> impl ParallelIterator<Item = TileMut<'_, u8>> {
+        let (width, bytes_per_pixel) = (self.width(), self.bytes_per_pixel());
+        tile::par_tiles_mut(
+            self.par_rows_mut(),
+            width,
+            (tile_width, tile_height),
+            bytes_per_pixel,
+        )
+    }
+
+    /// Marks `rect` as needing to be presented by the next [`blit_damage`](Self::blit_damage).
+    ///
+    /// Writing through [`row_mut`](Self::row_mut) and friends does this automatically; this is
+    /// for pixels changed some other way, or for parts of the window that need repainting.
+    pub fn mark_dirty(&mut self, rect: Rect) {
+        let bounds = Rect::new(0, 0, self.width(), self.height());
+        self.damage.add(rect.intersection(&bounds));
+    }
+
+    /// The parts of the pixel buffer that have changed since they were last presented.
+    pub fn damage(&self) -> &Region {
+        &self.damage
+    }
+
+    /// Forgets about all damage without presenting it.
+    pub fn clear_damage(&mut self) {
+        self.damage.clear();
+    }
+
+    /// Blits the damaged parts of the pixel buffer onto the same place in `window`, and clears
+    /// the damage.
+    ///
+    /// All the rectangles are sent at once and waited on together, which is much cheaper than
+    /// calling [`blit_rect`](Self::blit_rect) for each of them. If blitting fails, the damage is
+    /// kept so it can be retried.
+    ///
+    /// # Errors
+    /// Returns an error if the platform fails to blit. The `window` passed to this function must be
+    /// the same `window` passed to `new`, or an error may be returned.
+    pub fn blit_damage<H: HasWindowHandle>(&mut self, window: &H) -> io::Result<()> {
+        if self.damage.is_empty() {
+            return Ok(());
+        }
+        unsafe { self.p.blit_rects(self.damage.rects(), window)? };
+        self.damage.clear();
+        Ok(())
+    }
+
+    fn damage_all(&mut self) {
+        self.damage.clear();
+        self.mark_dirty(Rect::new(0, 0, self.width(), self.height()));
+    }
+
+    /// Mutably gets a row without marking it as damaged, for callers that mark exactly what they
+    /// change themselves.
+    pub(crate) fn row_mut_undamaged(&mut self, row: u32) -> Option<&mut [u8]> {
+        self.p.row_mut(row)
+    }
+
+    /// Mutably iterates through all rows without marking them as damaged.
+    pub(crate) fn rows_mut_undamaged(
+        &mut self,
+    ) -> impl ExactSizeIterator + DoubleEndedIterator<Item = &mut [u8]> {
+        self.p.rows_mut()
+    }
 }
 
 impl<P: PixelBufferFormat> PixelBufferTyped<P> {
     /// Initialize a new pixel buffer.
     ///
-    /// Can return `Err` if the platform doesn't support the requested pixel buffer type.
+    /// See [`PixelBuffer::new`] for how formats the platform can't blit directly are handled.
     pub fn new<H: HasWindowHandle, D: HasDisplayHandle>(
         width: u32,
         height: u32,
@@ -179,8 +477,12 @@ impl<P: PixelBufferFormat> PixelBufferTyped<P> {
 
     /// Initialize a new pixel buffer.
     ///
-    /// This always works, since we've statically checked that the pixel format is supported by
-    /// the platform.
+    /// This can't fail because of the pixel format, since we've statically checked that it's
+    /// supported by the platform.
+    ///
+    /// # Panics
+    /// Panics if the pixel buffer can't be created for any other reason, such as an unsupported
+    /// window handle. Use [`new`](Self::new) to handle those errors instead.
     pub fn new_supported<H: HasWindowHandle, D: HasDisplayHandle>(
         width: u32,
         height: u32,
@@ -190,23 +492,36 @@ impl<P: PixelBufferFormat> PixelBufferTyped<P> {
     where
         P: PixelBufferFormatSupported,
     {
-        Self::new(width, height, window, display).unwrap()
+        match Self::new(width, height, window, display) {
+            Ok(p) => p,
+            Err(e) => panic!("failed to create pixel buffer: {}", e),
+        }
+    }
+
+    /// Initialize a new pixel buffer that isn't attached to a window.
+    ///
+    /// See [`PixelBuffer::new_headless`] for details.
+    pub fn new_headless(width: u32, height: u32) -> PixelBufferTyped<P> {
+        PixelBufferTyped {
+            p: PixelBuffer::new_headless(width, height, P::FORMAT_TYPE),
+            _format: PhantomData,
+        }
     }
 
     /// Blits the pixel buffer's contents onto `window`.
     ///
-    /// # Panics
-    /// The `window` passed to this function must be the same `window` passed to `new`. Failing to
-    /// do so will result in a panic.
+    /// # Errors
+    /// Returns an error if the platform fails to blit. The `window` passed to this function must be
+    /// the same `window` passed to `new`, or an error may be returned.
     pub fn blit<H: HasWindowHandle>(&self, window: &H) -> io::Result<()> {
         self.p.blit(window)
     }
 
     /// Blits a subsection of the pixel buffer's contents onto `window`.
     ///
-    /// # Panics
-    /// The `window` passed to this function must be the same `window` passed to `new`. Failing to
-    /// do so will result in a panic.
+    /// # Errors
+    /// Returns an error if the platform fails to blit. The `window` passed to this function must be
+    /// the same `window` passed to `new`, or an error may be returned.
     pub fn blit_rect<H: HasWindowHandle>(
         &self,
         src_pos: (u32, u32),
@@ -217,6 +532,54 @@ impl<P: PixelBufferFormat> PixelBufferTyped<P> {
         self.p.blit_rect(src_pos, dst_pos, blit_size, window)
     }
 
+    /// Fills `rect` with what's currently shown in the same part of `window`.
+    ///
+    /// See [`PixelBuffer::capture_from`].
+    pub fn capture_from<H: HasWindowHandle>(&mut self, window: &H, rect: Rect) -> io::Result<()> {
+        self.p.capture_from(window, rect)
+    }
+
+    /// Changes the dimensions of the pixel buffer.
+    ///
+    /// See [`PixelBuffer::resize`].
+    pub fn resize(&mut self, width: u32, height: u32) -> Result<(), PixelBufferCreationError> {
+        self.p.resize(width, height)
+    }
+
+    /// Changes the dimensions of the pixel buffer, keeping the contents of the region that's in
+    /// both the old and new dimensions.
+    ///
+    /// See [`PixelBuffer::resize_preserving`].
+    pub fn resize_preserving(
+        &mut self,
+        width: u32,
+        height: u32,
+    ) -> Result<(), PixelBufferCreationError> {
+        self.p.resize_preserving(width, height)
+    }
+
+    /// The surface that a headless pixel buffer has been blitted onto.
+    ///
+    /// Returns `None` if the pixel buffer isn't headless.
+    pub fn headless_surface(&self) -> Option<Ref<'_, HeadlessSurface>> {
+        self.p.headless_surface()
+    }
+
+    /// The colors that the pixel buffer's [`Indexed8`] pixels index into.
+    ///
+    /// See [`PixelBuffer::palette`].
+    pub fn palette(&self) -> Option<&[BGRA; 256]> {
+        self.p.palette()
+    }
+
+    /// Mutably gets the colors that the pixel buffer's [`Indexed8`] pixels index into, and marks
+    /// the whole pixel buffer as damaged.
+    ///
+    /// See [`PixelBuffer::palette_mut`].
+    pub fn palette_mut(&mut self) -> Option<&mut [BGRA; 256]> {
+        self.p.palette_mut()
+    }
+
     /// The total number of bits in an individual pixel.
     ///
     /// Will always be a multiple of `8`.
@@ -254,21 +617,47 @@ impl<P: PixelBufferFormat> PixelBufferTyped<P> {
         self.p.row_mut(row).map(P::from_raw_slice_mut)
     }
 
+    /// See [`PixelBuffer::row_mut_undamaged`].
+    pub(crate) fn row_mut_undamaged(&mut self, row: u32) -> Option<&mut [P]> {
+        self.p.row_mut_undamaged(row).map(P::from_raw_slice_mut)
+    }
+
+    /// See [`PixelBuffer::rows_mut_undamaged`].
+    pub(crate) fn rows_mut_undamaged(
+        &mut self,
+    ) -> impl ExactSizeIterator + DoubleEndedIterator<Item = &mut [P]> {
+        self.p.rows_mut_undamaged().map(P::from_raw_slice_mut)
+    }
+
+    /// Gets the pixel at `(x, y)`, or `None` if it's outside the pixel buffer.
+    pub fn get(&self, x: u32, y: u32) -> Option<&P> {
+        self.row(y)?.get(x as usize)
+    }
+
+    /// Mutably gets the pixel at `(x, y)`, or `None` if it's outside the pixel buffer.
+    ///
+    /// The pixel is marked as damaged.
+    pub fn get_mut(&mut self, x: u32, y: u32) -> Option<&mut P> {
+        if x >= self.width() || y >= self.height() {
+            return None;
+        }
+        self.mark_dirty(Rect::new(x, y, 1, 1));
+        self.row_mut_undamaged(y)?.get_mut(x as usize)
+    }
+
     /// Iterate through all rows in the pixel buffer.
-    pub fn rows<'a>(&'a self) -> impl ExactSizeIterator + DoubleEndedIterator<Item = &'a [P]> {
+    pub fn rows(&self) -> impl ExactSizeIterator + DoubleEndedIterator<Item = &[P]> {
         self.p.rows().map(P::from_raw_slice)
     }
 
     /// Mutably iterate through all rows in the pixel buffer.
-    pub fn rows_mut<'a>(
-        &'a mut self,
-    ) -> impl ExactSizeIterator + DoubleEndedIterator<Item = &'a mut [P]> {
+    pub fn rows_mut(&mut self) -> impl ExactSizeIterator + DoubleEndedIterator<Item = &mut [P]> {
         self.p.rows_mut().map(P::from_raw_slice_mut)
     }
 
     /// Iterate through all rows in the pixel buffer.
     #[cfg(feature = "rayon")]
-    pub fn par_rows<'a>(&'a self) -> impl IndexedParallelIterator<Item = &'a [P]>
+    pub fn par_rows(&self) -> impl IndexedParallelIterator<Item = &[P]>
     where
         P: Send + Sync,
     {
@@ -277,12 +666,229 @@ impl<P: PixelBufferFormat> PixelBufferTyped<P> {
 
     /// Mutably iterate through all rows in the pixel buffer.
     #[cfg(feature = "rayon")]
-    pub fn par_rows_mut<'a>(&'a mut self) -> impl IndexedParallelIterator<Item = &'a mut [P]>
+    pub fn par_rows_mut(&mut self) -> impl IndexedParallelIterator<Item = &mut [P]>
     where
         P: Send + Sync,
     {
         self.p.par_rows_mut().map(P::from_raw_slice_mut)
     }
+
+    /// Mutably iterate through the pixel buffer in `tile_width`x`tile_height` tiles.
+    ///
+    /// See [`PixelBuffer::par_tiles_mut`].
+    #[cfg(feature = "rayon")]
+    pub fn par_tiles_mut(
+        &mut self,
+        tile_width: u32,
+        tile_height: u32,
+    ) -> impl ParallelIterator<Item = TileMut<'_, P>>
+    where
+        P: Send + Sync,
+    {
+        let width = self.width();
+        tile::par_tiles_mut(self.par_rows_mut(), width, (tile_width, tile_height), 1)
+    }
+
+    /// Mutably iterate through all pixels in the pixel buffer, along with their coordinates.
+    ///
+    /// The whole pixel buffer is marked as damaged.
+    pub fn enumerate_pixels_mut(&mut self) -> impl Iterator<Item = (u32, u32, &mut P)> {
+        (0..)
+            .zip(self.rows_mut())
+            .flat_map(|(y, row)| (0..).zip(row).map(move |(x, pixel)| (x, y, pixel)))
+    }
+
+    /// Borrows the part of the pixel buffer in `rect`, clipped to the pixel buffer.
+    ///
+    /// Views of [`Indexed8`] pixel buffers keep the pixel buffer's
+    /// [`palette`](PixelView::palette).
+    pub fn view(&self, rect: Rect) -> PixelView<'_, P> {
+        let rect = rect.intersection(&Rect::new(0, 0, self.width(), self.height()));
+        PixelView::new(self.rows(), rect, self.row_len()).with_palette(self.palette())
+    }
+
+    /// Mutably borrows the part of the pixel buffer in `rect`, clipped to the pixel buffer.
+    ///
+    /// The area the view covers is marked as damaged.
+    ///
+    /// ```
+    /// use winit_blit::{PixelBufferTyped, Rect, BGRA};
+    ///
+    /// let mut pb = PixelBufferTyped::<BGRA>::new_headless(8, 8);
+    /// let mut view = pb.view_mut(Rect::new(2, 3, 4, 4));
+    /// view[(1, 0)] = BGRA::from_rgb(255, 0, 0);
+    /// assert_eq!(pb[(3, 3)], BGRA::from_rgb(255, 0, 0));
+    /// assert_eq!(pb.damage().rects(), &[Rect::new(2, 3, 4, 4)]);
+    /// ```
+    pub fn view_mut(&mut self, rect: Rect) -> PixelViewMut<'_, P> {
+        let rect = rect.intersection(&Rect::new(0, 0, self.width(), self.height()));
+        self.mark_dirty(rect);
+        let stride = self.row_len();
+        PixelViewMut::new(self.rows_mut_undamaged(), rect, stride)
+    }
+
+    /// Splits the pixel buffer into views of the rows above `row` and the rest, which can be
+    /// written to from different threads.
+    ///
+    /// The whole pixel buffer is marked as damaged.
+    ///
+    /// # Panics
+    /// Panics if `row` is greater than the height of the pixel buffer.
+    pub fn split_at_row_mut(&mut self, row: u32) -> (PixelViewMut<'_, P>, PixelViewMut<'_, P>) {
+        let rect = Rect::new(0, 0, self.width(), self.height());
+        self.view_mut(rect).split_at_row_mut(row)
+    }
+
+    /// Splits the pixel buffer into views of the columns left of `column` and the rest, which can
+    /// be written to from different threads.
+    ///
+    /// The whole pixel buffer is marked as damaged.
+    ///
+    /// # Panics
+    /// Panics if `column` is greater than the width of the pixel buffer.
+    pub fn split_at_column_mut(
+        &mut self,
+        column: u32,
+    ) -> (PixelViewMut<'_, P>, PixelViewMut<'_, P>) {
+        let rect = Rect::new(0, 0, self.width(), self.height());
+        self.view_mut(rect).split_at_column_mut(column)
+    }
+
+    /// Composites `src` onto the pixel buffer with its top-left corner at `pos`, and marks the
+    /// area it covers as damaged.
+    ///
+    /// `src` can be in any format, and is clipped to the pixel buffer where it hangs over the
+    /// edges. See the [`composite`] module for what `blend` controls.
+    ///
+    /// ```
+    /// use winit_blit::{
+    ///     composite::{Blend, BlendMode},
+    ///     PixelBufferTyped, Rect, BGRA, RGBA,
+    /// };
+    ///
+    /// let mut sprite = PixelBufferTyped::<RGBA>::new_headless(2, 2);
+    /// sprite.row_mut(0).unwrap()[1] = RGBA::new(255, 0, 0, 255);
+    /// let mut layer = PixelBufferTyped::<BGRA>::new_headless(4, 4);
+    /// layer.composite(&sprite, (-1, 3), Blend::new(BlendMode::SourceOver));
+    /// assert_eq!(layer.row(3).unwrap()[0], BGRA::new(0, 0, 255, 255));
+    /// assert_eq!(layer.damage().rects(), &[Rect::new(0, 3, 1, 1)]);
+    /// ```
+    pub fn composite<S: PixelBufferFormat>(
+        &mut self,
+        src: &PixelBufferTyped<S>,
+        pos: (i32, i32),
+        blend: Blend,
+    ) {
+        self.composite_rows(src.rows(), (src.width(), src.height()), pos, blend);
+    }
+
+    /// Composites an image stored as `src_width`-pixel rows in `src` onto the pixel buffer, with
+    /// its top-left corner at `pos`.
+    ///
+    /// See [`composite`](Self::composite).
+    ///
+    /// # Panics
+    /// Panics if `src` isn't a whole number of rows long.
+    pub fn composite_pixels<S: PixelBufferFormat>(
+        &mut self,
+        src: &[S],
+        src_width: u32,
+        pos: (i32, i32),
+        blend: Blend,
+    ) {
+        if src_width == 0 {
+            return;
+        }
+        let row_len = src_width as usize;
+        assert_eq!(
+            0,
+            src.len() % row_len,
+            "pixel slice length not multiple of {}",
+            row_len
+        );
+        let src_height = (src.len() / row_len) as u32;
+        self.composite_rows(src.chunks(row_len), (src_width, src_height), pos, blend);
+    }
+
+    fn composite_rows<'a, S: PixelBufferFormat + 'a>(
+        &mut self,
+        src_rows: impl Iterator<Item = &'a [S]>,
+        src_size: (u32, u32),
+        pos: (i32, i32),
+        blend: Blend,
+    ) {
+        let dst_size = (self.width(), self.height());
+        let rect = composite::composite_rows(
+            src_rows,
+            src_size,
+            self.rows_mut_undamaged(),
+            dst_size,
+            pos,
+            blend,
+        );
+        self.mark_dirty(rect);
+    }
+
+    /// Marks `rect` as needing to be presented by the next [`blit_damage`](Self::blit_damage).
+    ///
+    /// See [`PixelBuffer::mark_dirty`].
+    pub fn mark_dirty(&mut self, rect: Rect) {
+        self.p.mark_dirty(rect)
+    }
+
+    /// The parts of the pixel buffer that have changed since they were last presented.
+    pub fn damage(&self) -> &Region {
+        self.p.damage()
+    }
+
+    /// Forgets about all damage without presenting it.
+    pub fn clear_damage(&mut self) {
+        self.p.clear_damage()
+    }
+
+    /// Blits the damaged parts of the pixel buffer onto `window`, and clears the damage.
+    ///
+    /// See [`PixelBuffer::blit_damage`].
+    pub fn blit_damage<H: HasWindowHandle>(&mut self, window: &H) -> io::Result<()> {
+        self.p.blit_damage(window)
+    }
+}
+
+impl<P: PixelBufferFormat> Index<(u32, u32)> for PixelBufferTyped<P> {
+    type Output = P;
+
+    /// The pixel at `(x, y)`.
+    ///
+    /// # Panics
+    /// Panics if the pixel is outside the pixel buffer.
+    fn index(&self, (x, y): (u32, u32)) -> &P {
+        match self.get(x, y) {
+            Some(pixel) => pixel,
+            None => out_of_bounds(x, y, self.width(), self.height()),
+        }
+    }
+}
+
+impl<P: PixelBufferFormat> IndexMut<(u32, u32)> for PixelBufferTyped<P> {
+    /// The pixel at `(x, y)`, which is marked as damaged.
+    ///
+    /// # Panics
+    /// Panics if the pixel is outside the pixel buffer.
+    fn index_mut(&mut self, (x, y): (u32, u32)) -> &mut P {
+        let (width, height) = (self.width(), self.height());
+        match self.get_mut(x, y) {
+            Some(pixel) => pixel,
+            None => out_of_bounds(x, y, width, height),
+        }
+    }
+}
+
+#[cold]
+fn out_of_bounds(x: u32, y: u32, width: u32, height: u32) -> ! {
+    panic!(
+        "pixel ({}, {}) out of range for a {}x{} pixel buffer",
+        x, y, width, height
+    )
 }
 
 /// The pixel buffer's format. Each variant corresponds to one of the pixel format types.
@@ -296,15 +902,45 @@ pub enum PixelBufferFormatType {
     RGB,
     /// Buffer is red-green-blue-alpha formatted. Corresponds to the [`RGBA`](crate::RGBA) type.
     RGBA,
+    /// Buffer is 5-6-5 bit red-green-blue formatted. Corresponds to the [`RGB565`](crate::RGB565)
+    /// type.
+    RGB565,
+    /// Buffer is 5-5-5 bit red-green-blue formatted. Corresponds to the [`RGB555`](crate::RGB555)
+    /// type.
+    RGB555,
+    /// Buffer is 2-10-10-10 bit alpha-red-green-blue formatted. Corresponds to the
+    /// [`ARGB2101010`](crate::ARGB2101010) type.
+    ARGB2101010,
+    /// Buffer is 8-bit grayscale. Corresponds to the [`Gray8`](crate::Gray8) type.
+    Gray8,
+    /// Buffer is 16-bit grayscale. Corresponds to the [`Gray16`](crate::Gray16) type.
+    Gray16,
+    /// Buffer holds 8-bit indices into the pixel buffer's palette. Corresponds to the
+    /// [`Indexed8`](crate::Indexed8) type.
+    Indexed8,
 }
 
 /// A pixel buffer format that's supported on the current platform.
 ///
-/// ## Supported formats by platform
+/// Every format is supported everywhere, but formats that the platform can't blit directly are
+/// converted to [`NativeFormat`] over the blitted area every time they're blitted.
+///
+/// ## Formats blitted without conversion, by platform
 ///
-/// |         | [`BGR`] | [`BGRA`] | [`RGB`] | [`RGBA`] |
-/// | ------- | ------- | -------- | ------- | -------- |
-/// | Windows | ✔      | ✔      | ❌      | ❌      |
+/// |                 | Windows | X11 | XCB, Wayland | Web |
+/// | --------------- | ------- | --- | ------------ | --- |
+/// | [`BGR`]         | ✔      | ❌  | ❌           | ❌  |
+/// | [`BGRA`]        | ✔      | ✔¹ | ✔           | ❌  |
+/// | [`RGB`]         | ❌      | ❌  | ❌           | ❌  |
+/// | [`RGBA`]        | ❌      | ✔¹ | ❌           | ✔  |
+/// | [`RGB565`]      | ❌      | ✔¹ | ❌           | ❌  |
+/// | [`RGB555`]      | ❌      | ✔¹ | ❌           | ❌  |
+/// | [`ARGB2101010`] | ❌      | ✔¹ | ❌           | ❌  |
+/// | [`Gray8`]       | ❌      | ❌  | ❌           | ❌  |
+/// | [`Gray16`]      | ❌      | ❌  | ❌           | ❌  |
+/// | [`Indexed8`]    | ❌      | ❌  | ❌           | ❌  |
+///
+/// ¹ Depending on the window's visual. See [`PixelBuffer::supported_formats`].
 pub trait PixelBufferFormatSupported: PixelBufferFormat {}
 /// The format of each individual pixel in the pixel buffer.
 ///
@@ -340,6 +976,18 @@ pub unsafe trait PixelBufferFormat:
 }
 
 macro_rules! pixel_buffer_format {
+    // Formats whose channels are packed into the bits of a native-endian integer. `packed` drops
+    // the integer's alignment, so raw byte slices can be cast to them.
+    ($(#[$attr:meta])* pub struct $pixel:ident(packed $bits:ty): $array:ty = $default:expr;) => {
+        $(#[$attr])*
+        #[repr(C, packed)]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        pub struct $pixel(pub $bits);
+        impl $pixel {
+            pub const DEFAULT: $pixel = $default;
+        }
+        pixel_buffer_format!(@common $pixel: $array);
+    };
     ($(#[$attr:meta])* pub struct $pixel:ident($($c:ident),+): $array:ty = $default:expr;) => {
         $(#[$attr])*
         #[repr(C)]
@@ -349,26 +997,33 @@ macro_rules! pixel_buffer_format {
         }
         impl $pixel {
             pub const DEFAULT: $pixel = $default;
-            pub const FORMAT_TYPE: PixelBufferFormatType = PixelBufferFormatType::$pixel;
-            #[inline(always)]
-            fn size() -> usize {
-                use std::mem;
-                let size = mem::size_of::<Self>() / mem::size_of::<u8>();
-                assert_eq!(0, mem::size_of::<Self>() % mem::size_of::<u8>());
-                size
-            }
 
             pub const fn new($($c: u8),+) -> $pixel {
                 $pixel {
                     $($c),+
                 }
             }
+            #[allow(clippy::needless_update)]
             pub const fn from_rgb(r: u8, g: u8, b: u8) -> Self {
                 Self {
                     r, g, b,
                     ..Self::DEFAULT
                 }
             }
+        }
+        pixel_buffer_format!(@common $pixel: $array);
+    };
+    (@common $pixel:ident: $array:ty) => {
+        impl $pixel {
+            pub const FORMAT_TYPE: PixelBufferFormatType = PixelBufferFormatType::$pixel;
+            #[inline(always)]
+            fn size() -> usize {
+                use std::mem;
+                let size = mem::size_of::<Self>() / mem::size_of::<u8>();
+                assert_eq!(0, mem::size_of::<Self>() % mem::size_of::<u8>());
+                size
+            }
+
             #[inline(always)]
             pub fn from_raw_slice(raw: &[u8]) -> &[Self] {
                 let size = Self::size();
@@ -522,3 +1177,175 @@ pixel_buffer_format! {
     /// A red-green-blue-alpha formatted pixel type.
     pub struct RGBA(r, g, b, a): [u8; 4] = Self::new(0, 0, 0, 255);
 }
+
+pixel_buffer_format! {
+    /// A 16-bit red-green-blue pixel type, with 5 bits of red in the most significant bits, then
+    /// 6 bits of green and 5 bits of blue.
+    ///
+    /// The bits are stored in native byte order.
+    pub struct RGB565(packed u16): [u8; 2] = Self(0);
+}
+pixel_buffer_format! {
+    /// A 16-bit red-green-blue pixel type, with 5 bits each of red, green and blue from the most
+    /// significant bits down. The top bit is unused.
+    ///
+    /// The bits are stored in native byte order.
+    pub struct RGB555(packed u16): [u8; 2] = Self(0);
+}
+pixel_buffer_format! {
+    /// A 32-bit alpha-red-green-blue pixel type, with 2 bits of alpha in the most significant bits,
+    /// then 10 bits each of red, green and blue.
+    ///
+    /// The bits are stored in native byte order.
+    pub struct ARGB2101010(packed u32): [u8; 4] = Self(0b11 << 30);
+}
+pixel_buffer_format! {
+    /// An 8-bit grayscale pixel type.
+    pub struct Gray8(packed u8): [u8; 1] = Self(0);
+}
+pixel_buffer_format! {
+    /// A 16-bit grayscale pixel type.
+    ///
+    /// The value is stored in native byte order.
+    pub struct Gray16(packed u16): [u8; 2] = Self(0);
+}
+pixel_buffer_format! {
+    /// An index into the palette of an indexed pixel buffer.
+    ///
+    /// See [`PixelBuffer::palette`].
+    pub struct Indexed8(packed u8): [u8; 1] = Self(0);
+}
+
+/// Widens the `bits` low bits of `value` to 8 bits, so that the largest value maps to 255.
+const fn widen(value: u32, bits: u32) -> u8 {
+    let value = value & ((1 << bits) - 1);
+    // Repeat the bits all the way down, so the bottom bits aren't left as zeroes.
+    let mut wide = value << (8 - bits);
+    let mut filled = bits;
+    while filled < 8 {
+        wide |= wide >> filled;
+        filled *= 2;
+    }
+    wide as u8
+}
+
+/// The Rec. 601 luma of a color, scaled so that white is 65535.
+const fn luma16(r: u8, g: u8, b: u8) -> u16 {
+    // The weights add up to 65536.
+    let luma = r as u32 * 19595 + g as u32 * 38470 + b as u32 * 7471;
+    (((luma >> 8) * 257) >> 8) as u16
+}
+
+impl RGB565 {
+    /// Packs 5-bit red, 6-bit green and 5-bit blue. Extra high bits are ignored.
+    pub const fn new(r: u8, g: u8, b: u8) -> RGB565 {
+        RGB565(((r as u16 & 0x1f) << 11) | ((g as u16 & 0x3f) << 5) | (b as u16 & 0x1f))
+    }
+    pub const fn from_rgb(r: u8, g: u8, b: u8) -> Self {
+        Self::new(r >> 3, g >> 2, b >> 3)
+    }
+    /// The red, green and blue channels, widened to 8 bits.
+    pub const fn to_rgb(self) -> (u8, u8, u8) {
+        let bits = self.0 as u32;
+        (widen(bits >> 11, 5), widen(bits >> 5, 6), widen(bits, 5))
+    }
+}
+
+impl RGB555 {
+    /// Packs 5-bit red, green and blue. Extra high bits are ignored.
+    pub const fn new(r: u8, g: u8, b: u8) -> RGB555 {
+        RGB555(((r as u16 & 0x1f) << 10) | ((g as u16 & 0x1f) << 5) | (b as u16 & 0x1f))
+    }
+    pub const fn from_rgb(r: u8, g: u8, b: u8) -> Self {
+        Self::new(r >> 3, g >> 3, b >> 3)
+    }
+    /// The red, green and blue channels, widened to 8 bits.
+    pub const fn to_rgb(self) -> (u8, u8, u8) {
+        let bits = self.0 as u32;
+        (widen(bits >> 10, 5), widen(bits >> 5, 5), widen(bits, 5))
+    }
+}
+
+impl ARGB2101010 {
+    /// Packs 10-bit red, green and blue and 2-bit alpha. Extra high bits are ignored.
+    pub const fn new(r: u16, g: u16, b: u16, a: u8) -> ARGB2101010 {
+        ARGB2101010(
+            ((a as u32 & 0x3) << 30)
+                | ((r as u32 & 0x3ff) << 20)
+                | ((g as u32 & 0x3ff) << 10)
+                | (b as u32 & 0x3ff),
+        )
+    }
+    pub const fn from_rgb(r: u8, g: u8, b: u8) -> Self {
+        // Repeat the top bits at the bottom, so 255 becomes the largest 10-bit value.
+        Self::new(
+            (r as u16) << 2 | (r as u16) >> 6,
+            (g as u16) << 2 | (g as u16) >> 6,
+            (b as u16) << 2 | (b as u16) >> 6,
+            0b11,
+        )
+    }
+    /// The red, green, blue and alpha channels, narrowed or widened to 8 bits.
+    pub const fn to_rgba(self) -> (u8, u8, u8, u8) {
+        let bits = self.0;
+        (
+            (bits >> 22) as u8,
+            (bits >> 12) as u8,
+            (bits >> 2) as u8,
+            widen(bits >> 30, 2),
+        )
+    }
+}
+
+impl Gray8 {
+    pub const fn new(value: u8) -> Gray8 {
+        Gray8(value)
+    }
+    /// The color's Rec. 601 luma.
+    pub const fn from_rgb(r: u8, g: u8, b: u8) -> Self {
+        Gray8((luma16(r, g, b) >> 8) as u8)
+    }
+}
+
+impl Indexed8 {
+    /// The palette indexed pixel buffers start out with, where each index is a shade of gray
+    /// from black to white.
+    pub const GRAYSCALE_PALETTE: [BGRA; 256] = {
+        let mut palette = [BGRA::DEFAULT; 256];
+        let mut i = 0;
+        while i < 256 {
+            palette[i] = BGRA::new(i as u8, i as u8, i as u8, 255);
+            i += 1;
+        }
+        palette
+    };
+
+    pub const fn new(index: u8) -> Indexed8 {
+        Indexed8(index)
+    }
+    /// The index of the color's Rec. 601 luma in [`GRAYSCALE_PALETTE`](Self::GRAYSCALE_PALETTE).
+    pub const fn from_rgb(r: u8, g: u8, b: u8) -> Self {
+        Indexed8(Gray8::from_rgb(r, g, b).0)
+    }
+}
+
+impl Gray16 {
+    pub const fn new(value: u16) -> Gray16 {
+        Gray16(value)
+    }
+    /// The color's Rec. 601 luma.
+    pub const fn from_rgb(r: u8, g: u8, b: u8) -> Self {
+        Gray16(luma16(r, g, b))
+    }
+}
+
+impl PixelBufferFormatSupported for BGR {}
+impl PixelBufferFormatSupported for BGRA {}
+impl PixelBufferFormatSupported for RGB {}
+impl PixelBufferFormatSupported for RGBA {}
+impl PixelBufferFormatSupported for RGB565 {}
+impl PixelBufferFormatSupported for RGB555 {}
+impl PixelBufferFormatSupported for ARGB2101010 {}
+impl PixelBufferFormatSupported for Gray8 {}
+impl PixelBufferFormatSupported for Gray16 {}
+impl PixelBufferFormatSupported for Indexed8 {}
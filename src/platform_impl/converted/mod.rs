@@ -0,0 +1,341 @@
+use std::{cell::RefCell, io};
+
+use raw_window_handle::HasWindowHandle;
+
+use super::{resize_rows, stride};
+use crate::{
+    convert::{convert_raw_indexed_row, convert_raw_row},
+    Indexed8, PixelBufferCreationError, PixelBufferFormatType, Rect, BGRA,
+};
+
+/// A pixel buffer in a format that the platform can't blit.
+///
+/// The pixels are converted into a pixel buffer of a format the platform does support whenever
+/// they're blitted, but only over the area being blitted.
+pub struct PixelBuffer {
+    width: u32,
+    height: u32,
+    format: PixelBufferFormatType,
+    pixels: Vec<u8>,
+    /// The pixel buffer that actually gets blitted. Only the parts that have been blitted are up to
+    /// date.
+    native: RefCell<Box<super::PixelBuffer>>,
+    native_format: PixelBufferFormatType,
+    /// The colors that indexed pixels are looked up in.
+    palette: Option<Box<[BGRA; 256]>>,
+}
+
+impl PixelBuffer {
+    pub fn new(
+        format: PixelBufferFormatType,
+        native: super::PixelBuffer,
+        native_format: PixelBufferFormatType,
+    ) -> PixelBuffer {
+        let (width, height) = (native.width(), native.height());
+        let len = width as usize * height as usize * format.bytes_per_pixel();
+        PixelBuffer {
+            width,
+            height,
+            format,
+            pixels: vec![255; len],
+            native: RefCell::new(Box::new(native)),
+            native_format,
+            palette: match format {
+                PixelBufferFormatType::Indexed8 => Some(Box::new(Indexed8::GRAYSCALE_PALETTE)),
+                _ => None,
+            },
+        }
+    }
+    pub unsafe fn blit<H: HasWindowHandle + ?Sized>(&self, window: &H) -> io::Result<()> {
+        self.blit_rect((0, 0), (0, 0), (self.width(), self.height()), window)
+    }
+    pub unsafe fn blit_rect<H: HasWindowHandle + ?Sized>(
+        &self,
+        src_pos: (u32, u32),
+        dst_pos: (u32, u32),
+        blit_size: (u32, u32),
+        window: &H,
+    ) -> io::Result<()> {
+        // Only the part of the source inside the pixel buffer gets converted, so that's all the
+        // native pixel buffer gets asked to blit.
+        let bounds = Rect::new(0, 0, self.width, self.height);
+        let src = Rect::new(src_pos.0, src_pos.1, blit_size.0, blit_size.1).intersection(&bounds);
+        if src.is_empty() {
+            return Ok(());
+        }
+        let mut native = self.native.borrow_mut();
+        self.convert_to_native(&mut native, src);
+        native.blit_rect((src.x, src.y), dst_pos, (src.width, src.height), window)
+    }
+    pub unsafe fn blit_rects<H: HasWindowHandle + ?Sized>(
+        &self,
+        rects: &[Rect],
+        window: &H,
+    ) -> io::Result<()> {
+        let mut native = self.native.borrow_mut();
+        for &rect in rects {
+            self.convert_to_native(&mut native, rect);
+        }
+        native.blit_rects(rects, window)
+    }
+    pub unsafe fn capture_from<H: HasWindowHandle + ?Sized>(
+        &mut self,
+        rect: Rect,
+        window: &H,
+    ) -> io::Result<()> {
+        let native = self.native.get_mut();
+        native.capture_from(rect, window)?;
+
+        let rect = rect.intersection(&Rect::new(0, 0, self.width, self.height));
+        let row_len = self.width as usize * self.format.bytes_per_pixel();
+        let start = rect.x as usize * self.format.bytes_per_pixel();
+        let len = rect.width as usize * self.format.bytes_per_pixel();
+        let native_start = rect.x as usize * self.native_format.bytes_per_pixel();
+        let native_len = rect.width as usize * self.native_format.bytes_per_pixel();
+        for y in rect.y..rect.bottom() {
+            let src = &native.row(y).unwrap()[native_start..][..native_len];
+            let dst = &mut self.pixels[y as usize * row_len + start..][..len];
+            match &self.palette {
+                Some(palette) => nearest_indices(src, self.native_format, palette, dst),
+                None => convert_raw_row(src, self.native_format, dst, self.format),
+            }
+        }
+        Ok(())
+    }
+    /// Converts the part of the pixel buffer in `rect` into the same part of `native`.
+    fn convert_to_native(&self, native: &mut super::PixelBuffer, rect: Rect) {
+        let rect = rect.intersection(&Rect::new(0, 0, self.width, self.height));
+        let start = rect.x as usize * self.bytes_per_pixel();
+        let len = rect.width as usize * self.bytes_per_pixel();
+        let native_start = rect.x as usize * self.native_format.bytes_per_pixel();
+        let native_len = rect.width as usize * self.native_format.bytes_per_pixel();
+        for y in rect.y..rect.bottom() {
+            let src = &self.row(y).unwrap()[start..][..len];
+            let dst = &mut native.row_mut(y).unwrap()[native_start..][..native_len];
+            match &self.palette {
+                Some(palette) => convert_raw_indexed_row(src, palette, dst, self.native_format),
+                None => convert_raw_row(src, self.format, dst, self.native_format),
+            }
+        }
+    }
+    pub unsafe fn resize(
+        &mut self,
+        width: u32,
+        height: u32,
+        preserve: bool,
+    ) -> Result<(), PixelBufferCreationError> {
+        // Whatever's in the native pixel buffer gets overwritten before it's blitted again, so
+        // there's nothing there worth preserving.
+        self.native.get_mut().resize(width, height, false)?;
+        let row_len = width as usize * self.bytes_per_pixel();
+        let rows = if preserve { self.height.min(height) } else { 0 };
+        let old_row_len = self.row_len();
+        resize_rows(
+            &mut self.pixels,
+            old_row_len,
+            row_len,
+            rows as usize,
+            row_len * height as usize,
+        );
+        self.width = width;
+        self.height = height;
+        Ok(())
+    }
+    pub fn palette(&self) -> Option<&[BGRA; 256]> {
+        self.palette.as_deref()
+    }
+    pub fn palette_mut(&mut self) -> Option<&mut [BGRA; 256]> {
+        self.palette.as_deref_mut()
+    }
+    pub fn bits_per_pixel(&self) -> usize {
+        self.bytes_per_pixel() * 8
+    }
+    pub fn bytes_per_pixel(&self) -> usize {
+        self.format.bytes_per_pixel()
+    }
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+    pub fn row_len(&self) -> usize {
+        self.width() as usize * self.bytes_per_pixel()
+    }
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+    pub fn row(&self, row: u32) -> Option<&[u8]> {
+        let start = row as usize * self.row_len();
+        let end = (row + 1) as usize * self.row_len();
+        self.pixels.get(start..end)
+    }
+    pub fn row_mut(&mut self, row: u32) -> Option<&mut [u8]> {
+        let start = row as usize * self.row_len();
+        let end = (row + 1) as usize * self.row_len();
+        self.pixels.get_mut(start..end)
+    }
+    pub fn contiguous_bytes(&self) -> Option<&[u8]> {
+        Some(&self.pixels)
+    }
+    pub fn contiguous_bytes_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.pixels)
+    }
+    pub fn rows(&self) -> std::slice::Chunks<'_, u8> {
+        self.pixels.chunks(stride(self.row_len()))
+    }
+    pub fn rows_mut(&mut self) -> std::slice::ChunksMut<'_, u8> {
+        let stride = stride(self.row_len());
+        self.pixels.chunks_mut(stride)
+    }
+    #[cfg(feature = "rayon")]
+    pub fn par_rows(&self) -> rayon::slice::Chunks<'_, u8> {
+        use rayon::prelude::*;
+        self.pixels.par_chunks(stride(self.row_len()))
+    }
+    #[cfg(feature = "rayon")]
+    pub fn par_rows_mut(&mut self) -> rayon::slice::ChunksMut<'_, u8> {
+        use rayon::prelude::*;
+        let stride = stride(self.row_len());
+        self.pixels.par_chunks_mut(stride)
+    }
+}
+
+/// Converts a row of `format` pixels into the indices of the closest colors in `palette`.
+fn nearest_indices(
+    src: &[u8],
+    format: PixelBufferFormatType,
+    palette: &[BGRA; 256],
+    dst: &mut [u8],
+) {
+    let distance = |a: BGRA, b: BGRA| {
+        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+        d(a.b, b.b) + d(a.g, b.g) + d(a.r, b.r) + d(a.a, b.a)
+    };
+    let mut colors = [BGRA::DEFAULT; 64];
+    let mut last = 0;
+    let src_chunks = src.chunks(colors.len() * format.bytes_per_pixel());
+    for (src, dst) in src_chunks.zip(dst.chunks_mut(colors.len())) {
+        convert_raw_row(
+            src,
+            format,
+            BGRA::to_raw_slice_mut(&mut colors),
+            PixelBufferFormatType::BGRA,
+        );
+        for (&color, dst) in colors.iter().zip(dst) {
+            // Consecutive pixels are often the same color, so check the last match first.
+            if palette[last as usize] != color {
+                let nearest = (0..=255).min_by_key(|&i| distance(palette[i as usize], color));
+                last = nearest.unwrap();
+            }
+            *dst = last;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::HeadlessWindow;
+
+    /// An RGB pixel buffer that's converted into a headless BGRA one.
+    fn converted(width: u32, height: u32) -> PixelBuffer {
+        let native =
+            super::super::PixelBuffer::new_headless(width, height, PixelBufferFormatType::BGRA);
+        PixelBuffer::new(
+            PixelBufferFormatType::RGB,
+            native,
+            PixelBufferFormatType::BGRA,
+        )
+    }
+
+    #[test]
+    fn blit_converts_blitted_area() {
+        let mut pb = converted(4, 3);
+        for row in pb.rows_mut() {
+            for pixel in row.chunks_mut(3) {
+                pixel.copy_from_slice(&[1, 2, 3]);
+            }
+        }
+        unsafe {
+            pb.blit_rect((1, 1), (1, 1), (8, 1), &HeadlessWindow)
+                .unwrap();
+        }
+        let native = pb.native.borrow();
+        let surface = native.headless_surface().unwrap();
+        assert_eq!(surface.row(0).unwrap(), &[255; 16][..]);
+        assert_eq!(
+            surface.row(1).unwrap(),
+            &[255, 255, 255, 255, 3, 2, 1, 255, 3, 2, 1, 255, 3, 2, 1, 255][..]
+        );
+        let before = surface.clone();
+        drop(surface);
+        drop(native);
+
+        // Blits from outside the pixel buffer don't convert or copy anything.
+        unsafe {
+            pb.blit_rect((10, 0), (0, 0), (1, 1), &HeadlessWindow)
+                .unwrap();
+            pb.blit_rect((u32::MAX, 0), (0, 0), (2, 2), &HeadlessWindow)
+                .unwrap();
+        }
+        assert_eq!(*pb.native.borrow().headless_surface().unwrap(), before);
+    }
+
+    #[test]
+    fn capture_from_converts_back() {
+        let mut pb = converted(2, 2);
+        pb.row_mut(1).unwrap().copy_from_slice(&[1, 2, 3, 4, 5, 6]);
+        unsafe {
+            pb.blit_rects(&[Rect::new(0, 1, 2, 1)], &HeadlessWindow)
+                .unwrap();
+            pb.row_mut(1).unwrap().fill(0);
+            pb.capture_from(Rect::new(1, 0, 1, 2), &HeadlessWindow)
+                .unwrap();
+        }
+        assert_eq!(pb.row(1).unwrap(), &[0, 0, 0, 4, 5, 6]);
+    }
+
+    #[test]
+    fn blit_looks_up_palette() {
+        let native = super::super::PixelBuffer::new_headless(3, 1, PixelBufferFormatType::BGRA);
+        let mut pb = PixelBuffer::new(
+            PixelBufferFormatType::Indexed8,
+            native,
+            PixelBufferFormatType::BGRA,
+        );
+        pb.row_mut(0).unwrap().copy_from_slice(&[1, 7, 1]);
+        pb.palette_mut().unwrap()[1] = BGRA::new(1, 2, 3, 4);
+        unsafe { pb.blit(&HeadlessWindow).unwrap() };
+        let native = pb.native.borrow();
+        assert_eq!(
+            native.headless_surface().unwrap().bytes(),
+            &[1, 2, 3, 4, 7, 7, 7, 255, 1, 2, 3, 4]
+        );
+        drop(native);
+
+        // Capturing picks the closest color in the palette.
+        pb.palette_mut().unwrap()[1] = BGRA::new(1, 2, 3, 3);
+        pb.row_mut(0).unwrap().fill(0);
+        unsafe {
+            pb.capture_from(Rect::new(0, 0, 3, 1), &HeadlessWindow)
+                .unwrap()
+        };
+        assert_eq!(pb.row(0).unwrap(), &[1, 7, 1]);
+    }
+
+    #[test]
+    fn resize_resizes_native() {
+        let mut pb = converted(2, 2);
+        pb.row_mut(0).unwrap().copy_from_slice(&[1, 2, 3, 4, 5, 6]);
+        unsafe {
+            pb.resize(3, 1, true).unwrap();
+            pb.blit(&HeadlessWindow).unwrap();
+        }
+        assert_eq!(pb.row(0).unwrap(), &[1, 2, 3, 4, 5, 6, 255, 255, 255]);
+        let native = pb.native.borrow();
+        assert_eq!((native.width(), native.height()), (3, 1));
+        assert_eq!(
+            native.headless_surface().unwrap().bytes(),
+            &[3, 2, 1, 255, 6, 5, 4, 255, 255, 255, 255, 255]
+        );
+    }
+}
@@ -0,0 +1,361 @@
+use std::{
+    cell::{Ref, RefCell},
+    io,
+};
+
+use raw_window_handle::{HandleError, HasWindowHandle, WindowHandle};
+
+use super::{resize_rows, stride};
+use crate::{Indexed8, PixelBufferFormatType, Rect, BGRA};
+
+/// A pixel buffer that isn't attached to any window.
+///
+/// Blitting copies pixels into an in-memory [`HeadlessSurface`] instead of presenting them.
+pub struct PixelBuffer {
+    width: u32,
+    height: u32,
+    format: PixelBufferFormatType,
+    pixels: Vec<u8>,
+    palette: Option<Box<[BGRA; 256]>>,
+    surface: RefCell<HeadlessSurface>,
+}
+
+/// The in-memory "window" that a headless pixel buffer is blitted onto.
+///
+/// The surface has the same dimensions and pixel format as the buffer it belongs to, and holds
+/// whatever was last blitted onto it. Surfaces of indexed pixel buffers hold indices, along with
+/// the palette they were last blitted with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeadlessSurface {
+    width: u32,
+    height: u32,
+    format: PixelBufferFormatType,
+    pixels: Vec<u8>,
+    palette: Option<Box<[BGRA; 256]>>,
+}
+
+/// A stand-in window to pass to the blitting functions of a headless pixel buffer.
+///
+/// Headless pixel buffers ignore the window they're blitted onto, so this doesn't provide a
+/// window handle.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HeadlessWindow;
+
+impl HasWindowHandle for HeadlessWindow {
+    fn window_handle(&self) -> Result<WindowHandle<'_>, HandleError> {
+        Err(HandleError::NotSupported)
+    }
+}
+
+impl HeadlessSurface {
+    /// The width, in pixels, of the surface.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// The height, in pixels, of the surface.
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// The format of the surface's pixels.
+    pub fn format(&self) -> PixelBufferFormatType {
+        self.format
+    }
+
+    /// The length, in bytes, of a single row of the surface.
+    pub fn row_len(&self) -> usize {
+        self.width as usize * self.format.bytes_per_pixel()
+    }
+
+    /// Gets the row at the particular height.
+    pub fn row(&self, row: u32) -> Option<&[u8]> {
+        let start = row as usize * self.row_len();
+        let end = (row + 1) as usize * self.row_len();
+        self.pixels.get(start..end)
+    }
+
+    /// Iterate through all rows in the surface.
+    pub fn rows(&self) -> impl ExactSizeIterator + DoubleEndedIterator<Item = &[u8]> {
+        let stride = stride(self.row_len());
+        self.pixels.chunks(stride)
+    }
+
+    /// The raw contents of the surface, with rows stored top-to-bottom and no padding.
+    pub fn bytes(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// The palette the surface was last blitted with.
+    ///
+    /// Returns `None` if the surface's format isn't [`Indexed8`](PixelBufferFormatType::Indexed8).
+    pub fn palette(&self) -> Option<&[BGRA; 256]> {
+        self.palette.as_deref()
+    }
+}
+
+impl PixelBuffer {
+    pub fn new(width: u32, height: u32, format: PixelBufferFormatType) -> PixelBuffer {
+        let len = width as usize * height as usize * format.bytes_per_pixel();
+        let palette = match format {
+            PixelBufferFormatType::Indexed8 => Some(Box::new(Indexed8::GRAYSCALE_PALETTE)),
+            _ => None,
+        };
+        let surface = HeadlessSurface {
+            width,
+            height,
+            format,
+            pixels: vec![255; len],
+            palette: palette.clone(),
+        };
+        PixelBuffer {
+            width,
+            height,
+            format,
+            pixels: vec![255; len],
+            palette,
+            surface: RefCell::new(surface),
+        }
+    }
+    pub fn blit(&self) -> io::Result<()> {
+        self.blit_rect((0, 0), (0, 0), (self.width(), self.height()))
+    }
+    pub fn blit_rect(
+        &self,
+        src_pos: (u32, u32),
+        dst_pos: (u32, u32),
+        blit_size: (u32, u32),
+    ) -> io::Result<()> {
+        // Clip against both the source and destination, like a real window would.
+        let bounds = Rect::new(0, 0, self.width, self.height);
+        let src = Rect::new(src_pos.0, src_pos.1, blit_size.0, blit_size.1).intersection(&bounds);
+        let dst = Rect::new(dst_pos.0, dst_pos.1, src.width, src.height).intersection(&bounds);
+        if dst.is_empty() {
+            return Ok(());
+        }
+        let bytes_per_pixel = self.bytes_per_pixel();
+        let mut surface = self.surface.borrow_mut();
+        // Every pixel on a real window would change color along with the palette.
+        if let (Some(palette), Some(surface_palette)) = (&self.palette, &mut surface.palette) {
+            **surface_palette = **palette;
+        }
+        let len = dst.width as usize * bytes_per_pixel;
+        for y in 0..dst.height {
+            let row = self.row(src.y + y).unwrap();
+            let start = (dst.y + y) as usize * self.row_len() + dst.x as usize * bytes_per_pixel;
+            surface.pixels[start..start + len]
+                .copy_from_slice(&row[src.x as usize * bytes_per_pixel..][..len]);
+        }
+        Ok(())
+    }
+    pub fn blit_rects(&self, rects: &[Rect]) -> io::Result<()> {
+        for rect in rects {
+            let pos = (rect.x, rect.y);
+            self.blit_rect(pos, pos, (rect.width, rect.height))?;
+        }
+        Ok(())
+    }
+    pub fn capture_from(&mut self, rect: Rect) -> io::Result<()> {
+        // The surface always has the same size and format as the buffer, so this is a plain copy.
+        let row_len = self.row_len();
+        let start = rect.x as usize * self.bytes_per_pixel();
+        let len = rect.width as usize * self.bytes_per_pixel();
+        let surface = self.surface.get_mut();
+        let rows = (rect.y as usize..rect.bottom() as usize).map(|y| y * row_len + start);
+        for offset in rows {
+            self.pixels[offset..offset + len]
+                .copy_from_slice(&surface.pixels[offset..offset + len]);
+        }
+        Ok(())
+    }
+    pub fn resize(&mut self, width: u32, height: u32, preserve: bool) {
+        let row_len = width as usize * self.bytes_per_pixel();
+        let len = row_len * height as usize;
+        let rows = if preserve { self.height.min(height) } else { 0 };
+        let old_row_len = self.row_len();
+        resize_rows(&mut self.pixels, old_row_len, row_len, rows as usize, len);
+        self.width = width;
+        self.height = height;
+
+        // The surface follows the buffer around like a resized window would, keeping whatever was
+        // last blitted onto the part that's still visible.
+        let surface = self.surface.get_mut();
+        let rows = surface.height.min(height) as usize;
+        let old_row_len = surface.row_len();
+        resize_rows(&mut surface.pixels, old_row_len, row_len, rows, len);
+        surface.width = width;
+        surface.height = height;
+    }
+    pub fn surface(&self) -> Ref<'_, HeadlessSurface> {
+        self.surface.borrow()
+    }
+    pub fn palette(&self) -> Option<&[BGRA; 256]> {
+        self.palette.as_deref()
+    }
+    pub fn palette_mut(&mut self) -> Option<&mut [BGRA; 256]> {
+        self.palette.as_deref_mut()
+    }
+    pub fn bits_per_pixel(&self) -> usize {
+        self.bytes_per_pixel() * 8
+    }
+    pub fn bytes_per_pixel(&self) -> usize {
+        self.format.bytes_per_pixel()
+    }
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+    pub fn row_len(&self) -> usize {
+        self.width() as usize * self.bytes_per_pixel()
+    }
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+    pub fn row(&self, row: u32) -> Option<&[u8]> {
+        let start = row as usize * self.row_len();
+        let end = (row + 1) as usize * self.row_len();
+        self.pixels.get(start..end)
+    }
+    pub fn row_mut(&mut self, row: u32) -> Option<&mut [u8]> {
+        let start = row as usize * self.row_len();
+        let end = (row + 1) as usize * self.row_len();
+        self.pixels.get_mut(start..end)
+    }
+    pub fn contiguous_bytes(&self) -> Option<&[u8]> {
+        Some(&self.pixels)
+    }
+    pub fn contiguous_bytes_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.pixels)
+    }
+    pub fn rows(&self) -> std::slice::Chunks<'_, u8> {
+        self.pixels.chunks(stride(self.row_len()))
+    }
+    pub fn rows_mut(&mut self) -> std::slice::ChunksMut<'_, u8> {
+        let stride = stride(self.row_len());
+        self.pixels.chunks_mut(stride)
+    }
+    #[cfg(feature = "rayon")]
+    pub fn par_rows(&self) -> rayon::slice::Chunks<'_, u8> {
+        use rayon::prelude::*;
+        self.pixels.par_chunks(stride(self.row_len()))
+    }
+    #[cfg(feature = "rayon")]
+    pub fn par_rows_mut(&mut self) -> rayon::slice::ChunksMut<'_, u8> {
+        use rayon::prelude::*;
+        let stride = stride(self.row_len());
+        self.pixels.par_chunks_mut(stride)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blit_rect_copies_and_clips() {
+        let mut pb = PixelBuffer::new(4, 3, PixelBufferFormatType::RGB);
+        for (y, row) in pb.rows_mut().enumerate() {
+            for (x, pixel) in row.chunks_mut(3).enumerate() {
+                pixel.copy_from_slice(&[x as u8, y as u8, 1]);
+            }
+        }
+
+        pb.blit_rect((1, 1), (2, 0), (8, 8)).unwrap();
+        let surface = pb.surface();
+        assert_eq!(
+            surface.row(0).unwrap(),
+            &[255, 255, 255, 255, 255, 255, 1, 1, 1, 2, 1, 1]
+        );
+        assert_eq!(
+            surface.row(1).unwrap(),
+            &[255, 255, 255, 255, 255, 255, 1, 2, 1, 2, 2, 1]
+        );
+        assert_eq!(surface.row(2).unwrap(), &[255; 12]);
+        drop(surface);
+
+        // Blits from outside the buffer don't copy anything.
+        let before = pb.surface().clone();
+        pb.blit_rect((10, 0), (0, 0), (1, 1)).unwrap();
+        pb.blit_rect((0, 3), (0, 0), (1, 1)).unwrap();
+        pb.blit_rect((u32::MAX, 0), (0, 0), (2, 2)).unwrap();
+        pb.blit_rect((0, 0), (u32::MAX, u32::MAX), (2, 2)).unwrap();
+        assert_eq!(*pb.surface(), before);
+
+        pb.blit().unwrap();
+        assert!(pb.rows().eq(pb.surface().rows()));
+    }
+
+    #[test]
+    fn resize_preserving_keeps_overlap() {
+        let mut pb = PixelBuffer::new(3, 2, PixelBufferFormatType::BGRA);
+        for (y, row) in pb.rows_mut().enumerate() {
+            for (x, pixel) in row.chunks_mut(4).enumerate() {
+                pixel.copy_from_slice(&[x as u8, y as u8, 0, 255]);
+            }
+        }
+        pb.blit().unwrap();
+
+        pb.resize(5, 3, true);
+        assert_eq!(pb.rows().len(), 3);
+        for (y, row) in pb.rows().take(2).enumerate() {
+            assert_eq!(row.len(), 20);
+            for (x, pixel) in row.chunks(4).take(3).enumerate() {
+                assert_eq!(pixel, &[x as u8, y as u8, 0, 255]);
+            }
+        }
+        assert_eq!(
+            pb.surface().row(1).unwrap()[..12],
+            [0, 1, 0, 255, 1, 1, 0, 255, 2, 1, 0, 255]
+        );
+
+        pb.resize(2, 1, true);
+        assert_eq!(
+            pb.rows().collect::<Vec<_>>(),
+            [&[0, 0, 0, 255, 1, 0, 0, 255]]
+        );
+
+        pb.resize(0, 4, false);
+        assert_eq!(pb.rows().len(), 0);
+        assert_eq!(pb.row(0), Some(&[][..]));
+        pb.blit().unwrap();
+    }
+
+    #[test]
+    fn capture_from_reads_surface() {
+        let mut pb = PixelBuffer::new(4, 4, PixelBufferFormatType::RGB);
+        pb.rows_mut().for_each(|row| row.fill(7));
+        pb.blit().unwrap();
+        pb.rows_mut().for_each(|row| row.fill(0));
+
+        pb.capture_from(Rect::new(1, 2, 2, 2)).unwrap();
+        assert_eq!(pb.row(1).unwrap(), &[0; 12]);
+        assert_eq!(pb.row(2).unwrap(), &[0, 0, 0, 7, 7, 7, 7, 7, 7, 0, 0, 0]);
+        assert_eq!(pb.row(3).unwrap(), &[0, 0, 0, 7, 7, 7, 7, 7, 7, 0, 0, 0]);
+    }
+
+    #[test]
+    fn blit_copies_palette() {
+        let mut pb = PixelBuffer::new(2, 1, PixelBufferFormatType::Indexed8);
+        pb.row_mut(0).unwrap().copy_from_slice(&[1, 2]);
+        pb.palette_mut().unwrap()[1] = BGRA::new(1, 2, 3, 4);
+        assert_eq!(pb.surface().palette(), Some(&Indexed8::GRAYSCALE_PALETTE));
+
+        pb.blit_rect((0, 0), (0, 0), (1, 1)).unwrap();
+        let surface = pb.surface();
+        assert_eq!(surface.bytes(), &[1, 255]);
+        assert_eq!(surface.palette().unwrap()[1], BGRA::new(1, 2, 3, 4));
+        assert_eq!(surface.palette().unwrap()[2], BGRA::new(2, 2, 2, 255));
+        drop(surface);
+
+        let pb = PixelBuffer::new(2, 1, PixelBufferFormatType::Gray8);
+        assert_eq!(pb.palette(), None);
+        assert_eq!(pb.surface().palette(), None);
+    }
+
+    #[test]
+    fn zero_sized() {
+        let mut pb = PixelBuffer::new(0, 0, PixelBufferFormatType::BGRA);
+        assert_eq!(pb.rows_mut().count(), 0);
+        pb.blit().unwrap();
+        assert_eq!(pb.surface().rows().count(), 0);
+    }
+}
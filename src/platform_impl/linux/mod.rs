@@ -6,9 +6,218 @@
     target_os = "openbsd"
 ))]
 
-#[cfg(all(not(feature = "x11")))]
-compile_error!("Please select a feature to build for unix: `x11`");
+use std::io;
 
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+use raw_window_handle::{DisplayHandle, RawWindowHandle, WindowHandle};
+
+use crate::{platform_impl::stride, PixelBufferCreationError, PixelBufferFormatType, Rect};
+
+#[cfg(not(any(feature = "x11", feature = "xcb", feature = "wayland")))]
+compile_error!("Please select a feature to build for unix: `x11`, `xcb`, `wayland`");
+
+#[cfg(feature = "wayland")]
+mod wayland;
+#[cfg(feature = "x11")]
+mod x11;
+#[cfg(feature = "xcb")]
+mod xcb;
+
+pub type NativeFormat = crate::BGRA;
+
+/// A pixel buffer for whichever display server the window lives on.
+///
+/// Every backend stores its pixels top-down and tightly packed, so row access is shared here.
+pub enum PixelBuffer {
+    // Backends vary wildly in size (`Xlib` alone is several KiB of function pointers), so they're
+    // boxed to keep the enum small.
+    #[cfg(feature = "x11")]
+    X11(Box<x11::PixelBuffer>),
+    #[cfg(feature = "xcb")]
+    Xcb(Box<xcb::PixelBuffer>),
+    #[cfg(feature = "wayland")]
+    Wayland(Box<wayland::PixelBuffer>),
+}
+
+macro_rules! dispatch {
+    ($self:expr, $p:ident => $body:expr) => {
+        match $self {
+            #[cfg(feature = "x11")]
+            PixelBuffer::X11($p) => $body,
+            #[cfg(feature = "xcb")]
+            PixelBuffer::Xcb($p) => $body,
+            #[cfg(feature = "wayland")]
+            PixelBuffer::Wayland($p) => $body,
+        }
+    };
+}
+
+impl PixelBuffer {
+    pub unsafe fn new(
+        width: u32,
+        height: u32,
+        format: PixelBufferFormatType,
+        window_handle: WindowHandle,
+        display_handle: DisplayHandle,
+    ) -> Result<PixelBuffer, PixelBufferCreationError> {
+        match window_handle.as_raw() {
+            #[cfg(feature = "x11")]
+            RawWindowHandle::Xlib(_) => {
+                x11::PixelBuffer::new(width, height, format, window_handle, display_handle)
+                    .map(|p| PixelBuffer::X11(Box::new(p)))
+            }
+            #[cfg(feature = "xcb")]
+            RawWindowHandle::Xcb(_) => {
+                xcb::PixelBuffer::new(width, height, format, window_handle, display_handle)
+                    .map(|p| PixelBuffer::Xcb(Box::new(p)))
+            }
+            #[cfg(feature = "wayland")]
+            RawWindowHandle::Wayland(_) => {
+                wayland::PixelBuffer::new(width, height, format, window_handle, display_handle)
+                    .map(|p| PixelBuffer::Wayland(Box::new(p)))
+            }
+            _ => Err(PixelBufferCreationError::HandleNotSupported),
+        }
+    }
+    pub unsafe fn supported_formats(
+        window_handle: WindowHandle,
+        display_handle: DisplayHandle,
+    ) -> Result<Vec<PixelBufferFormatType>, PixelBufferCreationError> {
+        match window_handle.as_raw() {
+            #[cfg(feature = "x11")]
+            RawWindowHandle::Xlib(_) => {
+                x11::PixelBuffer::supported_formats(window_handle, display_handle)
+            }
+            #[cfg(feature = "xcb")]
+            RawWindowHandle::Xcb(_) => {
+                xcb::PixelBuffer::supported_formats(window_handle, display_handle)
+            }
+            #[cfg(feature = "wayland")]
+            RawWindowHandle::Wayland(_) => {
+                wayland::PixelBuffer::supported_formats(window_handle, display_handle)
+            }
+            _ => Err(PixelBufferCreationError::HandleNotSupported),
+        }
+    }
+    pub unsafe fn blit(&self, handle: WindowHandle) -> io::Result<()> {
+        dispatch!(self, p => p.blit(handle))
+    }
+    pub unsafe fn blit_rect(
+        &self,
+        src_pos: (u32, u32),
+        dst_pos: (u32, u32),
+        blit_size: (u32, u32),
+        handle: WindowHandle,
+    ) -> io::Result<()> {
+        dispatch!(self, p => p.blit_rect(src_pos, dst_pos, blit_size, handle))
+    }
+    pub unsafe fn blit_rects(&self, rects: &[Rect], handle: WindowHandle) -> io::Result<()> {
+        dispatch!(self, p => p.blit_rects(rects, handle))
+    }
+    pub unsafe fn capture_from(&mut self, rect: Rect, handle: WindowHandle) -> io::Result<()> {
+        dispatch!(self, p => p.capture_from(rect, handle))
+    }
+    pub unsafe fn resize(
+        &mut self,
+        width: u32,
+        height: u32,
+        preserve: bool,
+    ) -> Result<(), PixelBufferCreationError> {
+        dispatch!(self, p => p.resize(width, height, preserve))
+    }
+    pub fn bits_per_pixel(&self) -> usize {
+        dispatch!(self, p => p.bits_per_pixel())
+    }
+
+    pub fn bytes_per_pixel(&self) -> usize {
+        dispatch!(self, p => p.bytes_per_pixel())
+    }
+
+    pub fn width(&self) -> u32 {
+        dispatch!(self, p => p.width())
+    }
+
+    pub fn row_len(&self) -> usize {
+        dispatch!(self, p => p.row_len())
+    }
+
+    pub fn height(&self) -> u32 {
+        dispatch!(self, p => p.height())
+    }
+
+    fn bytes(&self) -> &[u8] {
+        dispatch!(self, p => p.bytes())
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        dispatch!(self, p => p.bytes_mut())
+    }
+
+    pub fn row(&self, row: u32) -> Option<&[u8]> {
+        let start = row as usize * self.row_len();
+        let end = (row + 1) as usize * self.row_len();
+        self.bytes().get(start..end)
+    }
+
+    pub fn row_mut(&mut self, row: u32) -> Option<&mut [u8]> {
+        let start = row as usize * self.row_len();
+        let end = (row + 1) as usize * self.row_len();
+        self.bytes_mut().get_mut(start..end)
+    }
+
+    pub fn contiguous_bytes(&self) -> Option<&[u8]> {
+        Some(self.bytes())
+    }
+
+    pub fn contiguous_bytes_mut(&mut self) -> Option<&mut [u8]> {
+        Some(self.bytes_mut())
+    }
+
+    pub fn rows(&self) -> impl ExactSizeIterator + DoubleEndedIterator<Item = &[u8]> {
+        self.bytes().chunks(stride(self.row_len()))
+    }
+
+    pub fn rows_mut(&mut self) -> impl ExactSizeIterator + DoubleEndedIterator<Item = &mut [u8]> {
+        let chunk_size = stride(self.row_len());
+        self.bytes_mut().chunks_mut(chunk_size)
+    }
+
+    #[cfg(feature = "rayon")]
+    pub fn par_rows(&self) -> impl IndexedParallelIterator<Item = &[u8]> {
+        self.bytes().par_chunks(stride(self.row_len()))
+    }
+
+    #[cfg(feature = "rayon")]
+    pub fn par_rows_mut(&mut self) -> impl IndexedParallelIterator<Item = &mut [u8]> {
+        let chunk_size = stride(self.row_len());
+        self.bytes_mut().par_chunks_mut(chunk_size)
+    }
+}
+
+/// Sets the alpha of every pixel in `rect` to opaque.
+///
+/// Windows with a depth below 32 have no alpha channel, so the server is free to leave those bits
+/// zeroed in the pixels it sends back.
+#[cfg(any(feature = "x11", feature = "xcb"))]
+fn fill_alpha(bytes: &mut [u8], row_len: usize, format: PixelBufferFormatType, rect: Rect) {
+    let bytes_per_pixel = format.bytes_per_pixel();
+    for row in bytes[rect.y as usize * row_len..]
+        .chunks_mut(row_len)
+        .take(rect.height as usize)
+    {
+        let pixels = &mut row[rect.x as usize * bytes_per_pixel..];
+        let pixels = &mut pixels[..rect.width as usize * bytes_per_pixel];
+        crate::convert::fill_raw_alpha(pixels, format, 255);
+    }
+}
+
+/// Copies the first `rows` rows of `src` into `dst`, keeping as much of each row as fits.
 #[cfg(feature = "x11")]
-pub mod x11;
-pub use x11::*;
+fn copy_rows(src: &[u8], src_row_len: usize, dst: &mut [u8], dst_row_len: usize, rows: usize) {
+    let len = src_row_len.min(dst_row_len);
+    for y in 0..rows {
+        dst[y * dst_row_len..][..len].copy_from_slice(&src[y * src_row_len..][..len]);
+    }
+}
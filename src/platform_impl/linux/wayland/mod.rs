@@ -0,0 +1,491 @@
+use std::{
+    cell::RefCell,
+    io,
+    os::{
+        fd::{AsFd, FromRawFd, OwnedFd},
+        raw::{c_char, c_void},
+    },
+    ptr::{self, NonNull},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use raw_window_handle::{DisplayHandle, RawDisplayHandle, RawWindowHandle, WindowHandle};
+use wayland_client::{
+    backend::{Backend, ObjectId},
+    delegate_noop,
+    globals::{registry_queue_init, GlobalListContents},
+    protocol::{
+        wl_buffer::{self, WlBuffer},
+        wl_registry::WlRegistry,
+        wl_shm,
+        wl_shm_pool::WlShmPool,
+        wl_surface::WlSurface,
+    },
+    Connection, Dispatch, EventQueue, Proxy, QueueHandle,
+};
+
+use crate::{platform_impl::resize_rows, PixelBufferCreationError, PixelBufferFormatType, Rect};
+
+pub struct PixelBuffer {
+    width: u32,
+    height: u32,
+    conn: Connection,
+    queue: RefCell<EventQueue<State>>,
+    surface: WlSurface,
+    wl_shm: wl_shm::WlShm,
+    pixels: Vec<u8>,
+    surface_buffers: RefCell<SurfaceBuffers>,
+}
+
+/// The buffers that get attached to the surface.
+///
+/// The compositor may keep reading from a buffer until it sends `wl_buffer.release`, so there are
+/// two of them: blits draw into whichever one the compositor isn't holding onto, after bringing
+/// it up to date with the one that was last attached.
+#[derive(Default)]
+struct SurfaceBuffers {
+    // `None` until first needed, since `wl_shm` can't create zero-sized pools.
+    buffers: [Option<ShmBuffer>; 2],
+    // Index of the buffer that was last attached to the surface.
+    front: Option<usize>,
+}
+
+/// A `wl_buffer` backed by a memory-mapped `memfd` shared with the compositor.
+struct ShmBuffer {
+    pool: WlShmPool,
+    buffer: WlBuffer,
+    data: NonNull<u8>,
+    len: usize,
+    // Set from the moment the buffer is committed until the compositor releases it.
+    busy: Arc<AtomicBool>,
+    // Held so the mapping's file stays alive for as long as the pool does.
+    _fd: OwnedFd,
+}
+
+/// Dispatch state for the private event queue that owns our `wl_shm` objects.
+struct State;
+
+impl Dispatch<WlRegistry, GlobalListContents> for State {
+    fn event(
+        _: &mut State,
+        _: &WlRegistry,
+        _: <WlRegistry as Proxy>::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<State>,
+    ) {
+    }
+}
+
+impl Dispatch<WlBuffer, Arc<AtomicBool>> for State {
+    fn event(
+        _: &mut State,
+        _: &WlBuffer,
+        event: wl_buffer::Event,
+        busy: &Arc<AtomicBool>,
+        _: &Connection,
+        _: &QueueHandle<State>,
+    ) {
+        if let wl_buffer::Event::Release = event {
+            busy.store(false, Ordering::Relaxed);
+        }
+    }
+}
+delegate_noop!(State: ignore wl_shm::WlShm);
+delegate_noop!(State: WlShmPool);
+
+fn get_surface_and_display(
+    window_handle: WindowHandle,
+    display_handle: DisplayHandle,
+) -> Option<(NonNull<c_void>, NonNull<c_void>)> {
+    if let RawWindowHandle::Wayland(wl_window_handle) = window_handle.as_raw() {
+        if let RawDisplayHandle::Wayland(wl_display_handle) = display_handle.as_raw() {
+            return Some((wl_window_handle.surface, wl_display_handle.display));
+        }
+    }
+    None
+}
+
+fn allocation_failed<E: ToString>(e: E) -> PixelBufferCreationError {
+    PixelBufferCreationError::AllocationFailed(e.to_string())
+}
+
+const BYTES_PER_PIXEL: usize = 4;
+const BITS_PER_PIXEL: usize = BYTES_PER_PIXEL * 8;
+
+/// A rectangle to copy from the pixel buffer onto the surface, as (src_pos, dst_pos, blit_size).
+type Blit = ((u32, u32), (u32, u32), (u32, u32));
+
+impl ShmBuffer {
+    unsafe fn new(
+        shm: &wl_shm::WlShm,
+        width: u32,
+        height: u32,
+        qh: &QueueHandle<State>,
+    ) -> io::Result<ShmBuffer> {
+        let stride = width as usize * BYTES_PER_PIXEL;
+        let len = stride * height as usize;
+
+        let raw_fd =
+            libc::memfd_create(b"winit-blit\0".as_ptr() as *const c_char, libc::MFD_CLOEXEC);
+        if raw_fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = OwnedFd::from_raw_fd(raw_fd);
+        if libc::ftruncate(raw_fd, len as libc::off_t) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let data = libc::mmap(
+            ptr::null_mut(),
+            len,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED,
+            raw_fd,
+            0,
+        );
+        if data == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        let data = NonNull::new_unchecked(data as *mut u8);
+
+        let pool = shm.create_pool(fd.as_fd(), len as i32, qh, ());
+        let busy = Arc::new(AtomicBool::new(false));
+        // XRGB8888 is little-endian, so in memory each pixel is laid out as BGRA.
+        let buffer = pool.create_buffer(
+            0,
+            width as i32,
+            height as i32,
+            stride as i32,
+            wl_shm::Format::Xrgb8888,
+            qh,
+            busy.clone(),
+        );
+        Ok(ShmBuffer {
+            pool,
+            buffer,
+            data,
+            len,
+            busy,
+            _fd: fd,
+        })
+    }
+
+    fn bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.data.as_ptr(), self.len) }
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.data.as_ptr(), self.len) }
+    }
+}
+
+impl Drop for ShmBuffer {
+    fn drop(&mut self) {
+        self.buffer.destroy();
+        self.pool.destroy();
+        unsafe {
+            libc::munmap(self.data.as_ptr() as *mut _, self.len);
+        }
+    }
+}
+
+impl PixelBuffer {
+    pub unsafe fn new(
+        width: u32,
+        height: u32,
+        format: PixelBufferFormatType,
+        window_handle: WindowHandle,
+        display_handle: DisplayHandle,
+    ) -> Result<PixelBuffer, PixelBufferCreationError> {
+        if format != PixelBufferFormatType::BGRA {
+            return Err(PixelBufferCreationError::FormatNotSupported);
+        }
+        let (surface, display) = get_surface_and_display(window_handle, display_handle)
+            .ok_or(PixelBufferCreationError::HandleNotSupported)?;
+
+        // Borrow the windowing library's connection rather than opening our own; objects we
+        // create go on a private queue so we never dispatch events meant for someone else.
+        let conn = Connection::from_backend(Backend::from_foreign_display(display.as_ptr().cast()));
+        let surface = ObjectId::from_ptr(WlSurface::interface(), surface.as_ptr().cast())
+            .and_then(|id| WlSurface::from_id(&conn, id))
+            .map_err(|_| PixelBufferCreationError::HandleNotSupported)?;
+
+        let (globals, mut queue) =
+            registry_queue_init::<State>(&conn).map_err(allocation_failed)?;
+        let qh = queue.handle();
+        let shm: wl_shm::WlShm = globals.bind(&qh, 1..=1, ()).map_err(allocation_failed)?;
+        queue.roundtrip(&mut State).map_err(allocation_failed)?;
+
+        Ok(PixelBuffer {
+            width,
+            height,
+            conn,
+            queue: RefCell::new(queue),
+            surface,
+            wl_shm: shm,
+            pixels: vec![255; width as usize * height as usize * BYTES_PER_PIXEL],
+            surface_buffers: RefCell::new(SurfaceBuffers::default()),
+        })
+    }
+    pub unsafe fn supported_formats(
+        window_handle: WindowHandle,
+        display_handle: DisplayHandle,
+    ) -> Result<Vec<PixelBufferFormatType>, PixelBufferCreationError> {
+        get_surface_and_display(window_handle, display_handle)
+            .ok_or(PixelBufferCreationError::HandleNotSupported)?;
+        Ok(vec![PixelBufferFormatType::BGRA])
+    }
+    pub unsafe fn blit(&self, handle: WindowHandle) -> io::Result<()> {
+        self.blit_rect((0, 0), (0, 0), (self.width(), self.height()), handle)
+    }
+    pub unsafe fn blit_rect(
+        &self,
+        src_pos: (u32, u32),
+        dst_pos: (u32, u32),
+        blit_size: (u32, u32),
+        _handle: WindowHandle,
+    ) -> io::Result<()> {
+        self.present(&[(src_pos, dst_pos, blit_size)])
+    }
+    pub unsafe fn blit_rects(&self, rects: &[Rect], _handle: WindowHandle) -> io::Result<()> {
+        let blits: Vec<_> = rects
+            .iter()
+            .map(|rect| {
+                (
+                    (rect.x, rect.y),
+                    (rect.x, rect.y),
+                    (rect.width, rect.height),
+                )
+            })
+            .collect();
+        self.present(&blits)
+    }
+    pub unsafe fn capture_from(&mut self, _rect: Rect, _handle: WindowHandle) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "wayland doesn't allow reading back a surface's contents",
+        ))
+    }
+    /// Copies each of `blits` into a buffer the compositor isn't using, then attaches and commits
+    /// that buffer with the copied areas marked as damaged.
+    fn present(&self, blits: &[Blit]) -> io::Result<()> {
+        if self.width == 0 || self.height == 0 {
+            return Ok(());
+        }
+        let mut queue = self.queue.borrow_mut();
+        let qh = queue.handle();
+        let mut surface_buffers = self.surface_buffers.borrow_mut();
+        let SurfaceBuffers { buffers, front } = &mut *surface_buffers;
+
+        queue
+            .dispatch_pending(&mut State)
+            .map_err(io::Error::other)?;
+        let is_free = |buffer: &Option<ShmBuffer>| {
+            buffer
+                .as_ref()
+                .is_none_or(|b| !b.busy.load(Ordering::Relaxed))
+        };
+        let back = loop {
+            if let Some(back) = buffers.iter().position(is_free) {
+                break back;
+            }
+            // The compositor is holding onto both buffers, so wait for it to let go of one.
+            queue
+                .blocking_dispatch(&mut State)
+                .map_err(io::Error::other)?;
+        };
+
+        let [first, second] = buffers;
+        let (back_buffer, other_buffer) = if back == 0 {
+            (first, second)
+        } else {
+            (second, first)
+        };
+        // What the surface is showing right now, unless the back buffer already has it.
+        let on_screen = match *front {
+            Some(front) if front != back => other_buffer.as_ref(),
+            _ => None,
+        };
+        let fresh = back_buffer.is_none();
+        if fresh {
+            *back_buffer =
+                Some(unsafe { ShmBuffer::new(&self.wl_shm, self.width, self.height, &qh)? });
+        }
+        let shm = back_buffer.as_mut().unwrap();
+        // Bring the buffer up to date before blitting over it; one that the surface has never
+        // shown starts out as a copy of the whole pixel buffer.
+        match on_screen {
+            Some(on_screen) => shm.bytes_mut().copy_from_slice(on_screen.bytes()),
+            None if fresh => shm.bytes_mut().copy_from_slice(&self.pixels),
+            None => {}
+        }
+
+        self.surface.attach(Some(&shm.buffer), 0, 0);
+        let row_len = self.row_len();
+        for &(src_pos, dst_pos, blit_size) in blits {
+            // Clip against both the source and destination, since they're the same size.
+            let bounds = Rect::new(0, 0, self.width, self.height);
+            let src =
+                Rect::new(src_pos.0, src_pos.1, blit_size.0, blit_size.1).intersection(&bounds);
+            let dst = Rect::new(dst_pos.0, dst_pos.1, src.width, src.height).intersection(&bounds);
+            if dst.is_empty() {
+                continue;
+            }
+            let len = dst.width as usize * BYTES_PER_PIXEL;
+            for y in 0..dst.height as usize {
+                let src = (src.y as usize + y) * row_len + src.x as usize * BYTES_PER_PIXEL;
+                let dst = (dst.y as usize + y) * row_len + dst.x as usize * BYTES_PER_PIXEL;
+                shm.bytes_mut()[dst..dst + len].copy_from_slice(&self.pixels[src..src + len]);
+            }
+
+            let (x, y) = (dst.x as i32, dst.y as i32);
+            let (width, height) = (dst.width as i32, dst.height as i32);
+            if self.surface.version() >= 4 {
+                self.surface.damage_buffer(x, y, width, height);
+            } else {
+                self.surface.damage(x, y, width, height);
+            }
+        }
+        self.surface.commit();
+        shm.busy.store(true, Ordering::Relaxed);
+        *front = Some(back);
+        self.conn.flush().map_err(io::Error::other)
+    }
+    pub unsafe fn resize(
+        &mut self,
+        width: u32,
+        height: u32,
+        preserve: bool,
+    ) -> Result<(), PixelBufferCreationError> {
+        let row_len = width as usize * BYTES_PER_PIXEL;
+        let len = row_len * height as usize;
+        let rows = if preserve { self.height.min(height) } else { 0 } as usize;
+        let old_row_len = self.row_len();
+        resize_rows(&mut self.pixels, old_row_len, row_len, rows, len);
+        self.width = width;
+        self.height = height;
+        // The surface buffers are the wrong size now, so the next blit starts over with new ones.
+        *self.surface_buffers.get_mut() = SurfaceBuffers::default();
+        Ok(())
+    }
+    pub fn bits_per_pixel(&self) -> usize {
+        BITS_PER_PIXEL
+    }
+
+    pub fn bytes_per_pixel(&self) -> usize {
+        BYTES_PER_PIXEL
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn row_len(&self) -> usize {
+        self.width() as usize * self.bytes_per_pixel()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.pixels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use raw_window_handle::{WaylandDisplayHandle, WaylandWindowHandle};
+    use wayland_client::protocol::wl_compositor::WlCompositor;
+
+    delegate_noop!(State: ignore WlSurface);
+    delegate_noop!(State: WlCompositor);
+
+    /// A surface on the compositor named by `$WAYLAND_DISPLAY`, e.g. a headless `weston`.
+    struct TestSurface {
+        conn: Connection,
+        _queue: EventQueue<State>,
+        surface: WlSurface,
+    }
+
+    impl TestSurface {
+        /// Panics if there's no compositor to connect to.
+        fn new() -> TestSurface {
+            let conn = Connection::connect_to_env()
+                .expect("no wayland compositor available on `$WAYLAND_DISPLAY`");
+            let (globals, queue) = registry_queue_init::<State>(&conn).unwrap();
+            let compositor: WlCompositor = globals.bind(&queue.handle(), 1..=4, ()).unwrap();
+            let surface = compositor.create_surface(&queue.handle(), ());
+            TestSurface {
+                conn,
+                _queue: queue,
+                surface,
+            }
+        }
+
+        fn window_handle(&self) -> WindowHandle<'_> {
+            let surface = NonNull::new(self.surface.id().as_ptr().cast()).unwrap();
+            let handle = WaylandWindowHandle::new(surface);
+            unsafe { WindowHandle::borrow_raw(RawWindowHandle::Wayland(handle)) }
+        }
+
+        fn display_handle(&self) -> DisplayHandle<'_> {
+            let display = NonNull::new(self.conn.backend().display_ptr().cast()).unwrap();
+            let handle = WaylandDisplayHandle::new(display);
+            unsafe { DisplayHandle::borrow_raw(RawDisplayHandle::Wayland(handle)) }
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a wayland compositor on `$WAYLAND_DISPLAY`"]
+    /// The purpose of this test is to verify that a `PixelBuffer` can be attached to and committed
+    /// on a surface owned by another connection object, including blits to a different position.
+    fn pixelbuffer_blit_foreign_surface() {
+        let test_surface = TestSurface::new();
+
+        unsafe {
+            let mut pb = PixelBuffer::new(
+                64,
+                64,
+                PixelBufferFormatType::BGRA,
+                test_surface.window_handle(),
+                test_surface.display_handle(),
+            )
+            .unwrap();
+            pb.bytes_mut().fill(0x7f);
+            pb.blit(test_surface.window_handle()).unwrap();
+            pb.blit_rect((8, 8), (8, 8), (16, 16), test_surface.window_handle())
+                .unwrap();
+            pb.bytes_mut()[..4].copy_from_slice(&[1, 2, 3, 255]);
+            pb.blit_rect((0, 0), (8, 8), (16, 16), test_surface.window_handle())
+                .unwrap();
+            // Blits from outside the pixel buffer are clipped away.
+            pb.blit_rect((64, 0), (0, 0), (1, 1), test_surface.window_handle())
+                .unwrap();
+            pb.blit_rect((u32::MAX, 0), (0, 0), (2, 2), test_surface.window_handle())
+                .unwrap();
+            test_surface.conn.roundtrip().unwrap();
+
+            // The offset blit landed on the surface at its destination, on top of what the
+            // earlier blits left there.
+            let surface_buffers = pb.surface_buffers.borrow();
+            let front = surface_buffers.front.unwrap();
+            let shm = surface_buffers.buffers[front].as_ref().unwrap();
+            let row_len = pb.row_len();
+            assert_eq!(shm.bytes()[..4], [0x7f; 4]);
+            assert_eq!(
+                shm.bytes()[8 * row_len + 8 * 4..][..8],
+                [1, 2, 3, 255, 0x7f, 0x7f, 0x7f, 0x7f]
+            );
+        }
+    }
+}
@@ -1,24 +1,73 @@
 use std::{
     io,
     os::raw::{c_char, c_int, c_uint, c_ulong},
+    ptr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, PoisonError,
+    },
 };
 
 use raw_window_handle::{DisplayHandle, RawDisplayHandle, RawWindowHandle, WindowHandle};
-use x11_dl::xlib::{Display, Visual, XGCValues, XImage, XWindowAttributes, Xlib, ZPixmap, GC};
+use x11_dl::{
+    xlib::{
+        self, Display, Visual, XErrorEvent, XGCValues, XImage, XWindowAttributes, Xlib, ZPixmap, GC,
+    },
+    xshm::{XShmSegmentInfo, Xext},
+};
+
+use self::visual::PixelLayout;
+use super::{copy_rows, fill_alpha};
+use crate::{
+    platform_impl::{relayout_rows, resize_rows},
+    PixelBufferCreationError, PixelBufferFormatType, Rect,
+};
 
-use crate::{PixelBufferCreationError, PixelBufferFormatSupported, PixelBufferFormatType};
+mod visual;
 
 pub struct PixelBuffer {
     width: u32,
     height: u32,
-    pixels: Vec<u8>,
-    ximage: *mut XImage,
+    format: PixelBufferFormatType,
+    pixels: Pixels,
+    image: Image,
     display: *mut Display,
     window: c_ulong,
+    visual: *mut Visual,
+    depth: c_uint,
     xlib: Xlib,
     gc: GC,
 }
 
+/// How the pixels get onto the window.
+enum Image {
+    /// The pixels are already laid out like the window's visual, so they're blitted straight from
+    /// an image that points at them.
+    Direct(*mut XImage),
+    /// The pixels are BGRA, and every blit converts them into a temporary image laid out like the
+    /// window's visual.
+    Converted(PixelLayout),
+}
+
+/// Backing storage for the pixel buffer, and for its `XImage` when it has one.
+enum Pixels {
+    /// Pixels live in client memory and are copied over the X connection by `XPutImage`.
+    Heap(Vec<u8>),
+    /// Pixels live in a shared memory segment that the X server reads from directly.
+    ///
+    /// The segment may be larger than the image, if the image has shrunk since it was allocated.
+    Shm(ShmSegment),
+}
+
+/// A SysV shared memory segment attached to both this process and the X server.
+struct ShmSegment {
+    xext: Xext,
+    display: *mut Display,
+    // `XShmCreateImage` keeps a pointer to this in `XImage::obdata`, so it needs a stable address.
+    info: Box<XShmSegmentInfo>,
+    len: usize,
+}
+
 fn get_window_and_display(
     window_handle: WindowHandle,
     display_handle: DisplayHandle,
@@ -27,25 +76,174 @@ fn get_window_and_display(
         if let RawDisplayHandle::Xlib(x_display_handle) = display_handle.as_raw() {
             return Some((
                 x_window_handle.window,
-                x_display_handle
-                    .display
-                    .expect("display handle none")
-                    .as_ptr() as *mut Display,
+                x_display_handle.display?.as_ptr() as *mut Display,
             ));
         }
     }
     None
 }
 
-impl PixelBufferFormatSupported for crate::BGRA {}
-impl PixelBufferFormatSupported for crate::BGR {}
-pub type NativeFormat = crate::BGRA;
+/// Gets the depth and visual of `window`.
+unsafe fn window_visual(
+    xlib: &Xlib,
+    display: *mut Display,
+    window: c_ulong,
+) -> Result<(c_uint, *mut Visual), PixelBufferCreationError> {
+    let mut xwa: XWindowAttributes = std::mem::zeroed();
+    if (xlib.XGetWindowAttributes)(display, window, &mut xwa) == 0 {
+        return Err(PixelBufferCreationError::AllocationFailed(
+            "couldn't get window attributes".to_owned(),
+        ));
+    }
+    Ok((xwa.depth as c_uint, xwa.visual))
+}
+
+/// Works out how images of `depth` and `visual` lay out their pixels.
+///
+/// Returns `None` for visuals we can't convert to, such as ones that use a color palette.
+unsafe fn pixel_layout(
+    xlib: &Xlib,
+    display: *mut Display,
+    visual: *mut Visual,
+    depth: c_uint,
+) -> Option<PixelLayout> {
+    let visual = &*visual;
+    if visual.class != xlib::TrueColor {
+        return None;
+    }
+    let mut count = 0;
+    let formats = (xlib.XListPixmapFormats)(display, &mut count);
+    if formats.is_null() {
+        return None;
+    }
+    let bits_per_pixel = std::slice::from_raw_parts(formats, count as usize)
+        .iter()
+        .find(|format| format.depth == depth as c_int)
+        .map(|format| format.bits_per_pixel as u32);
+    (xlib.XFree)(formats as *mut _);
+    PixelLayout::new(
+        depth,
+        bits_per_pixel?,
+        (xlib.XImageByteOrder)(display) == xlib::MSBFirst,
+        visual.red_mask as u32,
+        visual.green_mask as u32,
+        visual.blue_mask as u32,
+    )
+}
+
+/// Set by `trap_error_handler` when the server reports an error.
+static ERROR_TRAPPED: AtomicBool = AtomicBool::new(false);
+
+/// Held by `trap_errors` while its handler is installed.
+static TRAP_LOCK: Mutex<()> = Mutex::new(());
+
+unsafe extern "C" fn trap_error_handler(_: *mut Display, _: *mut XErrorEvent) -> c_int {
+    ERROR_TRAPPED.store(true, Ordering::SeqCst);
+    0
+}
+
+/// Runs `f`, returning whether the server reported an error for any of the requests it made.
+///
+/// The server reports errors asynchronously, so this waits for `f`'s requests to round-trip. Xlib's
+/// default handler would kill the process instead.
+///
+/// Xlib's error handler is process-global, so pixel buffers on different threads take turns;
+/// otherwise one could restore the other's handler, or clear `ERROR_TRAPPED`, halfway through.
+unsafe fn trap_errors<T>(xlib: &Xlib, display: *mut Display, f: impl FnOnce() -> T) -> (T, bool) {
+    let _lock = TRAP_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    ERROR_TRAPPED.store(false, Ordering::SeqCst);
+    let prev_handler = (xlib.XSetErrorHandler)(Some(trap_error_handler));
+    let result = f();
+    (xlib.XSync)(display, xlib::False);
+    (xlib.XSetErrorHandler)(prev_handler);
+    (result, ERROR_TRAPPED.load(Ordering::SeqCst))
+}
+
+/// Allocates shared memory for `len` bytes of `format` pixels, if the server can read them from
+/// there.
+///
+/// The server pads rows of shared images to 32 bits, which rows of 16-bit pixels aren't, so those
+/// always go through `XPutImage` instead.
+unsafe fn shm_segment(
+    xlib: &Xlib,
+    display: *mut Display,
+    format: PixelBufferFormatType,
+    len: usize,
+) -> Option<ShmSegment> {
+    if format.bytes_per_pixel() != 4 {
+        return None;
+    }
+    ShmSegment::new(xlib, display, len)
+}
+
+impl ShmSegment {
+    /// Allocates a segment of `len` bytes and attaches it to the X server.
+    ///
+    /// Returns `None` if the server doesn't support MIT-SHM or can't access the segment (e.g. the
+    /// display is remote), in which case the caller should fall back to `XPutImage`.
+    unsafe fn new(xlib: &Xlib, display: *mut Display, len: usize) -> Option<ShmSegment> {
+        if len == 0 {
+            return None;
+        }
+        let xext = Xext::open().ok()?;
+        if (xext.XShmQueryExtension)(display) == xlib::False {
+            return None;
+        }
+
+        let shmid = libc::shmget(libc::IPC_PRIVATE, len, libc::IPC_CREAT | 0o600);
+        if shmid < 0 {
+            return None;
+        }
+        let shmaddr = libc::shmat(shmid, ptr::null(), 0);
+        if shmaddr as isize == -1 {
+            libc::shmctl(shmid, libc::IPC_RMID, ptr::null_mut());
+            return None;
+        }
+        let mut info = Box::new(XShmSegmentInfo {
+            shmseg: 0,
+            shmid,
+            shmaddr: shmaddr as *mut c_char,
+            readOnly: xlib::False,
+        });
+
+        let (_, attach_failed) = trap_errors(xlib, display, || {
+            (xext.XShmAttach)(display, &mut *info);
+        });
+
+        // Mark the segment for removal now; the kernel frees it once both we and the server have
+        // detached, even if the process dies without running `Drop`.
+        libc::shmctl(shmid, libc::IPC_RMID, ptr::null_mut());
+        if attach_failed {
+            libc::shmdt(shmaddr);
+            return None;
+        }
+
+        Some(ShmSegment {
+            xext,
+            display,
+            info,
+            len,
+        })
+    }
+
+    fn bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.info.shmaddr as *const u8, self.len) }
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.info.shmaddr as *mut u8, self.len) }
+    }
+}
 
-const BYTES_PER_PIXEL: usize = 4;
-const BITS_PER_PIXEL: usize = BYTES_PER_PIXEL * 8;
+impl Drop for ShmSegment {
+    fn drop(&mut self) {
+        unsafe {
+            (self.xext.XShmDetach)(self.display, &mut *self.info);
+            libc::shmdt(self.info.shmaddr as *const _);
+        }
+    }
+}
 
-// TODO(wathiede): this implementation uses xlib XImage to put pixels into a window.  There is
-// likely faster ways using MIT-SHM.  Investigate that.
 impl PixelBuffer {
     pub unsafe fn new(
         width: u32,
@@ -54,54 +252,122 @@ impl PixelBuffer {
         window_handle: WindowHandle,
         display_handle: DisplayHandle,
     ) -> Result<PixelBuffer, PixelBufferCreationError> {
-        if format != PixelBufferFormatType::BGRA {
-            return Err(PixelBufferCreationError::FormatNotSupported);
-        }
-        let x = Xlib::open().expect("failed to open Xlib library");
+        let x =
+            Xlib::open().map_err(|e| PixelBufferCreationError::LibraryNotFound(e.to_string()))?;
         let (window, display) = get_window_and_display(window_handle, display_handle)
-            .expect("handle wasn't an XlibHandle");
-        let pixels = vec![255; (width * height) as usize * BYTES_PER_PIXEL];
-        let (depth, visual) = {
-            let mut xwa: XWindowAttributes = std::mem::zeroed();
-            (x.XGetWindowAttributes)(display, window, &mut xwa);
-            (xwa.depth as c_uint, xwa.visual)
+            .ok_or(PixelBufferCreationError::HandleNotSupported)?;
+        let len = (width * height) as usize * format.bytes_per_pixel();
+        let (depth, visual) = window_visual(&x, display, window)?;
+        let converted_to = match pixel_layout(&x, display, visual, depth) {
+            Some(layout) if layout.format() == Some(format) => None,
+            Some(layout) if format == PixelBufferFormatType::BGRA => Some(layout),
+            _ => return Err(PixelBufferCreationError::FormatNotSupported),
         };
-        let gc = (x.XCreateGC)(display, window, 0, 0 as *mut XGCValues);
-        let format = ZPixmap;
-        let offset = 0;
-        let data = pixels.as_ptr();
+        let gc = (x.XCreateGC)(display, window, 0, ptr::null_mut::<XGCValues>());
+        if gc.is_null() {
+            return Err(PixelBufferCreationError::AllocationFailed(
+                "couldn't create GC".to_owned(),
+            ));
+        }
+        if let Some(layout) = converted_to {
+            return Ok(PixelBuffer {
+                width,
+                height,
+                format,
+                pixels: Pixels::Heap(vec![255; len]),
+                image: Image::Converted(layout),
+                xlib: x,
+                display,
+                window,
+                visual,
+                depth,
+                gc,
+            });
+        }
+
         let width = width as c_uint;
         let height = height as c_uint;
-        let bitmap_pad = 32;
-        let bytes_per_line = 0;
-        let ximage = (x.XCreateImage)(
-            display,
-            visual as *mut Visual,
-            depth,
-            format,
-            offset,
-            data as *mut c_char,
-            width,
-            height,
-            bitmap_pad,
-            bytes_per_line,
-        );
+
+        let shm_image = shm_segment(&x, display, format, len).and_then(|mut segment| {
+            let ximage = (segment.xext.XShmCreateImage)(
+                display,
+                visual,
+                depth,
+                ZPixmap,
+                segment.info.shmaddr,
+                &mut *segment.info,
+                width,
+                height,
+            );
+            if ximage.is_null() {
+                return None;
+            }
+            segment.bytes_mut().fill(255);
+            Some((Pixels::Shm(segment), ximage))
+        });
+        let (pixels, ximage) = match shm_image {
+            Some(shm_image) => shm_image,
+            None => {
+                let pixels = vec![255; len];
+                let offset = 0;
+                let data = pixels.as_ptr();
+                let bitmap_pad = format.bytes_per_pixel() as c_int * 8;
+                let bytes_per_line = width as c_int * format.bytes_per_pixel() as c_int;
+                let ximage = (x.XCreateImage)(
+                    display,
+                    visual,
+                    depth,
+                    ZPixmap,
+                    offset,
+                    data as *mut c_char,
+                    width,
+                    height,
+                    bitmap_pad,
+                    bytes_per_line,
+                );
+                (Pixels::Heap(pixels), ximage)
+            }
+        };
         if ximage.is_null() {
-            // TODO(wathiede): better error handling here and throughout.
-            panic!("Couldn't create XImage");
+            (x.XFreeGC)(display, gc);
+            return Err(PixelBufferCreationError::AllocationFailed(
+                "couldn't create XImage".to_owned(),
+            ));
         }
-        // TODO(wathiede): cleanup X resources when PixelBuffer is dropped.
         Ok(PixelBuffer {
             width,
             height,
+            format,
             pixels,
-            ximage,
+            image: Image::Direct(ximage),
             xlib: x,
             display,
             window,
+            visual,
+            depth,
             gc,
         })
     }
+    /// The formats `new` accepts for `window_handle`, with the ones that can be blitted without
+    /// converting first.
+    pub unsafe fn supported_formats(
+        window_handle: WindowHandle,
+        display_handle: DisplayHandle,
+    ) -> Result<Vec<PixelBufferFormatType>, PixelBufferCreationError> {
+        let x =
+            Xlib::open().map_err(|e| PixelBufferCreationError::LibraryNotFound(e.to_string()))?;
+        let (window, display) = get_window_and_display(window_handle, display_handle)
+            .ok_or(PixelBufferCreationError::HandleNotSupported)?;
+        let (depth, visual) = window_visual(&x, display, window)?;
+        let mut formats = Vec::new();
+        if let Some(layout) = pixel_layout(&x, display, visual, depth) {
+            formats.extend(layout.format());
+            if !formats.contains(&PixelBufferFormatType::BGRA) {
+                formats.push(PixelBufferFormatType::BGRA);
+            }
+        }
+        Ok(formats)
+    }
     pub unsafe fn blit(&self, handle: WindowHandle) -> io::Result<()> {
         self.blit_rect((0, 0), (0, 0), (self.width(), self.height()), handle)
     }
@@ -114,30 +380,295 @@ impl PixelBuffer {
     ) -> io::Result<()> {
         // TODO(wathiede): do we need to check the incoming handle matches our existing
         // display/window/gc and rebuild ximage if it's changed?
+        self.put_image(src_pos, dst_pos, blit_size, &mut Vec::new())?;
+        self.sync();
+        Ok(())
+    }
+    pub unsafe fn blit_rects(&self, rects: &[Rect], _handle: WindowHandle) -> io::Result<()> {
+        let mut staging = Vec::new();
+        for rect in rects {
+            let pos = (rect.x, rect.y);
+            self.put_image(pos, pos, (rect.width, rect.height), &mut staging)?;
+        }
+        self.sync();
+        Ok(())
+    }
+    pub unsafe fn capture_from(&mut self, rect: Rect, _handle: WindowHandle) -> io::Result<()> {
+        let mut xwa: XWindowAttributes = std::mem::zeroed();
+        if (self.xlib.XGetWindowAttributes)(self.display, self.window, &mut xwa) == 0 {
+            return Err(io::Error::other("couldn't get window attributes"));
+        }
+        if xwa.map_state != xlib::IsViewable {
+            return Err(io::Error::other(
+                "can't capture a window that isn't viewable",
+            ));
+        }
+        let window = Rect::new(0, 0, xwa.width as u32, xwa.height as u32);
+        let rect = rect.intersection(&window);
+        if rect.is_empty() {
+            return Ok(());
+        }
+
+        let all_planes = !0;
+        // Most likely part of the window is off-screen, which X can't read back.
+        let read_failed = || io::Error::other("failed to read back window contents");
+        let layout = match self.image {
+            Image::Direct(ximage) => {
+                // Our image already has the window's depth and visual, so the server's pixels can
+                // be copied straight into it.
+                let (ximage, failed) = trap_errors(&self.xlib, self.display, || {
+                    (self.xlib.XGetSubImage)(
+                        self.display,
+                        self.window,
+                        rect.x as c_int,
+                        rect.y as c_int,
+                        rect.width,
+                        rect.height,
+                        all_planes,
+                        ZPixmap,
+                        ximage,
+                        rect.x as c_int,
+                        rect.y as c_int,
+                    )
+                });
+                if failed || ximage.is_null() {
+                    return Err(read_failed());
+                }
+                if (*ximage).depth < 32 {
+                    let (row_len, format) = (self.row_len(), self.format);
+                    fill_alpha(self.bytes_mut(), row_len, format, rect);
+                }
+                return Ok(());
+            }
+            Image::Converted(layout) => layout,
+        };
+
+        let (ximage, failed) = trap_errors(&self.xlib, self.display, || {
+            (self.xlib.XGetImage)(
+                self.display,
+                self.window,
+                rect.x as c_int,
+                rect.y as c_int,
+                rect.width,
+                rect.height,
+                all_planes,
+                ZPixmap,
+            )
+        });
+        if ximage.is_null() {
+            return Err(read_failed());
+        } else if failed {
+            (self.xlib.XDestroyImage)(ximage);
+            return Err(read_failed());
+        }
+        let bytes_per_line = (*ximage).bytes_per_line as usize;
+        let data = std::slice::from_raw_parts(
+            (*ximage).data as *const u8,
+            bytes_per_line * rect.height as usize,
+        );
+        let row_len = self.row_len();
+        let dst_rows = self.bytes_mut()[rect.y as usize * row_len..].chunks_mut(row_len);
+        for (src, dst) in data.chunks(bytes_per_line).zip(dst_rows) {
+            let dst = &mut dst[rect.x as usize * 4..];
+            layout.unpack_bgra(src, &mut dst[..rect.width as usize * 4]);
+        }
+        (self.xlib.XDestroyImage)(ximage);
+        Ok(())
+    }
+    /// Queues up a request to copy part of the image onto the window, using `staging` to hold
+    /// converted pixels if the image isn't laid out like the window's visual.
+    unsafe fn put_image(
+        &self,
+        src_pos: (u32, u32),
+        dst_pos: (u32, u32),
+        blit_size: (u32, u32),
+        staging: &mut Vec<u8>,
+    ) -> io::Result<()> {
+        let ximage = match self.image {
+            Image::Direct(ximage) => ximage,
+            Image::Converted(layout) => {
+                return self.put_converted(layout, src_pos, dst_pos, blit_size, staging)
+            }
+        };
+        match &self.pixels {
+            Pixels::Heap(_) => {
+                (self.xlib.XPutImage)(
+                    self.display,
+                    self.window,
+                    self.gc,
+                    ximage,
+                    src_pos.0 as c_int,
+                    src_pos.1 as c_int,
+                    dst_pos.0 as c_int,
+                    dst_pos.1 as c_int,
+                    blit_size.0 as c_uint,
+                    blit_size.1 as c_uint,
+                );
+            }
+            Pixels::Shm(segment) => {
+                let send_event = xlib::False;
+                (segment.xext.XShmPutImage)(
+                    self.display,
+                    self.window,
+                    self.gc,
+                    ximage,
+                    src_pos.0 as c_int,
+                    src_pos.1 as c_int,
+                    dst_pos.0 as c_int,
+                    dst_pos.1 as c_int,
+                    blit_size.0 as c_uint,
+                    blit_size.1 as c_uint,
+                    send_event,
+                );
+            }
+        }
+        Ok(())
+    }
+    /// Converts part of the image into `staging` and queues up a request to copy it onto the
+    /// window.
+    unsafe fn put_converted(
+        &self,
+        layout: PixelLayout,
+        src_pos: (u32, u32),
+        dst_pos: (u32, u32),
+        blit_size: (u32, u32),
+        staging: &mut Vec<u8>,
+    ) -> io::Result<()> {
+        // `XPutImage` clips the source rectangle to the image, so we have to as well.
+        let bounds = Rect::new(0, 0, self.width, self.height);
+        let src = Rect::new(src_pos.0, src_pos.1, blit_size.0, blit_size.1).intersection(&bounds);
+        if src.is_empty() {
+            return Ok(());
+        }
+        let bitmap_pad = 32;
+        let bytes_per_line = 0;
+        let ximage = (self.xlib.XCreateImage)(
+            self.display,
+            self.visual,
+            self.depth,
+            ZPixmap,
+            0,
+            ptr::null_mut(),
+            src.width,
+            src.height,
+            bitmap_pad,
+            bytes_per_line,
+        );
+        if ximage.is_null() {
+            return Err(io::Error::other("couldn't create XImage"));
+        }
+
+        let bytes_per_line = (*ximage).bytes_per_line as usize;
+        staging.clear();
+        staging.resize(bytes_per_line * src.height as usize, 0);
+        let row_len = self.row_len();
+        let src_rows = self.bytes()[src.y as usize * row_len..].chunks(row_len);
+        // Converted pixel buffers are always BGRA, so 4 bytes per pixel.
+        for (src_row, dst) in src_rows.zip(staging.chunks_mut(bytes_per_line)) {
+            let src_row = &src_row[src.x as usize * 4..];
+            layout.pack_bgra(&src_row[..src.width as usize * 4], dst);
+        }
+
+        (*ximage).data = staging.as_mut_ptr() as *mut c_char;
         (self.xlib.XPutImage)(
             self.display,
             self.window,
             self.gc,
-            self.ximage,
-            src_pos.0 as c_int,
-            src_pos.1 as c_int,
+            ximage,
+            0,
+            0,
             dst_pos.0 as c_int,
             dst_pos.1 as c_int,
-            blit_size.0 as c_uint,
-            blit_size.1 as c_uint,
+            src.width,
+            src.height,
         );
+        // Xlib has copied the pixels into its request buffer by now, and they belong to
+        // `staging` anyway.
+        (*ximage).data = ptr::null_mut();
+        (self.xlib.XDestroyImage)(ximage);
+        Ok(())
+    }
+    /// Waits for the server to finish with every queued request.
+    unsafe fn sync(&self) {
+        // With MIT-SHM the server reads the pixels asynchronously, so this also guarantees the
+        // caller can't scribble over them mid-blit.
         let discard = 0;
         (self.xlib.XSync)(self.display, discard);
         //(self.xlib.XFlush)(self.display);
+    }
+    pub unsafe fn resize(
+        &mut self,
+        width: u32,
+        height: u32,
+        preserve: bool,
+    ) -> Result<(), PixelBufferCreationError> {
+        let row_len = width as usize * self.bytes_per_pixel();
+        let len = row_len * height as usize;
+        let rows = if preserve { self.height.min(height) } else { 0 } as usize;
+        let old_row_len = self.row_len();
 
+        let fits = match &self.pixels {
+            Pixels::Heap(pixels) => len <= pixels.capacity(),
+            Pixels::Shm(segment) => len <= segment.len,
+        };
+        if fits {
+            match &mut self.pixels {
+                Pixels::Heap(pixels) => resize_rows(pixels, old_row_len, row_len, rows, len),
+                Pixels::Shm(segment) => {
+                    relayout_rows(segment.bytes_mut(), old_row_len, row_len, rows)
+                }
+            }
+        } else {
+            // We need a new allocation anyway, so take the opportunity to (re)try MIT-SHM. That's
+            // no use if the pixels get converted before they're sent, though.
+            let segment = match self.image {
+                Image::Direct(_) => shm_segment(&self.xlib, self.display, self.format, len),
+                Image::Converted(_) => None,
+            };
+            let mut pixels = match segment {
+                Some(segment) => Pixels::Shm(segment),
+                None => Pixels::Heap(vec![255; len]),
+            };
+            let dst = match &mut pixels {
+                Pixels::Heap(pixels) => &mut pixels[..],
+                Pixels::Shm(segment) => segment.bytes_mut(),
+            };
+            copy_rows(self.bytes(), old_row_len, dst, row_len, rows);
+            self.pixels = pixels;
+        }
+
+        if let Image::Direct(ximage) = self.image {
+            // Point the existing image at the new pixels rather than creating a new one.
+            let ximage = &mut *ximage;
+            ximage.width = width as c_int;
+            ximage.height = height as c_int;
+            ximage.bytes_per_line = row_len as c_int;
+            match &mut self.pixels {
+                Pixels::Heap(pixels) => {
+                    ximage.data = pixels.as_mut_ptr() as *mut c_char;
+                    ximage.obdata = ptr::null_mut();
+                }
+                Pixels::Shm(segment) => {
+                    ximage.data = segment.info.shmaddr;
+                    ximage.obdata = &mut *segment.info as *mut XShmSegmentInfo as *mut c_char;
+                }
+            }
+            if (self.xlib.XInitImage)(ximage) == 0 {
+                return Err(PixelBufferCreationError::AllocationFailed(
+                    "couldn't resize XImage".to_owned(),
+                ));
+            }
+        }
+        self.width = width;
+        self.height = height;
         Ok(())
     }
     pub fn bits_per_pixel(&self) -> usize {
-        BITS_PER_PIXEL
+        self.bytes_per_pixel() * 8
     }
 
     pub fn bytes_per_pixel(&self) -> usize {
-        BYTES_PER_PIXEL
+        self.format.bytes_per_pixel()
     }
 
     pub fn width(&self) -> u32 {
@@ -151,32 +682,297 @@ impl PixelBuffer {
     pub fn height(&self) -> u32 {
         self.height
     }
-    pub fn row(&self, row: u32) -> Option<&[u8]> {
-        let start = row as usize * self.row_len();
-        let end = (row + 1) as usize * self.row_len();
-        if (0..self.pixels.len()).contains(&end) {
-            return Some(&self.pixels[start..end]);
+
+    pub fn bytes(&self) -> &[u8] {
+        let len = self.row_len() * self.height as usize;
+        match &self.pixels {
+            Pixels::Heap(pixels) => pixels,
+            Pixels::Shm(segment) => &segment.bytes()[..len],
         }
-        None
     }
 
-    pub fn row_mut(&mut self, row: u32) -> Option<&mut [u8]> {
-        let start = row as usize * self.row_len();
-        let end = (row + 1) as usize * self.row_len();
-        if (0..self.pixels.len()).contains(&end) {
-            return Some(&mut self.pixels[start..end]);
+    pub fn bytes_mut(&mut self) -> &mut [u8] {
+        let len = self.row_len() * self.height as usize;
+        match &mut self.pixels {
+            Pixels::Heap(pixels) => pixels,
+            Pixels::Shm(segment) => &mut segment.bytes_mut()[..len],
         }
-        None
     }
+}
 
-    pub fn rows<'a>(&'a self) -> impl ExactSizeIterator + DoubleEndedIterator<Item = &'a [u8]> {
-        self.pixels.chunks(self.row_len())
+impl Drop for PixelBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            if let Image::Direct(ximage) = self.image {
+                // `XDestroyImage` frees `data` and `obdata` along with the image, but those are
+                // owned by `pixels`.
+                (*ximage).data = ptr::null_mut();
+                (*ximage).obdata = ptr::null_mut();
+                (self.xlib.XDestroyImage)(ximage);
+            }
+            (self.xlib.XFreeGC)(self.display, self.gc);
+            // Detach the segment now, rather than after this function returns, so the flush
+            // below sends that request as well.
+            self.pixels = Pixels::Heap(Vec::new());
+            (self.xlib.XFlush)(self.display);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use raw_window_handle::{XlibDisplayHandle, XlibWindowHandle};
+    use std::ptr::NonNull;
+    use x11rb::{protocol::res::ConnectionExt as _, rust_connection::RustConnection};
+    // Tests need the X server named by `$DISPLAY` (e.g. an `Xvfb` instance), so they only run with
+    // `cargo test -- --ignored`. They share it, so run them one at a time.
+    use serial_test::serial;
+
+    /// An unmapped window on the display named by `$DISPLAY`.
+    struct TestWindow {
+        xlib: Xlib,
+        display: *mut Display,
+        window: c_ulong,
+    }
+
+    impl TestWindow {
+        /// Panics if there's no X server to connect to.
+        fn new() -> TestWindow {
+            unsafe {
+                let xlib = Xlib::open().expect("couldn't load Xlib");
+                let display = (xlib.XOpenDisplay)(ptr::null());
+                assert!(!display.is_null(), "no X server available on `$DISPLAY`");
+                let root = (xlib.XDefaultRootWindow)(display);
+                let window = (xlib.XCreateSimpleWindow)(display, root, 0, 0, 256, 256, 0, 0, 0);
+                TestWindow {
+                    xlib,
+                    display,
+                    window,
+                }
+            }
+        }
+
+        fn window_handle(&self) -> WindowHandle<'_> {
+            let handle = XlibWindowHandle::new(self.window);
+            unsafe { WindowHandle::borrow_raw(RawWindowHandle::Xlib(handle)) }
+        }
+
+        fn display_handle(&self) -> DisplayHandle<'_> {
+            let screen = unsafe { (self.xlib.XDefaultScreen)(self.display) };
+            let handle = XlibDisplayHandle::new(NonNull::new(self.display as *mut _), screen);
+            unsafe { DisplayHandle::borrow_raw(RawDisplayHandle::Xlib(handle)) }
+        }
+    }
+
+    impl Drop for TestWindow {
+        fn drop(&mut self) {
+            unsafe {
+                (self.xlib.XDestroyWindow)(self.display, self.window);
+                (self.xlib.XCloseDisplay)(self.display);
+            }
+        }
     }
 
-    pub fn rows_mut<'a>(
-        &'a mut self,
-    ) -> impl ExactSizeIterator + DoubleEndedIterator<Item = &'a mut [u8]> {
-        let chunk_size = self.row_len();
-        self.pixels.chunks_mut(chunk_size)
+    #[test]
+    #[ignore = "needs an X server on `$DISPLAY`"]
+    #[serial]
+    /// The purpose of this test is to verify that `PixelBuffer` presents through MIT-SHM whenever
+    /// the server advertises it.
+    fn pixelbuffer_blit_shm() {
+        let test_window = TestWindow::new();
+
+        unsafe {
+            let mut pb = PixelBuffer::new(
+                64,
+                64,
+                PixelBufferFormatType::BGRA,
+                test_window.window_handle(),
+                test_window.display_handle(),
+            )
+            .unwrap();
+            let has_shm = Xext::open()
+                .map(|xext| (xext.XShmQueryExtension)(test_window.display) != xlib::False)
+                .unwrap_or(false);
+            assert_eq!(has_shm, matches!(pb.pixels, Pixels::Shm(_)));
+
+            pb.bytes_mut().fill(0x7f);
+            pb.blit(test_window.window_handle()).unwrap();
+            assert!(pb.bytes().iter().all(|&b| b == 0x7f));
+        }
+    }
+
+    /// Returns the number of server-side resources (windows, GCs, SHM segments, ...) owned by the
+    /// client that created `test_window`.
+    ///
+    /// Queried over a second connection, since the X-Resource extension isn't part of Xlib.
+    fn x_resource_count(test_window: &TestWindow) -> u32 {
+        unsafe {
+            (test_window.xlib.XSync)(test_window.display, xlib::False);
+        }
+        let (conn, _) = RustConnection::connect(None).expect("failed to connect to X server");
+        conn.res_query_client_resources(test_window.window as u32)
+            .expect("failed to send X-Resource request")
+            .reply()
+            .expect("X server doesn't support X-Resource")
+            .types
+            .iter()
+            .map(|ty| ty.count)
+            .sum()
+    }
+
+    #[test]
+    #[ignore = "needs an X server on `$DISPLAY`"]
+    #[serial]
+    /// The purpose of this test is to verify that `PixelBuffer::new` doesn't leak any resources.
+    ///
+    /// The test creates a new `PixelBuffer`, blits it, and drops it again. It is expected that
+    /// the X server's resource count for our client stays the same across this test.
+    fn pixelbuffer_new_resource_leaks() {
+        let test_window = TestWindow::new();
+        let res_count_base = x_resource_count(&test_window);
+
+        // Perform test(s).
+        unsafe {
+            let pb = PixelBuffer::new(
+                256,
+                256,
+                PixelBufferFormatType::BGRA,
+                test_window.window_handle(),
+                test_window.display_handle(),
+            )
+            .unwrap();
+            pb.blit(test_window.window_handle()).unwrap();
+            assert!(x_resource_count(&test_window) > res_count_base);
+        } // <- drop PixelBuffer and release resources
+
+        // Compare resource count at test end.
+        let res_count_current = x_resource_count(&test_window);
+        assert_eq!(
+            res_count_base, res_count_current,
+            "Expected X resource count: {}; observed X resource count: {}",
+            res_count_base, res_count_current
+        );
+    }
+
+    #[test]
+    #[ignore = "needs an X server on `$DISPLAY`"]
+    #[serial]
+    /// The purpose of this test is to verify that `PixelBuffer::resize` keeps the overlapping
+    /// pixels and doesn't allocate any more server-side resources than a fresh `PixelBuffer`.
+    fn pixelbuffer_resize() {
+        let test_window = TestWindow::new();
+
+        unsafe {
+            let mut pb = PixelBuffer::new(
+                32,
+                32,
+                PixelBufferFormatType::BGRA,
+                test_window.window_handle(),
+                test_window.display_handle(),
+            )
+            .unwrap();
+            pb.bytes_mut().fill(0x7f);
+            pb.blit(test_window.window_handle()).unwrap();
+            let res_count = x_resource_count(&test_window);
+
+            // Grow past the original allocation, then shrink back into it.
+            for &(width, height) in &[(128, 96), (16, 8), (0, 0), (64, 64)] {
+                pb.resize(width, height, true).unwrap();
+                assert_eq!(pb.bytes().len(), (width * height) as usize * 4);
+                if let Image::Direct(ximage) = pb.image {
+                    assert_eq!((*ximage).width, width as c_int);
+                    assert_eq!((*ximage).bytes_per_line, width as c_int * 4);
+                }
+                pb.blit(test_window.window_handle()).unwrap();
+                assert_eq!(x_resource_count(&test_window), res_count);
+            }
+
+            pb.resize(16, 8, true).unwrap();
+            pb.bytes_mut().fill(0x7f);
+            pb.resize(24, 12, true).unwrap();
+            for (y, row) in pb.bytes().chunks(pb.row_len()).enumerate() {
+                if y < 8 {
+                    assert!(row[..16 * 4].iter().all(|&b| b == 0x7f));
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[ignore = "needs an X server on `$DISPLAY`"]
+    #[serial]
+    /// The purpose of this test is to verify that every format reported by
+    /// `PixelBuffer::supported_formats` can actually be created and blitted, and that others
+    /// can't.
+    fn pixelbuffer_supported_formats() {
+        let test_window = TestWindow::new();
+
+        unsafe {
+            let formats = PixelBuffer::supported_formats(
+                test_window.window_handle(),
+                test_window.display_handle(),
+            )
+            .unwrap();
+            assert!(formats.contains(&PixelBufferFormatType::BGRA));
+            for &format in &[
+                PixelBufferFormatType::BGR,
+                PixelBufferFormatType::BGRA,
+                PixelBufferFormatType::RGB,
+                PixelBufferFormatType::RGBA,
+            ] {
+                let pb = PixelBuffer::new(
+                    16,
+                    16,
+                    format,
+                    test_window.window_handle(),
+                    test_window.display_handle(),
+                );
+                match pb {
+                    Ok(pb) => {
+                        assert!(formats.contains(&format), "{:?}", format);
+                        pb.blit(test_window.window_handle()).unwrap();
+                    }
+                    Err(PixelBufferCreationError::FormatNotSupported) => {
+                        assert!(!formats.contains(&format), "{:?}", format)
+                    }
+                    Err(e) => panic!("{}", e),
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[ignore = "needs an X server on `$DISPLAY`"]
+    #[serial]
+    /// The purpose of this test is to verify that blitted pixels can be read back from the
+    /// window with `PixelBuffer::capture_from`.
+    fn pixelbuffer_capture_from() {
+        let test_window = TestWindow::new();
+
+        unsafe {
+            (test_window.xlib.XMapWindow)(test_window.display, test_window.window);
+            (test_window.xlib.XSync)(test_window.display, xlib::False);
+
+            let mut pb = PixelBuffer::new(
+                64,
+                64,
+                PixelBufferFormatType::BGRA,
+                test_window.window_handle(),
+                test_window.display_handle(),
+            )
+            .unwrap();
+            for (i, pixel) in pb.bytes_mut().chunks_mut(4).enumerate() {
+                pixel.copy_from_slice(&[i as u8, (i / 64) as u8, 0x55, 255]);
+            }
+            pb.blit(test_window.window_handle()).unwrap();
+            let expected = pb.bytes().to_vec();
+
+            pb.bytes_mut().fill(0);
+            pb.capture_from(Rect::new(0, 0, 64, 64), test_window.window_handle())
+                .unwrap();
+            assert_eq!(pb.bytes(), &expected[..]);
+        }
     }
 }
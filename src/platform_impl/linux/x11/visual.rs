@@ -0,0 +1,252 @@
+use crate::PixelBufferFormatType;
+
+/// How a TrueColor visual lays out the pixels of a `ZPixmap` image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PixelLayout {
+    pub bits_per_pixel: u32,
+    /// Whether multi-byte pixels are stored most significant byte first.
+    pub msb_first: bool,
+    pub red_mask: u32,
+    pub green_mask: u32,
+    pub blue_mask: u32,
+    /// The bits of the visual's depth that aren't used by any color channel, which ARGB visuals
+    /// use for alpha.
+    pub alpha_mask: u32,
+}
+
+impl PixelLayout {
+    /// Returns `None` if pixels of this layout can't be converted to and from, such as visuals
+    /// without color masks or with pixels that don't fill a whole number of bytes.
+    pub fn new(
+        depth: u32,
+        bits_per_pixel: u32,
+        msb_first: bool,
+        red_mask: u32,
+        green_mask: u32,
+        blue_mask: u32,
+    ) -> Option<PixelLayout> {
+        let rgb_mask = red_mask | green_mask | blue_mask;
+        if red_mask == 0 || green_mask == 0 || blue_mask == 0 {
+            return None;
+        }
+        if !matches!(bits_per_pixel, 8 | 16 | 24 | 32) || depth == 0 || depth > bits_per_pixel {
+            return None;
+        }
+        let depth_mask = (!0u64 >> (64 - depth)) as u32;
+        Some(PixelLayout {
+            bits_per_pixel,
+            msb_first,
+            red_mask,
+            green_mask,
+            blue_mask,
+            alpha_mask: depth_mask & !rgb_mask,
+        })
+    }
+
+    fn bytes_per_pixel(&self) -> usize {
+        self.bits_per_pixel as usize / 8
+    }
+
+    /// The pixel buffer format whose bytes are laid out exactly like this, if any.
+    ///
+    /// Buffers of that format can be handed to the server as-is.
+    pub fn format(&self) -> Option<PixelBufferFormatType> {
+        // Packed formats are native-endian, so they only match servers with the same byte order.
+        let native_order = self.msb_first == cfg!(target_endian = "big");
+        let masks = (
+            self.red_mask,
+            self.green_mask,
+            self.blue_mask,
+            self.alpha_mask,
+        );
+        match (self.bits_per_pixel, masks) {
+            (16, (0xf800, 0x07e0, 0x001f, 0)) if native_order => {
+                return Some(PixelBufferFormatType::RGB565)
+            }
+            (16, (0x7c00, 0x03e0, 0x001f, 0)) if native_order => {
+                return Some(PixelBufferFormatType::RGB555)
+            }
+            (32, (0x3ff0_0000, 0x000f_fc00, 0x0000_03ff, 0 | 0xc000_0000)) if native_order => {
+                return Some(PixelBufferFormatType::ARGB2101010)
+            }
+            (32, _) => (),
+            _ => return None,
+        }
+        // The index of the byte a mask covers, if it covers exactly one whole byte.
+        let byte = |mask: u32| match mask {
+            0xff | 0xff00 | 0xff_0000 | 0xff00_0000 => {
+                let lsb_index = mask.trailing_zeros() / 8;
+                Some(if self.msb_first {
+                    3 - lsb_index
+                } else {
+                    lsb_index
+                })
+            }
+            _ => None,
+        };
+        if self.alpha_mask != 0 && byte(self.alpha_mask) != Some(3) {
+            return None;
+        }
+        match (
+            byte(self.red_mask)?,
+            byte(self.green_mask)?,
+            byte(self.blue_mask)?,
+        ) {
+            (2, 1, 0) => Some(PixelBufferFormatType::BGRA),
+            (0, 1, 2) => Some(PixelBufferFormatType::RGBA),
+            _ => None,
+        }
+    }
+
+    /// Converts a row of BGRA pixels into this layout.
+    ///
+    /// `dst` must be at least `bits_per_pixel / 8` bytes per pixel in `src`.
+    pub fn pack_bgra(&self, src: &[u8], dst: &mut [u8]) {
+        let bytes_per_pixel = self.bytes_per_pixel();
+        for (src, dst) in src
+            .chunks_exact(4)
+            .zip(dst.chunks_exact_mut(bytes_per_pixel))
+        {
+            let pixel = pack_channel(src[2], self.red_mask)
+                | pack_channel(src[1], self.green_mask)
+                | pack_channel(src[0], self.blue_mask)
+                | pack_channel(src[3], self.alpha_mask);
+            if self.msb_first {
+                dst.copy_from_slice(&pixel.to_be_bytes()[4 - bytes_per_pixel..]);
+            } else {
+                dst.copy_from_slice(&pixel.to_le_bytes()[..bytes_per_pixel]);
+            }
+        }
+    }
+
+    /// Converts a row of pixels in this layout into BGRA.
+    ///
+    /// Pixels are opaque unless the layout has an alpha channel.
+    pub fn unpack_bgra(&self, src: &[u8], dst: &mut [u8]) {
+        let bytes_per_pixel = self.bytes_per_pixel();
+        for (src, dst) in src
+            .chunks_exact(bytes_per_pixel)
+            .zip(dst.chunks_exact_mut(4))
+        {
+            let mut bytes = [0; 4];
+            let pixel = if self.msb_first {
+                bytes[4 - bytes_per_pixel..].copy_from_slice(src);
+                u32::from_be_bytes(bytes)
+            } else {
+                bytes[..bytes_per_pixel].copy_from_slice(src);
+                u32::from_le_bytes(bytes)
+            };
+            dst[0] = unpack_channel(pixel, self.blue_mask);
+            dst[1] = unpack_channel(pixel, self.green_mask);
+            dst[2] = unpack_channel(pixel, self.red_mask);
+            dst[3] = match self.alpha_mask {
+                0 => 255,
+                mask => unpack_channel(pixel, mask),
+            };
+        }
+    }
+}
+
+/// Scales an 8-bit channel value to the width of `mask` and shifts it into place.
+fn pack_channel(value: u8, mask: u32) -> u32 {
+    if mask == 0 {
+        return 0;
+    }
+    let shift = mask.trailing_zeros();
+    let max = (mask >> shift) as u64;
+    (((value as u64 * max + 127) / 255) as u32) << shift
+}
+
+/// Extracts the channel covered by `mask` and scales it to 8 bits.
+fn unpack_channel(pixel: u32, mask: u32) -> u8 {
+    let shift = mask.trailing_zeros();
+    let max = (mask >> shift) as u64;
+    let value = ((pixel & mask) >> shift) as u64;
+    ((value * 255 + max / 2) / max) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn native_formats() {
+        let bgrx = PixelLayout::new(24, 32, false, 0xff_0000, 0xff00, 0xff).unwrap();
+        assert_eq!(bgrx.alpha_mask, 0);
+        assert_eq!(bgrx.format(), Some(PixelBufferFormatType::BGRA));
+
+        let bgra = PixelLayout::new(32, 32, false, 0xff_0000, 0xff00, 0xff).unwrap();
+        assert_eq!(bgra.alpha_mask, 0xff00_0000);
+        assert_eq!(bgra.format(), Some(PixelBufferFormatType::BGRA));
+
+        // The same masks on a big-endian server put red in the second byte.
+        let xrgb = PixelLayout::new(24, 32, true, 0xff_0000, 0xff00, 0xff).unwrap();
+        assert_eq!(xrgb.format(), None);
+        let rgbx = PixelLayout::new(24, 32, true, 0xff00_0000, 0xff_0000, 0xff00).unwrap();
+        assert_eq!(rgbx.format(), Some(PixelBufferFormatType::RGBA));
+
+        // Packed formats only match when the server's byte order is ours.
+        let native = cfg!(target_endian = "big");
+        let rgb565 = PixelLayout::new(16, 16, native, 0xf800, 0x07e0, 0x001f).unwrap();
+        assert_eq!(rgb565.format(), Some(PixelBufferFormatType::RGB565));
+        let rgb565 = PixelLayout::new(16, 16, !native, 0xf800, 0x07e0, 0x001f).unwrap();
+        assert_eq!(rgb565.format(), None);
+        let rgb555 = PixelLayout::new(15, 16, native, 0x7c00, 0x03e0, 0x001f).unwrap();
+        assert_eq!(rgb555.format(), Some(PixelBufferFormatType::RGB555));
+        let rgb30 = PixelLayout::new(30, 32, native, 0x3ff0_0000, 0xffc00, 0x3ff).unwrap();
+        assert_eq!(rgb30.format(), Some(PixelBufferFormatType::ARGB2101010));
+        let argb30 = PixelLayout::new(32, 32, native, 0x3ff0_0000, 0xffc00, 0x3ff).unwrap();
+        assert_eq!(argb30.format(), Some(PixelBufferFormatType::ARGB2101010));
+        let bgr30 = PixelLayout::new(30, 32, native, 0x3ff, 0xffc00, 0x3ff0_0000).unwrap();
+        assert_eq!(bgr30.format(), None);
+
+        assert_eq!(PixelLayout::new(8, 8, false, 0, 0, 0), None);
+        assert_eq!(PixelLayout::new(12, 12, false, 0xf00, 0xf0, 0xf), None);
+    }
+
+    #[test]
+    fn pack_and_unpack() {
+        let bgra = [0x10, 0x80, 0xff, 0x40, 0, 0, 0, 255];
+
+        let rgb565 = PixelLayout::new(16, 16, false, 0xf800, 0x07e0, 0x001f).unwrap();
+        let mut packed = [0; 4];
+        rgb565.pack_bgra(&bgra, &mut packed);
+        let pixel = u16::from_le_bytes([packed[0], packed[1]]);
+        assert_eq!(pixel, 31 << 11 | 32 << 5 | 2);
+        let mut unpacked = [0; 8];
+        rgb565.unpack_bgra(&packed, &mut unpacked);
+        assert_eq!(unpacked, [0x10, 0x82, 0xff, 255, 0, 0, 0, 255]);
+
+        let rgb30 = PixelLayout::new(30, 32, true, 0x3ff0_0000, 0xffc00, 0x3ff).unwrap();
+        let mut packed = [0; 8];
+        rgb30.pack_bgra(&bgra, &mut packed);
+        let pixel = u32::from_be_bytes([packed[0], packed[1], packed[2], packed[3]]);
+        assert_eq!(pixel, 0x3ff << 20 | 0x202 << 10 | 0x40);
+        rgb30.unpack_bgra(&packed, &mut unpacked);
+        assert_eq!(unpacked, [0x10, 0x80, 0xff, 255, 0, 0, 0, 255]);
+
+        // Alpha survives a round trip through an ARGB visual.
+        let argb = PixelLayout::new(32, 32, true, 0xff_0000, 0xff00, 0xff).unwrap();
+        argb.pack_bgra(&bgra, &mut packed);
+        assert_eq!(packed, [0x40, 0xff, 0x80, 0x10, 255, 0, 0, 0]);
+        argb.unpack_bgra(&packed, &mut unpacked);
+        assert_eq!(unpacked, bgra);
+    }
+
+    #[test]
+    fn round_trips_every_channel_value() {
+        let layouts = [
+            PixelLayout::new(24, 32, false, 0xff_0000, 0xff00, 0xff).unwrap(),
+            PixelLayout::new(24, 24, true, 0xff, 0xff00, 0xff_0000).unwrap(),
+            PixelLayout::new(30, 32, false, 0x3ff, 0xffc00, 0x3ff0_0000).unwrap(),
+        ];
+        let bgra: Vec<u8> = (0..=255).flat_map(|v| [v, v, v, 255]).collect();
+        for layout in &layouts {
+            let mut packed = vec![0; 256 * layout.bytes_per_pixel()];
+            let mut unpacked = vec![0; bgra.len()];
+            layout.pack_bgra(&bgra, &mut packed);
+            layout.unpack_bgra(&packed, &mut unpacked);
+            assert_eq!(unpacked, bgra, "{:?}", layout);
+        }
+    }
+}
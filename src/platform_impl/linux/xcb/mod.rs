@@ -0,0 +1,440 @@
+use std::io;
+
+use raw_window_handle::{DisplayHandle, RawDisplayHandle, RawWindowHandle, WindowHandle};
+use x11rb::{
+    connection::{Connection, RequestConnection},
+    protocol::xproto::{
+        ConnectionExt as _, CreateGCAux, Gcontext, ImageFormat, ImageOrder, Window,
+    },
+    wrapper::ConnectionExt as _,
+    xcb_ffi::{load_libxcb, XCBConnection},
+};
+
+use super::fill_alpha;
+use crate::{platform_impl::resize_rows, PixelBufferCreationError, PixelBufferFormatType, Rect};
+
+pub struct PixelBuffer {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+    conn: XCBConnection,
+    window: Window,
+    gc: Gcontext,
+    depth: u8,
+}
+
+fn get_window_and_connection(
+    window_handle: WindowHandle,
+    display_handle: DisplayHandle,
+) -> Result<(Window, XCBConnection), PixelBufferCreationError> {
+    if let RawWindowHandle::Xcb(xcb_window_handle) = window_handle.as_raw() {
+        if let RawDisplayHandle::Xcb(xcb_display_handle) = display_handle.as_raw() {
+            let connection = xcb_display_handle
+                .connection
+                .ok_or(PixelBufferCreationError::HandleNotSupported)?;
+            // libxcb is loaded lazily, and panics if it's missing unless we load it up front.
+            load_libxcb().map_err(|e| PixelBufferCreationError::LibraryNotFound(e.to_string()))?;
+            // The windowing library owns the connection, so don't disconnect it on drop.
+            let should_drop = false;
+            let conn =
+                unsafe { XCBConnection::from_raw_xcb_connection(connection.as_ptr(), should_drop) }
+                    .map_err(allocation_failed)?;
+            return Ok((xcb_window_handle.window.get(), conn));
+        }
+    }
+    Err(PixelBufferCreationError::HandleNotSupported)
+}
+
+fn allocation_failed<E: ToString>(e: E) -> PixelBufferCreationError {
+    PixelBufferCreationError::AllocationFailed(e.to_string())
+}
+
+const BYTES_PER_PIXEL: usize = 4;
+const BITS_PER_PIXEL: usize = BYTES_PER_PIXEL * 8;
+/// Size of the fixed part of a `PutImage` request.
+const PUT_IMAGE_HEADER_LEN: usize = 24;
+
+/// Splits a `size` rectangle into `(x, y, width, height)` tiles that each fit in a single
+/// `PutImage` request.
+///
+/// Tiles span as many columns as possible, so full-width blits can be sent straight out of the
+/// pixel buffer without restaging.
+fn tiles(
+    size: (u32, u32),
+    max_request_bytes: usize,
+) -> impl Iterator<Item = (usize, usize, usize, usize)> {
+    let (width, height) = (size.0 as usize, size.1 as usize);
+    let max_pixels = (max_request_bytes - PUT_IMAGE_HEADER_LEN) / BYTES_PER_PIXEL;
+    let tile_width = width.min(max_pixels).clamp(1, u16::MAX as usize);
+    let tile_height = height
+        .min(max_pixels / tile_width)
+        .clamp(1, u16::MAX as usize);
+    (0..height).step_by(tile_height).flat_map(move |y| {
+        (0..width)
+            .step_by(tile_width)
+            .map(move |x| (x, y, tile_width.min(width - x), tile_height.min(height - y)))
+    })
+}
+
+/// Whether the server lays out images of `depth` as 32-bit little-endian pixels, which is the only
+/// layout we know how to send and read back.
+fn supports_bgra(conn: &XCBConnection, depth: u8) -> bool {
+    let setup = conn.setup();
+    let bits_per_pixel = setup
+        .pixmap_formats
+        .iter()
+        .find(|format| format.depth == depth)
+        .map(|format| format.bits_per_pixel);
+    bits_per_pixel == Some(BITS_PER_PIXEL as u8) && setup.image_byte_order == ImageOrder::LSB_FIRST
+}
+
+/// Gets the depth of `window`.
+fn window_depth(conn: &XCBConnection, window: Window) -> Result<u8, PixelBufferCreationError> {
+    Ok(conn
+        .get_geometry(window)
+        .map_err(allocation_failed)?
+        .reply()
+        .map_err(allocation_failed)?
+        .depth)
+}
+
+impl PixelBuffer {
+    pub unsafe fn new(
+        width: u32,
+        height: u32,
+        format: PixelBufferFormatType,
+        window_handle: WindowHandle,
+        display_handle: DisplayHandle,
+    ) -> Result<PixelBuffer, PixelBufferCreationError> {
+        if format != PixelBufferFormatType::BGRA {
+            return Err(PixelBufferCreationError::FormatNotSupported);
+        }
+        let (window, conn) = get_window_and_connection(window_handle, display_handle)?;
+        let depth = window_depth(&conn, window)?;
+        // 16-bit and 30-bit visuals lay out their pixels differently, so `PutImage` would garble
+        // ours.
+        if !supports_bgra(&conn, depth) {
+            return Err(PixelBufferCreationError::FormatNotSupported);
+        }
+        let gc = conn.generate_id().map_err(allocation_failed)?;
+        conn.create_gc(gc, window, &CreateGCAux::new())
+            .map_err(allocation_failed)?
+            .check()
+            .map_err(allocation_failed)?;
+        let pixels = vec![255; width as usize * height as usize * BYTES_PER_PIXEL];
+        Ok(PixelBuffer {
+            width,
+            height,
+            pixels,
+            conn,
+            window,
+            gc,
+            depth,
+        })
+    }
+    pub unsafe fn supported_formats(
+        window_handle: WindowHandle,
+        display_handle: DisplayHandle,
+    ) -> Result<Vec<PixelBufferFormatType>, PixelBufferCreationError> {
+        let (window, conn) = get_window_and_connection(window_handle, display_handle)?;
+        if supports_bgra(&conn, window_depth(&conn, window)?) {
+            Ok(vec![PixelBufferFormatType::BGRA])
+        } else {
+            Ok(Vec::new())
+        }
+    }
+    pub unsafe fn blit(&self, handle: WindowHandle) -> io::Result<()> {
+        self.blit_rect((0, 0), (0, 0), (self.width(), self.height()), handle)
+    }
+    pub unsafe fn blit_rect(
+        &self,
+        src_pos: (u32, u32),
+        dst_pos: (u32, u32),
+        blit_size: (u32, u32),
+        _handle: WindowHandle,
+    ) -> io::Result<()> {
+        self.put_image(src_pos, dst_pos, blit_size, &mut Vec::new())?;
+        self.conn.sync().map_err(io::Error::other)?;
+
+        Ok(())
+    }
+    pub unsafe fn blit_rects(&self, rects: &[Rect], _handle: WindowHandle) -> io::Result<()> {
+        let mut staging = Vec::new();
+        for rect in rects {
+            let pos = (rect.x, rect.y);
+            self.put_image(pos, pos, (rect.width, rect.height), &mut staging)?;
+        }
+        self.conn.sync().map_err(io::Error::other)?;
+
+        Ok(())
+    }
+    pub unsafe fn capture_from(&mut self, rect: Rect, _handle: WindowHandle) -> io::Result<()> {
+        let geometry = self
+            .conn
+            .get_geometry(self.window)
+            .map_err(io::Error::other)?
+            .reply()
+            .map_err(io::Error::other)?;
+        let window = Rect::new(0, 0, geometry.width as u32, geometry.height as u32);
+        let rect = rect.intersection(&window);
+        if rect.is_empty() {
+            return Ok(());
+        }
+
+        // `new` made sure the server lays out the window's pixels just like ours.
+        let all_planes = !0;
+        let image = self
+            .conn
+            .get_image(
+                ImageFormat::Z_PIXMAP,
+                self.window,
+                rect.x as i16,
+                rect.y as i16,
+                rect.width as u16,
+                rect.height as u16,
+                all_planes,
+            )
+            .map_err(io::Error::other)?
+            .reply()
+            .map_err(io::Error::other)?;
+        let row_len = self.row_len();
+        let len = rect.width as usize * BYTES_PER_PIXEL;
+        for (src, dst) in image
+            .data
+            .chunks(len)
+            .zip(self.pixels[rect.y as usize * row_len..].chunks_mut(row_len))
+        {
+            dst[rect.x as usize * BYTES_PER_PIXEL..][..len].copy_from_slice(src);
+        }
+        if self.depth < 32 {
+            fill_alpha(&mut self.pixels, row_len, PixelBufferFormatType::BGRA, rect);
+        }
+        Ok(())
+    }
+    /// Sends the `PutImage` requests that copy part of the pixel buffer onto the window, using
+    /// `staging` to hold tiles that aren't contiguous in the pixel buffer.
+    fn put_image(
+        &self,
+        src_pos: (u32, u32),
+        dst_pos: (u32, u32),
+        blit_size: (u32, u32),
+        staging: &mut Vec<u8>,
+    ) -> io::Result<()> {
+        // `PutImage` has no source rectangle to clip, so we have to clip to the pixel buffer
+        // ourselves.
+        let bounds = Rect::new(0, 0, self.width, self.height);
+        let src = Rect::new(src_pos.0, src_pos.1, blit_size.0, blit_size.1).intersection(&bounds);
+        let max_request_bytes = self.conn.maximum_request_bytes();
+        for (x, y, columns, rows) in tiles((src.width, src.height), max_request_bytes) {
+            let src_x = src.x as usize + x;
+            let src_y = src.y as usize + y;
+            let start = src_y * self.row_len() + src_x * BYTES_PER_PIXEL;
+            let data = if columns == self.width as usize {
+                &self.pixels[start..start + rows * self.row_len()]
+            } else {
+                staging.clear();
+                for row in self.pixels[start..].chunks(self.row_len()).take(rows) {
+                    staging.extend_from_slice(&row[..columns * BYTES_PER_PIXEL]);
+                }
+                &staging[..]
+            };
+            self.conn
+                .put_image(
+                    ImageFormat::Z_PIXMAP,
+                    self.window,
+                    self.gc,
+                    columns as u16,
+                    rows as u16,
+                    (dst_pos.0 as usize + x) as i16,
+                    (dst_pos.1 as usize + y) as i16,
+                    0,
+                    self.depth,
+                    data,
+                )
+                .map_err(io::Error::other)?;
+        }
+        Ok(())
+    }
+    pub unsafe fn resize(
+        &mut self,
+        width: u32,
+        height: u32,
+        preserve: bool,
+    ) -> Result<(), PixelBufferCreationError> {
+        // The pixels are sent with every `PutImage`, so there's no server-side image to rebuild.
+        let row_len = width as usize * BYTES_PER_PIXEL;
+        let rows = if preserve { self.height.min(height) } else { 0 };
+        let old_row_len = self.row_len();
+        resize_rows(
+            &mut self.pixels,
+            old_row_len,
+            row_len,
+            rows as usize,
+            row_len * height as usize,
+        );
+        self.width = width;
+        self.height = height;
+        Ok(())
+    }
+    pub fn bits_per_pixel(&self) -> usize {
+        BITS_PER_PIXEL
+    }
+
+    pub fn bytes_per_pixel(&self) -> usize {
+        BYTES_PER_PIXEL
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn row_len(&self) -> usize {
+        self.width() as usize * self.bytes_per_pixel()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.pixels
+    }
+}
+
+impl Drop for PixelBuffer {
+    fn drop(&mut self) {
+        let _ = self.conn.free_gc(self.gc);
+        let _ = self.conn.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use raw_window_handle::{XcbDisplayHandle, XcbWindowHandle};
+    use std::{num::NonZeroU32, ptr::NonNull};
+    use x11rb::{
+        protocol::xproto::{CreateWindowAux, WindowClass},
+        COPY_DEPTH_FROM_PARENT, COPY_FROM_PARENT,
+    };
+    // Tests need the X server named by `$DISPLAY` (e.g. an `Xvfb` instance), so they only run with
+    // `cargo test -- --ignored`. They share it, so run them one at a time.
+    use serial_test::serial;
+
+    /// An unmapped window on the display named by `$DISPLAY`.
+    struct TestWindow {
+        conn: XCBConnection,
+        screen: usize,
+        window: Window,
+    }
+
+    impl TestWindow {
+        /// Panics if there's no X server to connect to.
+        fn new() -> TestWindow {
+            load_libxcb().expect("couldn't load libxcb");
+            let (conn, screen) =
+                XCBConnection::connect(None).expect("no X server available on `$DISPLAY`");
+            let root = conn.setup().roots[screen].root;
+            let window = conn.generate_id().unwrap();
+            conn.create_window(
+                COPY_DEPTH_FROM_PARENT,
+                window,
+                root,
+                0,
+                0,
+                256,
+                256,
+                0,
+                WindowClass::INPUT_OUTPUT,
+                COPY_FROM_PARENT,
+                &CreateWindowAux::new(),
+            )
+            .unwrap()
+            .check()
+            .unwrap();
+            TestWindow {
+                conn,
+                screen,
+                window,
+            }
+        }
+
+        fn window_handle(&self) -> WindowHandle<'_> {
+            let handle = XcbWindowHandle::new(NonZeroU32::new(self.window).unwrap());
+            unsafe { WindowHandle::borrow_raw(RawWindowHandle::Xcb(handle)) }
+        }
+
+        fn display_handle(&self) -> DisplayHandle<'_> {
+            let connection = NonNull::new(self.conn.get_raw_xcb_connection());
+            let handle = XcbDisplayHandle::new(connection, self.screen as i32);
+            unsafe { DisplayHandle::borrow_raw(RawDisplayHandle::Xcb(handle)) }
+        }
+    }
+
+    impl Drop for TestWindow {
+        fn drop(&mut self) {
+            let _ = self.conn.destroy_window(self.window);
+            let _ = self.conn.flush();
+        }
+    }
+
+    #[test]
+    #[ignore = "needs an X server on `$DISPLAY`"]
+    #[serial]
+    /// The purpose of this test is to verify that blitting rectangles that reach past the pixel
+    /// buffer sends just the part inside it, rather than panicking.
+    fn pixelbuffer_blit_out_of_range() {
+        let test_window = TestWindow::new();
+
+        unsafe {
+            let pb = match PixelBuffer::new(
+                64,
+                32,
+                PixelBufferFormatType::BGRA,
+                test_window.window_handle(),
+                test_window.display_handle(),
+            ) {
+                Ok(pb) => pb,
+                Err(PixelBufferCreationError::FormatNotSupported) => {
+                    eprintln!("the default depth doesn't use 32-bit pixels; skipping test");
+                    return;
+                }
+                Err(e) => panic!("{}", e),
+            };
+            let handle = || test_window.window_handle();
+            pb.blit_rect((48, 16), (0, 0), (32, 32), handle()).unwrap();
+            pb.blit_rect((100, 100), (0, 0), (8, 8), handle()).unwrap();
+            let rects = [Rect::new(60, 0, 10, 40), Rect::new(0, 30, 100, 100)];
+            pb.blit_rects(&rects, handle()).unwrap();
+        }
+    }
+
+    #[test]
+    fn tiles_fit_in_request() {
+        // Room for 100 pixels per request.
+        let max_request_bytes = PUT_IMAGE_HEADER_LEN + 100 * BYTES_PER_PIXEL;
+        let split: Vec<_> = tiles((30, 8), max_request_bytes).collect();
+        assert_eq!(split, vec![(0, 0, 30, 3), (0, 3, 30, 3), (0, 6, 30, 2)]);
+
+        // A single row that's too wide has to be split into columns as well.
+        let split: Vec<_> = tiles((250, 2), max_request_bytes).collect();
+        assert_eq!(
+            split,
+            vec![
+                (0, 0, 100, 1),
+                (100, 0, 100, 1),
+                (200, 0, 50, 1),
+                (0, 1, 100, 1),
+                (100, 1, 100, 1),
+                (200, 1, 50, 1)
+            ]
+        );
+
+        assert_eq!(tiles((0, 0), max_request_bytes).count(), 0);
+    }
+}
@@ -1,8 +1,18 @@
-pub use self::platform_impl::*;
+use std::{cell::Ref, io};
+
+use either::Either;
+use raw_window_handle::{DisplayHandle, HasWindowHandle, WindowHandle};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+use crate::{PixelBufferCreationError, PixelBufferFormatType, Rect, BGRA};
+
+pub use self::headless::{HeadlessSurface, HeadlessWindow};
+pub use self::platform::NativeFormat;
 
 #[cfg(target_os = "windows")]
 #[path = "windows/mod.rs"]
-mod platform_impl;
+mod platform;
 
 #[cfg(any(
     target_os = "linux",
@@ -12,8 +22,260 @@ mod platform_impl;
     target_os = "openbsd"
 ))]
 #[path = "linux/mod.rs"]
-mod platform_impl;
+mod platform;
 
 #[cfg(target_arch = "wasm32")]
 #[path = "web/mod.rs"]
-mod platform_impl;
+mod platform;
+
+mod converted;
+mod headless;
+
+/// Either a window-backed pixel buffer for the current platform, or a headless one.
+pub enum PixelBuffer {
+    Native(platform::PixelBuffer),
+    /// A window-backed pixel buffer in a format the platform can't blit directly.
+    Converted(converted::PixelBuffer),
+    Headless(headless::PixelBuffer),
+}
+
+macro_rules! dispatch {
+    ($self:expr, $p:ident => $body:expr) => {
+        match $self {
+            PixelBuffer::Native($p) => $body,
+            PixelBuffer::Converted($p) => $body,
+            PixelBuffer::Headless($p) => $body,
+        }
+    };
+}
+
+/// The chunk size that splits a buffer into rows of `row_len` elements.
+///
+/// `chunks` panics on a zero chunk size, which we'd otherwise hit for zero-width buffers.
+pub(crate) fn stride(row_len: usize) -> usize {
+    row_len.max(1)
+}
+
+/// Moves the first `rows` rows of `pixels` from rows of `old_row_len` bytes to rows of
+/// `new_row_len` bytes, keeping as much of each row as fits.
+///
+/// `pixels` must be large enough to hold `rows` rows in both layouts.
+pub(crate) fn relayout_rows(
+    pixels: &mut [u8],
+    old_row_len: usize,
+    new_row_len: usize,
+    rows: usize,
+) {
+    let len = old_row_len.min(new_row_len);
+    if new_row_len > old_row_len {
+        // Rows move towards the end, so start from the last one to avoid clobbering rows that
+        // haven't moved yet.
+        for y in (0..rows).rev() {
+            pixels.copy_within(y * old_row_len..y * old_row_len + len, y * new_row_len);
+        }
+    } else if new_row_len < old_row_len {
+        for y in 0..rows {
+            pixels.copy_within(y * old_row_len..y * old_row_len + len, y * new_row_len);
+        }
+    }
+}
+
+/// Resizes `pixels` to `new_len` bytes, reusing its allocation if possible, and moves the first
+/// `rows` rows over to the new row length as in [`relayout_rows`].
+pub(crate) fn resize_rows(
+    pixels: &mut Vec<u8>,
+    old_row_len: usize,
+    new_row_len: usize,
+    rows: usize,
+    new_len: usize,
+) {
+    if new_len > pixels.len() {
+        pixels.resize(new_len, 0);
+    }
+    relayout_rows(pixels, old_row_len, new_row_len, rows);
+    pixels.truncate(new_len);
+}
+
+fn window_handle<H: HasWindowHandle + ?Sized>(window: &H) -> io::Result<WindowHandle<'_>> {
+    window.window_handle().map_err(io::Error::other)
+}
+
+impl PixelBuffer {
+    pub unsafe fn new(
+        width: u32,
+        height: u32,
+        format: PixelBufferFormatType,
+        window_handle: WindowHandle,
+        display_handle: DisplayHandle,
+    ) -> Result<PixelBuffer, PixelBufferCreationError> {
+        // No platform blits indices, and the palette lives in the converted pixel buffer.
+        let native = match format {
+            PixelBufferFormatType::Indexed8 => Err(PixelBufferCreationError::FormatNotSupported),
+            _ => platform::PixelBuffer::new(width, height, format, window_handle, display_handle),
+        };
+        match native {
+            Err(PixelBufferCreationError::FormatNotSupported)
+                if format != PixelBufferFormatType::NATIVE =>
+            {
+                let native = platform::PixelBuffer::new(
+                    width,
+                    height,
+                    PixelBufferFormatType::NATIVE,
+                    window_handle,
+                    display_handle,
+                )?;
+                Ok(PixelBuffer::Converted(converted::PixelBuffer::new(
+                    format,
+                    PixelBuffer::Native(native),
+                    PixelBufferFormatType::NATIVE,
+                )))
+            }
+            native => native.map(PixelBuffer::Native),
+        }
+    }
+    pub unsafe fn supported_formats(
+        window_handle: WindowHandle,
+        display_handle: DisplayHandle,
+    ) -> Result<Vec<PixelBufferFormatType>, PixelBufferCreationError> {
+        platform::PixelBuffer::supported_formats(window_handle, display_handle)
+    }
+    pub fn new_headless(width: u32, height: u32, format: PixelBufferFormatType) -> PixelBuffer {
+        PixelBuffer::Headless(headless::PixelBuffer::new(width, height, format))
+    }
+    pub unsafe fn blit<H: HasWindowHandle + ?Sized>(&self, window: &H) -> io::Result<()> {
+        match self {
+            PixelBuffer::Native(p) => p.blit(window_handle(window)?),
+            PixelBuffer::Converted(p) => p.blit(window),
+            PixelBuffer::Headless(p) => p.blit(),
+        }
+    }
+    pub unsafe fn blit_rect<H: HasWindowHandle + ?Sized>(
+        &self,
+        src_pos: (u32, u32),
+        dst_pos: (u32, u32),
+        blit_size: (u32, u32),
+        window: &H,
+    ) -> io::Result<()> {
+        match self {
+            PixelBuffer::Native(p) => {
+                p.blit_rect(src_pos, dst_pos, blit_size, window_handle(window)?)
+            }
+            PixelBuffer::Converted(p) => p.blit_rect(src_pos, dst_pos, blit_size, window),
+            PixelBuffer::Headless(p) => p.blit_rect(src_pos, dst_pos, blit_size),
+        }
+    }
+    pub unsafe fn blit_rects<H: HasWindowHandle + ?Sized>(
+        &self,
+        rects: &[Rect],
+        window: &H,
+    ) -> io::Result<()> {
+        match self {
+            PixelBuffer::Native(p) => p.blit_rects(rects, window_handle(window)?),
+            PixelBuffer::Converted(p) => p.blit_rects(rects, window),
+            PixelBuffer::Headless(p) => p.blit_rects(rects),
+        }
+    }
+    pub unsafe fn capture_from<H: HasWindowHandle + ?Sized>(
+        &mut self,
+        rect: Rect,
+        window: &H,
+    ) -> io::Result<()> {
+        match self {
+            PixelBuffer::Native(p) => p.capture_from(rect, window_handle(window)?),
+            PixelBuffer::Converted(p) => p.capture_from(rect, window),
+            PixelBuffer::Headless(p) => p.capture_from(rect),
+        }
+    }
+    pub unsafe fn resize(
+        &mut self,
+        width: u32,
+        height: u32,
+        preserve: bool,
+    ) -> Result<(), PixelBufferCreationError> {
+        match self {
+            PixelBuffer::Native(p) => p.resize(width, height, preserve),
+            PixelBuffer::Converted(p) => p.resize(width, height, preserve),
+            PixelBuffer::Headless(p) => {
+                p.resize(width, height, preserve);
+                Ok(())
+            }
+        }
+    }
+    pub fn headless_surface(&self) -> Option<Ref<'_, HeadlessSurface>> {
+        match self {
+            PixelBuffer::Native(_) | PixelBuffer::Converted(_) => None,
+            PixelBuffer::Headless(p) => Some(p.surface()),
+        }
+    }
+    pub fn palette(&self) -> Option<&[BGRA; 256]> {
+        match self {
+            PixelBuffer::Native(_) => None,
+            PixelBuffer::Converted(p) => p.palette(),
+            PixelBuffer::Headless(p) => p.palette(),
+        }
+    }
+    pub fn palette_mut(&mut self) -> Option<&mut [BGRA; 256]> {
+        match self {
+            PixelBuffer::Native(_) => None,
+            PixelBuffer::Converted(p) => p.palette_mut(),
+            PixelBuffer::Headless(p) => p.palette_mut(),
+        }
+    }
+    pub fn bits_per_pixel(&self) -> usize {
+        dispatch!(self, p => p.bits_per_pixel())
+    }
+    pub fn bytes_per_pixel(&self) -> usize {
+        dispatch!(self, p => p.bytes_per_pixel())
+    }
+    pub fn width(&self) -> u32 {
+        dispatch!(self, p => p.width())
+    }
+    pub fn row_len(&self) -> usize {
+        dispatch!(self, p => p.row_len())
+    }
+    pub fn height(&self) -> u32 {
+        dispatch!(self, p => p.height())
+    }
+    pub fn row(&self, row: u32) -> Option<&[u8]> {
+        dispatch!(self, p => p.row(row))
+    }
+    pub fn row_mut(&mut self, row: u32) -> Option<&mut [u8]> {
+        dispatch!(self, p => p.row_mut(row))
+    }
+    pub fn contiguous_bytes(&self) -> Option<&[u8]> {
+        dispatch!(self, p => p.contiguous_bytes())
+    }
+    pub fn contiguous_bytes_mut(&mut self) -> Option<&mut [u8]> {
+        dispatch!(self, p => p.contiguous_bytes_mut())
+    }
+    pub fn rows(&self) -> impl ExactSizeIterator + DoubleEndedIterator<Item = &[u8]> {
+        match self {
+            PixelBuffer::Native(p) => Either::Left(p.rows()),
+            PixelBuffer::Converted(p) => Either::Right(p.rows()),
+            PixelBuffer::Headless(p) => Either::Right(p.rows()),
+        }
+    }
+    pub fn rows_mut(&mut self) -> impl ExactSizeIterator + DoubleEndedIterator<Item = &mut [u8]> {
+        match self {
+            PixelBuffer::Native(p) => Either::Left(p.rows_mut()),
+            PixelBuffer::Converted(p) => Either::Right(p.rows_mut()),
+            PixelBuffer::Headless(p) => Either::Right(p.rows_mut()),
+        }
+    }
+    #[cfg(feature = "rayon")]
+    pub fn par_rows(&self) -> impl IndexedParallelIterator<Item = &[u8]> {
+        match self {
+            PixelBuffer::Native(p) => Either::Left(p.par_rows()),
+            PixelBuffer::Converted(p) => Either::Right(p.par_rows()),
+            PixelBuffer::Headless(p) => Either::Right(p.par_rows()),
+        }
+    }
+    #[cfg(feature = "rayon")]
+    pub fn par_rows_mut(&mut self) -> impl IndexedParallelIterator<Item = &mut [u8]> {
+        match self {
+            PixelBuffer::Native(p) => Either::Left(p.par_rows_mut()),
+            PixelBuffer::Converted(p) => Either::Right(p.par_rows_mut()),
+            PixelBuffer::Headless(p) => Either::Right(p.par_rows_mut()),
+        }
+    }
+}
@@ -2,10 +2,15 @@ use std::io;
 
 use log::{debug, error};
 use raw_window_handle::{DisplayHandle, RawWindowHandle, WebWindowHandle, WindowHandle};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
 use wasm_bindgen::{Clamped, JsCast};
 use web_sys::{CanvasRenderingContext2d, ImageData};
 
-use crate::{PixelBufferCreationError, PixelBufferFormatSupported, PixelBufferFormatType};
+use crate::{
+    platform_impl::{resize_rows, stride},
+    PixelBufferCreationError, PixelBufferFormatType, Rect,
+};
 
 pub struct PixelBuffer {
     ctx: CanvasRenderingContext2d,
@@ -26,21 +31,24 @@ impl PixelBuffer {
             "wasm32 PixelBuffer::new {} {} {:?} {:?}",
             width, height, format, window_handle
         );
+        if format != PixelBufferFormatType::RGBA {
+            return Err(PixelBufferCreationError::FormatNotSupported);
+        }
 
         let raw_handle_id =
             if let RawWindowHandle::Web(WebWindowHandle { id, .. }) = window_handle.as_raw() {
                 id
             } else {
-                return Err(PixelBufferCreationError::FormatNotSupported);
+                return Err(PixelBufferCreationError::HandleNotSupported);
             };
 
         let window = web_sys::window().ok_or_else(|| {
             error!("failed to find window");
-            PixelBufferCreationError::FormatNotSupported
+            PixelBufferCreationError::CanvasNotFound
         })?;
         let document = window.document().ok_or_else(|| {
             error!("failed to find document");
-            PixelBufferCreationError::FormatNotSupported
+            PixelBufferCreationError::CanvasNotFound
         })?;
         // Now find the canvas with this raw handle id.
         let canvases = document.get_elements_by_tag_name("canvas");
@@ -48,11 +56,11 @@ impl PixelBuffer {
         for idx in 0..canvases.length() {
             let c = canvases
                 .item(idx)
-                .unwrap()
+                .ok_or(PixelBufferCreationError::CanvasNotFound)?
                 .dyn_into::<web_sys::HtmlCanvasElement>()
                 .map_err(|_| {
                     error!("Couldn't cast canvas {} to HtmlCanvasElement", idx);
-                    PixelBufferCreationError::FormatNotSupported
+                    PixelBufferCreationError::CanvasNotFound
                 })?;
             // "raw-handle" is from the `raw_window_handle::web::WebWindowHandle` documentation for
             // `id()`.
@@ -76,14 +84,19 @@ impl PixelBuffer {
                 "failed to find canvas matching raw handle id {}",
                 raw_handle_id
             );
-            PixelBufferCreationError::FormatNotSupported
+            PixelBufferCreationError::CanvasNotFound
         })?;
         let ctx = canvas
             .get_context("2d")
-            .unwrap()
-            .unwrap()
-            .dyn_into::<web_sys::CanvasRenderingContext2d>()
-            .unwrap();
+            .ok()
+            .flatten()
+            .and_then(|ctx| ctx.dyn_into::<web_sys::CanvasRenderingContext2d>().ok())
+            .ok_or_else(|| {
+                error!("failed to get 2d context for canvas {}", raw_handle_id);
+                PixelBufferCreationError::AllocationFailed(
+                    "couldn't get a 2d canvas context".to_string(),
+                )
+            })?;
 
         let data = Clamped(vec![0; width as usize * height as usize * 4]);
         Ok(PixelBuffer {
@@ -94,17 +107,19 @@ impl PixelBuffer {
         })
     }
 
+    pub unsafe fn supported_formats(
+        window_handle: WindowHandle,
+        _display_handle: DisplayHandle,
+    ) -> Result<Vec<PixelBufferFormatType>, PixelBufferCreationError> {
+        match window_handle.as_raw() {
+            RawWindowHandle::Web(_) => Ok(vec![PixelBufferFormatType::RGBA]),
+            _ => Err(PixelBufferCreationError::HandleNotSupported),
+        }
+    }
+
     pub unsafe fn blit(&self, handle: WindowHandle) -> io::Result<()> {
         debug!("wasm32 PixelBuffer::blit {:?}", handle);
-        let imagedata = ImageData::new_with_u8_clamped_array_and_sh(
-            Clamped(&self.data.0),
-            self.width,
-            self.height,
-        )
-        .map_err(|e| {
-            error!("failed to create image data {:?}", e);
-            io::Error::new(io::ErrorKind::InvalidData, "failed to create image data")
-        })?;
+        let imagedata = self.image_data()?;
         self.ctx.put_image_data(&imagedata, 0., 0.).map_err(|e| {
             error!("failed to put image data {:?}", e);
             io::Error::new(io::ErrorKind::InvalidData, "failed to put image data")
@@ -119,13 +134,114 @@ impl PixelBuffer {
         blit_size: (u32, u32),
         handle: WindowHandle,
     ) -> io::Result<()> {
-        todo!(
+        debug!(
             "wasm32 PixelBuffer::blit_rect {:?} {:?} {:?} {:?}",
-            src_pos,
-            dst_pos,
-            blit_size,
-            handle
+            src_pos, dst_pos, blit_size, handle
+        );
+        let imagedata = self.image_data()?;
+        self.put_image_data(&imagedata, src_pos, dst_pos, blit_size)
+    }
+
+    pub unsafe fn blit_rects(&self, rects: &[Rect], handle: WindowHandle) -> io::Result<()> {
+        debug!("wasm32 PixelBuffer::blit_rects {:?} {:?}", rects, handle);
+        let imagedata = self.image_data()?;
+        for rect in rects {
+            let pos = (rect.x, rect.y);
+            self.put_image_data(&imagedata, pos, pos, (rect.width, rect.height))?;
+        }
+        Ok(())
+    }
+
+    pub unsafe fn capture_from(&mut self, rect: Rect, handle: WindowHandle) -> io::Result<()> {
+        debug!("wasm32 PixelBuffer::capture_from {:?} {:?}", rect, handle);
+        // Reading outside the canvas gives transparent black rather than an error, so clip it
+        // ourselves to leave those pixels alone.
+        let canvas = match self.ctx.canvas() {
+            Some(canvas) => Rect::new(0, 0, canvas.width(), canvas.height()),
+            None => return Ok(()),
+        };
+        let rect = rect.intersection(&canvas);
+        if rect.is_empty() {
+            return Ok(());
+        }
+        let imagedata = self
+            .ctx
+            .get_image_data(
+                rect.x as f64,
+                rect.y as f64,
+                rect.width as f64,
+                rect.height as f64,
+            )
+            .map_err(|e| {
+                error!("failed to get image data {:?}", e);
+                io::Error::new(io::ErrorKind::InvalidData, "failed to get image data")
+            })?;
+        // Canvas pixels are always RGBA, same as ours.
+        let row_len = self.row_len();
+        let start = rect.x as usize * self.bytes_per_pixel();
+        let len = rect.width as usize * self.bytes_per_pixel();
+        for (src, dst) in imagedata
+            .data()
+            .chunks(len)
+            .zip(self.data[rect.y as usize * row_len..].chunks_mut(row_len))
+        {
+            dst[start..start + len].copy_from_slice(src);
+        }
+        Ok(())
+    }
+
+    fn image_data(&self) -> io::Result<ImageData> {
+        ImageData::new_with_u8_clamped_array_and_sh(Clamped(&self.data.0), self.width, self.height)
+            .map_err(|e| {
+                error!("failed to create image data {:?}", e);
+                io::Error::new(io::ErrorKind::InvalidData, "failed to create image data")
+            })
+    }
+
+    /// Draws the `blit_size` rectangle at `src_pos` in `imagedata` onto the canvas at `dst_pos`.
+    fn put_image_data(
+        &self,
+        imagedata: &ImageData,
+        src_pos: (u32, u32),
+        dst_pos: (u32, u32),
+        blit_size: (u32, u32),
+    ) -> io::Result<()> {
+        // The canvas API positions the whole image and then only draws the "dirty" part of it.
+        self.ctx
+            .put_image_data_with_dirty_x_and_dirty_y_and_dirty_width_and_dirty_height(
+                imagedata,
+                dst_pos.0 as f64 - src_pos.0 as f64,
+                dst_pos.1 as f64 - src_pos.1 as f64,
+                src_pos.0 as f64,
+                src_pos.1 as f64,
+                blit_size.0 as f64,
+                blit_size.1 as f64,
+            )
+            .map_err(|e| {
+                error!("failed to put image data {:?}", e);
+                io::Error::new(io::ErrorKind::InvalidData, "failed to put image data")
+            })
+    }
+
+    pub unsafe fn resize(
+        &mut self,
+        width: u32,
+        height: u32,
+        preserve: bool,
+    ) -> Result<(), PixelBufferCreationError> {
+        let row_len = width as usize * self.bytes_per_pixel();
+        let rows = if preserve { self.height.min(height) } else { 0 };
+        let old_row_len = self.row_len();
+        resize_rows(
+            &mut self.data.0,
+            old_row_len,
+            row_len,
+            rows as usize,
+            row_len * height as usize,
         );
+        self.width = width;
+        self.height = height;
+        Ok(())
     }
     pub fn bits_per_pixel(&self) -> usize {
         32
@@ -143,21 +259,37 @@ impl PixelBuffer {
         self.height
     }
     pub fn row(&self, row: u32) -> Option<&[u8]> {
-        todo!("wasm32 PixelBuffer::row {}", row)
+        let start = row as usize * self.row_len();
+        let end = (row + 1) as usize * self.row_len();
+        self.data.get(start..end)
     }
     pub fn row_mut(&mut self, row: u32) -> Option<&mut [u8]> {
-        todo!("wasm32 PixelBuffer::row_mut {}", row)
+        let start = row as usize * self.row_len();
+        let end = (row + 1) as usize * self.row_len();
+        self.data.get_mut(start..end)
     }
-    pub fn rows<'a>(&'a self) -> impl ExactSizeIterator + DoubleEndedIterator<Item = &'a [u8]> {
-        self.data.chunks(self.row_len())
+    pub fn contiguous_bytes(&self) -> Option<&[u8]> {
+        Some(&self.data)
     }
-    pub fn rows_mut<'a>(
-        &'a mut self,
-    ) -> impl ExactSizeIterator + DoubleEndedIterator<Item = &'a mut [u8]> {
-        let row_len = self.row_len();
-        self.data.chunks_mut(row_len)
+    pub fn contiguous_bytes_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.data)
+    }
+    pub fn rows(&self) -> impl ExactSizeIterator + DoubleEndedIterator<Item = &[u8]> {
+        self.data.chunks(stride(self.row_len()))
+    }
+    pub fn rows_mut(&mut self) -> impl ExactSizeIterator + DoubleEndedIterator<Item = &mut [u8]> {
+        let stride = stride(self.row_len());
+        self.data.chunks_mut(stride)
+    }
+    #[cfg(feature = "rayon")]
+    pub fn par_rows(&self) -> impl IndexedParallelIterator<Item = &[u8]> {
+        self.data.par_chunks(stride(self.row_len()))
+    }
+    #[cfg(feature = "rayon")]
+    pub fn par_rows_mut(&mut self) -> impl IndexedParallelIterator<Item = &mut [u8]> {
+        let stride = stride(self.row_len());
+        self.data.par_chunks_mut(stride)
     }
 }
 
-impl PixelBufferFormatSupported for crate::RGBA {}
 pub type NativeFormat = crate::RGBA;
@@ -1,8 +1,8 @@
-use crate::{PixelBufferCreationError, PixelBufferFormatSupported, PixelBufferFormatType};
-use raw_window_handle::{windows::WindowsHandle, RawWindowHandle};
+use crate::{platform_impl::stride, PixelBufferCreationError, PixelBufferFormatType, Rect};
+use raw_window_handle::{DisplayHandle, RawWindowHandle, Win32WindowHandle, WindowHandle};
 use std::{convert::TryInto, io, ptr};
 use winapi::{
-    shared::windef::{HBITMAP, HWND},
+    shared::windef::{HBITMAP, HWND, RECT},
     um::{
         wingdi::{self, BITMAP, BITMAPINFOHEADER},
         winuser,
@@ -26,15 +26,87 @@ fn px_cast(u: u32) -> i32 {
         .expect("Pixel value too large; must be less than 2,147,483,647")
 }
 
-impl PixelBufferFormatSupported for crate::BGRA {}
-impl PixelBufferFormatSupported for crate::BGR {}
 pub type NativeFormat = crate::BGRA;
 
-fn hwnd(handle: RawWindowHandle) -> HWND {
-    match handle {
-        RawWindowHandle::Windows(WindowsHandle { hwnd, .. }) => hwnd as _,
-        _ => panic!("Unsupported window handle type"),
+/// A rectangle to copy from the pixel buffer onto the window, as `(src_pos, dst_pos, blit_size)`.
+type Blit = ((u32, u32), (u32, u32), (u32, u32));
+
+fn hwnd(handle: WindowHandle) -> Option<HWND> {
+    match handle.as_raw() {
+        RawWindowHandle::Win32(Win32WindowHandle { hwnd, .. }) => Some(hwnd.get() as _),
+        _ => None,
+    }
+}
+
+/// Creates a DIB section of the given dimensions, or an empty bitmap if either is zero.
+unsafe fn create_dib_section(
+    width: u32,
+    height: u32,
+    bit_count: u16,
+) -> Result<(HBITMAP, BITMAP), PixelBufferCreationError> {
+    let handle: HBITMAP;
+    let bitmap: BITMAP;
+    if width != 0 && height != 0 {
+        handle = {
+            let info = BITMAPINFOHEADER {
+                biSize: std::mem::size_of::<BITMAPINFOHEADER>() as _,
+                biWidth: px_cast(width),
+                biHeight: px_cast(height),
+                biPlanes: 1,
+                biBitCount: bit_count,
+                biCompression: wingdi::BI_RGB,
+                biSizeImage: 0,
+                biXPelsPerMeter: 1,
+                biYPelsPerMeter: 1,
+                biClrUsed: 0,
+                biClrImportant: 0,
+            };
+            let dc = winuser::GetDC(ptr::null_mut());
+            let dib_section = wingdi::CreateDIBSection(
+                dc,
+                &info as *const BITMAPINFOHEADER as _,
+                wingdi::DIB_RGB_COLORS,
+                &mut ptr::null_mut(),
+                ptr::null_mut(),
+                0,
+            );
+            winuser::ReleaseDC(ptr::null_mut(), dc);
+            dib_section
+        };
+
+        if handle.is_null() {
+            return Err(PixelBufferCreationError::AllocationFailed(
+                io::Error::last_os_error().to_string(),
+            ));
+        }
+        bitmap = {
+            let mut bitmap: BITMAP = std::mem::zeroed();
+            let bytes_written = wingdi::GetObjectW(
+                handle as _,
+                std::mem::size_of::<BITMAP>() as i32,
+                &mut bitmap as *mut BITMAP as *mut _,
+            );
+            if bytes_written == 0 {
+                wingdi::DeleteObject(handle as _);
+                return Err(PixelBufferCreationError::AllocationFailed(
+                    "couldn't read DIB section".to_string(),
+                ));
+            }
+            bitmap
+        };
+    } else {
+        handle = ptr::null_mut();
+        bitmap = BITMAP {
+            bmType: 0,
+            bmWidth: px_cast(width),
+            bmHeight: px_cast(height),
+            bmWidthBytes: px_cast(width * bit_count as u32 / 8),
+            bmPlanes: 1,
+            bmBitsPixel: bit_count,
+            bmBits: ptr::null_mut(),
+        };
     }
+    Ok((handle, bitmap))
 }
 
 impl PixelBuffer {
@@ -42,74 +114,34 @@ impl PixelBuffer {
         width: u32,
         height: u32,
         format: PixelBufferFormatType,
-        raw_window_handle: RawWindowHandle,
+        window_handle: WindowHandle,
+        _display_handle: DisplayHandle,
     ) -> Result<PixelBuffer, PixelBufferCreationError> {
+        let hwnd = hwnd(window_handle).ok_or(PixelBufferCreationError::HandleNotSupported)?;
         let bit_count = match format {
             PixelBufferFormatType::BGRA => 32,
             PixelBufferFormatType::BGR => 24,
             _ => return Err(PixelBufferCreationError::FormatNotSupported),
         };
-        let handle: HBITMAP;
-        let bitmap: BITMAP;
-        if width != 0 && height != 0 {
-            handle = {
-                let info = BITMAPINFOHEADER {
-                    biSize: std::mem::size_of::<BITMAPINFOHEADER>() as _,
-                    biWidth: px_cast(width),
-                    biHeight: px_cast(height),
-                    biPlanes: 1,
-                    biBitCount: bit_count,
-                    biCompression: wingdi::BI_RGB,
-                    biSizeImage: 0,
-                    biXPelsPerMeter: 1,
-                    biYPelsPerMeter: 1,
-                    biClrUsed: 0,
-                    biClrImportant: 0,
-                };
-                let dc = winuser::GetDC(ptr::null_mut());
-                let dib_section = wingdi::CreateDIBSection(
-                    dc,
-                    &info as *const BITMAPINFOHEADER as _,
-                    wingdi::DIB_RGB_COLORS,
-                    &mut ptr::null_mut(),
-                    ptr::null_mut(),
-                    0,
-                );
-                winuser::ReleaseDC(ptr::null_mut(), dc);
-                dib_section
-            };
-
-            assert_ne!(std::ptr::null_mut(), handle);
-            bitmap = {
-                let mut bitmap: BITMAP = std::mem::zeroed();
-                let bytes_written = wingdi::GetObjectW(
-                    handle as _,
-                    std::mem::size_of::<BITMAP>() as i32,
-                    &mut bitmap as *mut BITMAP as *mut _,
-                );
-                assert_ne!(0, bytes_written);
-                bitmap
-            };
-        } else {
-            handle = ptr::null_mut();
-            bitmap = BITMAP {
-                bmType: 0,
-                bmWidth: px_cast(width),
-                bmHeight: px_cast(height),
-                bmWidthBytes: px_cast(width * bit_count as u32 / 8),
-                bmPlanes: 1,
-                bmBitsPixel: bit_count,
-                bmBits: ptr::null_mut(),
-            };
-        }
+        let (handle, bitmap) = create_dib_section(width, height, bit_count)?;
         Ok(PixelBuffer {
             handle,
             bitmap,
             len: (bitmap.bmWidthBytes * bitmap.bmHeight) as usize,
-            hwnd: hwnd(raw_window_handle),
+            hwnd,
         })
     }
-    pub unsafe fn blit(&self, handle: RawWindowHandle) -> io::Result<()> {
+    pub unsafe fn supported_formats(
+        window_handle: WindowHandle,
+        _display_handle: DisplayHandle,
+    ) -> Result<Vec<PixelBufferFormatType>, PixelBufferCreationError> {
+        hwnd(window_handle).ok_or(PixelBufferCreationError::HandleNotSupported)?;
+        Ok(vec![
+            PixelBufferFormatType::BGRA,
+            PixelBufferFormatType::BGR,
+        ])
+    }
+    pub unsafe fn blit(&self, handle: WindowHandle) -> io::Result<()> {
         self.blit_rect((0, 0), (0, 0), (self.width(), self.height()), handle)
     }
 
@@ -118,32 +150,61 @@ impl PixelBuffer {
         src_pos: (u32, u32),
         dst_pos: (u32, u32),
         blit_size: (u32, u32),
-        handle: RawWindowHandle,
+        handle: WindowHandle,
     ) -> io::Result<()> {
-        if self.handle == ptr::null_mut() {
+        self.bit_blt(&[(src_pos, dst_pos, blit_size)], handle)
+    }
+
+    pub unsafe fn blit_rects(&self, rects: &[Rect], handle: WindowHandle) -> io::Result<()> {
+        let blits: Vec<_> = rects
+            .iter()
+            .map(|rect| {
+                (
+                    (rect.x, rect.y),
+                    (rect.x, rect.y),
+                    (rect.width, rect.height),
+                )
+            })
+            .collect();
+        self.bit_blt(&blits, handle)
+    }
+
+    pub unsafe fn capture_from(&mut self, rect: Rect, handle: WindowHandle) -> io::Result<()> {
+        if self.handle.is_null() {
+            return Ok(());
+        }
+        let hwnd = self.window(handle)?;
+        let mut client: RECT = std::mem::zeroed();
+        if winuser::GetClientRect(hwnd, &mut client) == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let client = Rect::new(0, 0, client.right as u32, client.bottom as u32);
+        let rect = rect.intersection(&client);
+        if rect.is_empty() {
             return Ok(());
         }
-        let hwnd = hwnd(handle);
-        assert_eq!(hwnd, self.hwnd);
         let hdc = winuser::GetDC(hwnd as _);
 
-        let src_dc = wingdi::CreateCompatibleDC(hdc);
-        let prev_bmp = wingdi::SelectObject(src_dc, self.handle as _);
+        // GDI converts from the window's pixel format to the DIB section's for us.
+        let dst_dc = wingdi::CreateCompatibleDC(hdc);
+        let prev_bmp = wingdi::SelectObject(dst_dc, self.handle as _);
         let result = wingdi::BitBlt(
+            dst_dc,
+            px_cast(rect.x),
+            px_cast(rect.y),
+            px_cast(rect.width),
+            px_cast(rect.height),
             hdc,
-            px_cast(src_pos.0),
-            px_cast(src_pos.1),
-            px_cast(blit_size.0),
-            px_cast(blit_size.1),
-            src_dc,
-            px_cast(dst_pos.0),
-            px_cast(dst_pos.1),
+            px_cast(rect.x),
+            px_cast(rect.y),
             wingdi::SRCCOPY,
         );
         let error = io::Error::last_os_error();
+        // Make sure GDI has finished writing before the caller reads the pixels.
+        wingdi::GdiFlush();
 
-        wingdi::SelectObject(src_dc, prev_bmp);
-        wingdi::DeleteDC(src_dc);
+        wingdi::SelectObject(dst_dc, prev_bmp);
+        wingdi::DeleteDC(dst_dc);
         winuser::ReleaseDC(hwnd, hdc);
 
         if result != 0 {
@@ -153,6 +214,85 @@ impl PixelBuffer {
         }
     }
 
+    /// Gets the window `handle` refers to, which has to be the one the pixel buffer was created
+    /// for.
+    fn window(&self, handle: WindowHandle) -> io::Result<HWND> {
+        let hwnd = hwnd(handle).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "not a Win32 window handle")
+        })?;
+        if hwnd != self.hwnd {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "not the window the pixel buffer was created for",
+            ));
+        }
+        Ok(hwnd)
+    }
+
+    /// Copies each rectangle onto the window, sharing a single device context between them.
+    unsafe fn bit_blt(&self, blits: &[Blit], handle: WindowHandle) -> io::Result<()> {
+        if self.handle.is_null() {
+            return Ok(());
+        }
+        let hwnd = self.window(handle)?;
+        let hdc = winuser::GetDC(hwnd as _);
+
+        let src_dc = wingdi::CreateCompatibleDC(hdc);
+        let prev_bmp = wingdi::SelectObject(src_dc, self.handle as _);
+        let mut result = Ok(());
+        for &(src_pos, dst_pos, blit_size) in blits {
+            let succeeded = wingdi::BitBlt(
+                hdc,
+                px_cast(dst_pos.0),
+                px_cast(dst_pos.1),
+                px_cast(blit_size.0),
+                px_cast(blit_size.1),
+                src_dc,
+                px_cast(src_pos.0),
+                px_cast(src_pos.1),
+                wingdi::SRCCOPY,
+            );
+            if succeeded == 0 {
+                result = Err(io::Error::last_os_error());
+                break;
+            }
+        }
+
+        wingdi::SelectObject(src_dc, prev_bmp);
+        wingdi::DeleteDC(src_dc);
+        winuser::ReleaseDC(hwnd, hdc);
+
+        result
+    }
+
+    pub unsafe fn resize(
+        &mut self,
+        width: u32,
+        height: u32,
+        preserve: bool,
+    ) -> Result<(), PixelBufferCreationError> {
+        // DIB sections can't change size, so there's nothing to reuse unless the size is the same.
+        if (width, height) == (self.width(), self.height()) {
+            return Ok(());
+        }
+        let (handle, bitmap) = create_dib_section(width, height, self.bitmap.bmBitsPixel)?;
+        let mut resized = PixelBuffer {
+            handle,
+            bitmap,
+            len: (bitmap.bmWidthBytes * bitmap.bmHeight) as usize,
+            hwnd: self.hwnd,
+        };
+        if preserve {
+            for (dst, src) in resized.rows_mut().zip(self.rows()) {
+                let len = dst.len().min(src.len());
+                dst[..len].copy_from_slice(&src[..len]);
+            }
+        }
+        // Dropping the old buffer deletes the old DIB section.
+        *self = resized;
+        Ok(())
+    }
+
     pub fn bits_per_pixel(&self) -> usize {
         self.bitmap.bmBitsPixel as usize
     }
@@ -174,14 +314,14 @@ impl PixelBuffer {
     }
 
     fn bytes(&self) -> &[u8] {
-        if self.handle == ptr::null_mut() {
+        if self.handle.is_null() {
             return &[];
         }
         unsafe { std::slice::from_raw_parts(self.bitmap.bmBits as *const u8, self.len) }
     }
 
     fn bytes_mut(&mut self) -> &mut [u8] {
-        if self.handle == ptr::null_mut() {
+        if self.handle.is_null() {
             return &mut [];
         }
         unsafe { std::slice::from_raw_parts_mut(self.bitmap.bmBits as *mut u8, self.len) }
@@ -199,11 +339,18 @@ impl PixelBuffer {
         self.bytes_mut().get_mut(index..index + pixel_len)
     }
 
-    pub fn rows<'a>(&'a self) -> impl ExactSizeIterator + DoubleEndedIterator<Item = &'a [u8]> {
-        let stride = match self.row_len() {
-            0 => 1,
-            l => l,
-        };
+    /// DIBs store their rows bottom-up and pad them to 4 bytes, so they're never contiguous the
+    /// way the rest of the crate expects.
+    pub fn contiguous_bytes(&self) -> Option<&[u8]> {
+        None
+    }
+
+    pub fn contiguous_bytes_mut(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+
+    pub fn rows(&self) -> impl ExactSizeIterator + DoubleEndedIterator<Item = &[u8]> {
+        let stride = stride(self.row_len());
         let pixel_len = self.width() as usize * self.bytes_per_pixel();
         self.bytes()
             .chunks(stride)
@@ -211,13 +358,8 @@ impl PixelBuffer {
             .map(move |row| &row[..pixel_len])
     }
 
-    pub fn rows_mut<'a>(
-        &'a mut self,
-    ) -> impl ExactSizeIterator + DoubleEndedIterator<Item = &'a mut [u8]> {
-        let stride = match self.row_len() {
-            0 => 1,
-            l => l,
-        };
+    pub fn rows_mut(&mut self) -> impl ExactSizeIterator + DoubleEndedIterator<Item = &mut [u8]> {
+        let stride = stride(self.row_len());
         let pixel_len = self.width() as usize * self.bytes_per_pixel();
         self.bytes_mut()
             .chunks_mut(stride)
@@ -226,11 +368,8 @@ impl PixelBuffer {
     }
 
     #[cfg(feature = "rayon")]
-    pub fn par_rows<'a>(&'a self) -> impl IndexedParallelIterator<Item = &'a [u8]> {
-        let stride = match self.row_len() {
-            0 => 1,
-            l => l,
-        };
+    pub fn par_rows(&self) -> impl IndexedParallelIterator<Item = &[u8]> {
+        let stride = stride(self.row_len());
         let pixel_len = self.width() as usize * self.bytes_per_pixel();
         self.bytes()
             .par_chunks(stride)
@@ -239,11 +378,8 @@ impl PixelBuffer {
     }
 
     #[cfg(feature = "rayon")]
-    pub fn par_rows_mut<'a>(&'a mut self) -> impl IndexedParallelIterator<Item = &'a mut [u8]> {
-        let stride = match self.row_len() {
-            0 => 1,
-            l => l,
-        };
+    pub fn par_rows_mut(&mut self) -> impl IndexedParallelIterator<Item = &mut [u8]> {
+        let stride = stride(self.row_len());
         let pixel_len = self.width() as usize * self.bytes_per_pixel();
         self.bytes_mut()
             .par_chunks_mut(stride)
@@ -268,6 +404,8 @@ impl Drop for PixelBuffer {
 mod tests {
     use super::*;
 
+    use std::num::NonZeroIsize;
+
     use winapi::shared::windef::HWND;
     use winapi::um::{
         processthreadsapi::GetCurrentProcess, winnt::HANDLE, winuser::GetDesktopWindow,
@@ -295,13 +433,10 @@ mod tests {
         }
     }
 
-    /// Constructs a `RawWindowHandle` from an `HWND`.
-    fn from_hwnd(hwnd: HWND) -> RawWindowHandle {
-        let handle = WindowsHandle {
-            hwnd: hwnd as _,
-            ..WindowsHandle::empty()
-        };
-        RawWindowHandle::Windows(handle)
+    /// Constructs a `WindowHandle` from an `HWND`.
+    unsafe fn from_hwnd(hwnd: HWND) -> WindowHandle<'static> {
+        let handle = Win32WindowHandle::new(NonZeroIsize::new(hwnd as isize).unwrap());
+        WindowHandle::borrow_raw(RawWindowHandle::Win32(handle))
     }
 
     #[test]
@@ -315,8 +450,15 @@ mod tests {
 
         // Perform test(s).
         unsafe {
-            let raw_handle = from_hwnd(GetDesktopWindow());
-            let _pb = PixelBuffer::new(256, 256, PixelBufferFormatType::BGRA, raw_handle).unwrap();
+            let window_handle = from_hwnd(GetDesktopWindow());
+            let _pb = PixelBuffer::new(
+                256,
+                256,
+                PixelBufferFormatType::BGRA,
+                window_handle,
+                DisplayHandle::windows(),
+            )
+            .unwrap();
         } // <- drop PixelBuffer and release resources
 
         // Compare GDI object count at test end.
@@ -337,7 +479,14 @@ mod tests {
         // Perform test
         unsafe {
             let desktop_wnd = from_hwnd(GetDesktopWindow());
-            let pb = PixelBuffer::new(31, 31, PixelBufferFormatType::BGR, desktop_wnd).unwrap();
+            let pb = PixelBuffer::new(
+                31,
+                31,
+                PixelBufferFormatType::BGR,
+                desktop_wnd,
+                DisplayHandle::windows(),
+            )
+            .unwrap();
             let _res = pb.blit(desktop_wnd);
         }
 
@@ -349,4 +498,27 @@ mod tests {
             obj_count_base, obj_count_current
         );
     }
+
+    #[test]
+    #[serial]
+    /// The purpose of this test is to verify that blitting onto a window other than the one the
+    /// `PixelBuffer` was created for returns an error instead of panicking.
+    fn pixelbuffer_blit_other_window() {
+        unsafe {
+            let desktop_wnd = from_hwnd(GetDesktopWindow());
+            let pb = PixelBuffer::new(
+                8,
+                8,
+                PixelBufferFormatType::BGRA,
+                desktop_wnd,
+                DisplayHandle::windows(),
+            )
+            .unwrap();
+            // The handle is never used once it doesn't match, so it doesn't need to be a real
+            // window.
+            let other_wnd = from_hwnd(1 as HWND);
+            let error = pb.blit(other_wnd).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
 }
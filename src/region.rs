@@ -0,0 +1,216 @@
+use std::iter::FromIterator;
+
+/// An axis-aligned rectangle of pixels.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// A set of rectangles, such as the parts of a pixel buffer that have changed since it was last
+/// blitted.
+///
+/// Overlapping rectangles are merged as they're added, so the rectangles in a region never
+/// overlap.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Region {
+    rects: Vec<Rect>,
+}
+
+impl Rect {
+    pub const fn new(x: u32, y: u32, width: u32, height: u32) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Whether the rectangle doesn't cover any pixels.
+    pub const fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    /// The column just past the right edge of the rectangle, or `u32::MAX` if that's past the
+    /// last column there is.
+    pub const fn right(&self) -> u32 {
+        self.x.saturating_add(self.width)
+    }
+
+    /// The row just past the bottom edge of the rectangle, or `u32::MAX` if that's past the last
+    /// row there is.
+    pub const fn bottom(&self) -> u32 {
+        self.y.saturating_add(self.height)
+    }
+
+    /// Whether the rectangles have any pixels in common.
+    pub fn intersects(&self, other: &Rect) -> bool {
+        !self.intersection(other).is_empty()
+    }
+
+    /// The pixels the rectangles have in common.
+    ///
+    /// The result is empty if they don't intersect.
+    pub fn intersection(&self, other: &Rect) -> Rect {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = self.right().min(other.right());
+        let bottom = self.bottom().min(other.bottom());
+        Rect::new(x, y, right.saturating_sub(x), bottom.saturating_sub(y))
+    }
+
+    /// The smallest rectangle containing both rectangles.
+    pub fn union(&self, other: &Rect) -> Rect {
+        if self.is_empty() {
+            return *other;
+        } else if other.is_empty() {
+            return *self;
+        }
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Rect::new(
+            x,
+            y,
+            self.right().max(other.right()) - x,
+            self.bottom().max(other.bottom()) - y,
+        )
+    }
+}
+
+impl Region {
+    pub fn new() -> Region {
+        Region::default()
+    }
+
+    /// Adds `rect` to the region, merging it with any rectangles it overlaps.
+    pub fn add(&mut self, rect: Rect) {
+        if rect.is_empty() {
+            return;
+        }
+        // Merging can make the rectangle overlap ones it didn't before, so keep going until it
+        // doesn't overlap anything.
+        let mut rect = rect;
+        while let Some(i) = self.rects.iter().position(|r| r.intersects(&rect)) {
+            rect = rect.union(&self.rects.swap_remove(i));
+        }
+        self.rects.push(rect);
+    }
+
+    /// The non-overlapping rectangles that make up the region.
+    pub fn rects(&self) -> &[Rect] {
+        &self.rects
+    }
+
+    /// Whether the region doesn't cover any pixels.
+    pub fn is_empty(&self) -> bool {
+        self.rects.is_empty()
+    }
+
+    /// The smallest rectangle containing the whole region, or `None` if it's empty.
+    pub fn bounds(&self) -> Option<Rect> {
+        self.rects.iter().copied().reduce(|a, b| a.union(&b))
+    }
+
+    /// Removes every rectangle from the region.
+    pub fn clear(&mut self) {
+        self.rects.clear();
+    }
+}
+
+impl Extend<Rect> for Region {
+    fn extend<I: IntoIterator<Item = Rect>>(&mut self, iter: I) {
+        for rect in iter {
+            self.add(rect);
+        }
+    }
+}
+
+impl FromIterator<Rect> for Region {
+    fn from_iter<I: IntoIterator<Item = Rect>>(iter: I) -> Region {
+        let mut region = Region::new();
+        region.extend(iter);
+        region
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{HeadlessWindow, PixelBuffer, PixelBufferFormatType};
+
+    #[test]
+    fn add_coalesces_overlapping_rects() {
+        let mut region = Region::new();
+        region.add(Rect::new(0, 0, 10, 10));
+        region.add(Rect::new(20, 0, 10, 10));
+        region.add(Rect::new(0, 20, 0, 10));
+        assert_eq!(region.rects().len(), 2);
+
+        // Bridging the two rects merges them into one.
+        region.add(Rect::new(5, 5, 20, 2));
+        assert_eq!(region.rects(), &[Rect::new(0, 0, 30, 10)]);
+
+        // Touching edges don't count as overlapping.
+        region.add(Rect::new(30, 0, 5, 5));
+        assert_eq!(region.rects().len(), 2);
+        assert_eq!(region.bounds(), Some(Rect::new(0, 0, 35, 10)));
+
+        region.clear();
+        assert!(region.is_empty());
+        assert_eq!(region.bounds(), None);
+    }
+
+    #[test]
+    fn intersection_and_union() {
+        let a = Rect::new(0, 0, 10, 10);
+        let b = Rect::new(5, 8, 10, 10);
+        assert_eq!(a.intersection(&b), Rect::new(5, 8, 5, 2));
+        assert_eq!(a.union(&b), Rect::new(0, 0, 15, 18));
+        assert!(!a.intersects(&Rect::new(10, 0, 5, 5)));
+        assert_eq!(a.union(&Rect::default()), a);
+
+        // Edges past the last column or row stop there instead of overflowing.
+        let huge = Rect::new(6, u32::MAX, u32::MAX, 2);
+        assert_eq!((huge.right(), huge.bottom()), (u32::MAX, u32::MAX));
+        assert!(a.intersection(&huge).is_empty());
+        assert!(!a.intersects(&Rect::new(u32::MAX, 0, 2, 2)));
+    }
+
+    #[test]
+    fn pixel_buffer_tracks_damage() {
+        let mut pb = PixelBuffer::new_headless(8, 8, PixelBufferFormatType::RGBA);
+        assert!(pb.damage().is_empty());
+
+        pb.row_mut(2).unwrap().fill(1);
+        pb.row_mut(3).unwrap().fill(1);
+        pb.row_mut(8);
+        pb.mark_dirty(Rect::new(6, 6, 10, 10));
+        pb.mark_dirty(Rect::new(20, 0, 1, 1));
+        pb.mark_dirty(Rect::new(6, 6, u32::MAX, 1));
+        pb.mark_dirty(Rect::new(u32::MAX, 0, 2, 2));
+        assert_eq!(
+            pb.damage().rects(),
+            &[
+                Rect::new(0, 2, 8, 1),
+                Rect::new(0, 3, 8, 1),
+                Rect::new(6, 6, 2, 2)
+            ]
+        );
+
+        pb.blit_damage(&HeadlessWindow).unwrap();
+        assert!(pb.damage().is_empty());
+        let surface = pb.headless_surface().unwrap();
+        assert!(surface.row(2).unwrap().iter().all(|&b| b == 1));
+        assert!(surface.row(4).unwrap().iter().all(|&b| b == 255));
+        drop(surface);
+
+        pb.rows_mut().for_each(|row| row.fill(2));
+        assert_eq!(pb.damage().rects(), &[Rect::new(0, 0, 8, 8)]);
+        pb.resize(4, 2).unwrap();
+        assert_eq!(pb.damage().rects(), &[Rect::new(0, 0, 4, 2)]);
+    }
+}
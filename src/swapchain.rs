@@ -0,0 +1,196 @@
+use std::io;
+
+use raw_window_handle::{HasDisplayHandle, HasWindowHandle};
+
+use crate::{PixelBufferCreationError, PixelBufferFormat, PixelBufferTyped};
+
+/// A set of two or three pixel buffers that take turns being drawn to and presented.
+///
+/// Drawing goes into the [back buffer](Self::back_buffer_mut), which [`present`](Self::present)
+/// blits onto the window before moving on to the next buffer. Since the buffer being drawn to is
+/// never the one that was just presented, the compositor can keep reading from the front buffer
+/// while the next frame is drawn.
+///
+/// [`buffer_age`](Self::buffer_age) reports how many frames ago the back buffer was last
+/// presented, so callers can redraw only what's changed since then instead of the whole frame.
+pub struct Swapchain<P: PixelBufferFormat> {
+    buffers: Vec<PixelBufferTyped<P>>,
+    /// The frame each buffer was last presented in, or `None` if its contents are undefined.
+    presented_in: Vec<Option<u64>>,
+    back: usize,
+    /// The number of frames presented so far.
+    frame: u64,
+}
+
+impl<P: PixelBufferFormat> Swapchain<P> {
+    /// Initialize a new swapchain of `buffer_count` pixel buffers.
+    ///
+    /// Can return `Err` if any of the pixel buffers can't be created.
+    ///
+    /// # Panics
+    /// Panics if `buffer_count` isn't `2` or `3`.
+    pub fn new<H: HasWindowHandle, D: HasDisplayHandle>(
+        width: u32,
+        height: u32,
+        buffer_count: usize,
+        window: &H,
+        display: &D,
+    ) -> Result<Swapchain<P>, PixelBufferCreationError> {
+        Self::check_buffer_count(buffer_count);
+        let buffers = (0..buffer_count)
+            .map(|_| PixelBufferTyped::new(width, height, window, display))
+            .collect::<Result<_, _>>()?;
+        Ok(Self::from_buffers(buffers))
+    }
+
+    /// Initialize a new swapchain of `buffer_count` pixel buffers that aren't attached to a
+    /// window.
+    ///
+    /// See [`PixelBuffer::new_headless`](crate::PixelBuffer::new_headless).
+    ///
+    /// # Panics
+    /// Panics if `buffer_count` isn't `2` or `3`.
+    pub fn new_headless(width: u32, height: u32, buffer_count: usize) -> Swapchain<P> {
+        Self::check_buffer_count(buffer_count);
+        let buffers = (0..buffer_count)
+            .map(|_| PixelBufferTyped::new_headless(width, height))
+            .collect();
+        Self::from_buffers(buffers)
+    }
+
+    fn check_buffer_count(buffer_count: usize) {
+        assert!(
+            (2..=3).contains(&buffer_count),
+            "swapchains need 2 or 3 buffers, not {}",
+            buffer_count
+        );
+    }
+
+    fn from_buffers(buffers: Vec<PixelBufferTyped<P>>) -> Swapchain<P> {
+        Swapchain {
+            presented_in: vec![None; buffers.len()],
+            buffers,
+            back: 0,
+            frame: 0,
+        }
+    }
+
+    /// The buffer that the next frame should be drawn into.
+    pub fn back_buffer(&self) -> &PixelBufferTyped<P> {
+        &self.buffers[self.back]
+    }
+
+    /// Mutably gets the buffer that the next frame should be drawn into.
+    ///
+    /// The back buffer shouldn't be resized directly; use [`resize`](Self::resize) instead so
+    /// every buffer stays the same size.
+    pub fn back_buffer_mut(&mut self) -> &mut PixelBufferTyped<P> {
+        &mut self.buffers[self.back]
+    }
+
+    /// The buffer that was presented most recently, if any.
+    pub fn front_buffer(&self) -> Option<&PixelBufferTyped<P>> {
+        let front = (self.back + self.buffers.len() - 1) % self.buffers.len();
+        self.presented_in[front].map(|_| &self.buffers[front])
+    }
+
+    /// The number of frames since the back buffer's current contents were presented.
+    ///
+    /// An age of `1` means the back buffer holds the previous frame, `2` the frame before that,
+    /// and so on. An age of `0` means its contents are undefined and the whole frame needs to be
+    /// redrawn, which is the case for every buffer until it's first presented and after the
+    /// swapchain is resized.
+    pub fn buffer_age(&self) -> u32 {
+        match self.presented_in[self.back] {
+            Some(frame) => (self.frame - frame) as u32,
+            None => 0,
+        }
+    }
+
+    /// Blits the back buffer onto `window` and moves on to the next buffer.
+    ///
+    /// If blitting fails, the back buffer stays the same.
+    ///
+    /// # Errors
+    /// Returns an error if the platform fails to blit. The `window` passed to this function must be
+    /// the same `window` passed to `new`, or an error may be returned.
+    pub fn present<H: HasWindowHandle>(&mut self, window: &H) -> io::Result<()> {
+        self.buffers[self.back].blit(window)?;
+        self.presented_in[self.back] = Some(self.frame);
+        self.frame += 1;
+        self.back = (self.back + 1) % self.buffers.len();
+        Ok(())
+    }
+
+    /// Changes the dimensions of every buffer in the swapchain.
+    ///
+    /// The contents of every buffer are undefined afterwards, so [`buffer_age`](Self::buffer_age)
+    /// starts over from `0`. That's the case even if resizing fails, in which case the buffers
+    /// are put back to their old dimensions.
+    pub fn resize(&mut self, width: u32, height: u32) -> Result<(), PixelBufferCreationError> {
+        let (old_width, old_height) = (self.width(), self.height());
+        self.presented_in.fill(None);
+        for i in 0..self.buffers.len() {
+            if let Err(e) = self.buffers[i].resize(width, height) {
+                // Keep every buffer the same size by putting back the ones already resized.
+                for buffer in &mut self.buffers[..i] {
+                    let _ = buffer.resize(old_width, old_height);
+                }
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// The number of buffers in the swapchain.
+    pub fn buffer_count(&self) -> usize {
+        self.buffers.len()
+    }
+
+    /// The width, in pixels, of the swapchain's buffers.
+    pub fn width(&self) -> u32 {
+        self.back_buffer().width()
+    }
+
+    /// The height, in pixels, of the swapchain's buffers.
+    pub fn height(&self) -> u32 {
+        self.back_buffer().height()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{HeadlessWindow, BGRA};
+
+    #[test]
+    fn buffer_age_follows_presents() {
+        let mut swapchain = Swapchain::<BGRA>::new_headless(4, 4, 3);
+        assert!(swapchain.front_buffer().is_none());
+
+        let mut ages = Vec::new();
+        for frame in 0..6u8 {
+            ages.push(swapchain.buffer_age());
+            for row in swapchain.back_buffer_mut().rows_mut() {
+                row.fill(BGRA::from_rgb(frame, 0, 0));
+            }
+            swapchain.present(&HeadlessWindow).unwrap();
+        }
+        assert_eq!(ages, [0, 0, 0, 3, 3, 3]);
+
+        // The back buffer still holds what was presented three frames ago.
+        assert_eq!(swapchain.back_buffer().row(0).unwrap()[0].r, 3);
+        let front = swapchain.front_buffer().unwrap();
+        assert_eq!(front.headless_surface().unwrap().row(0).unwrap()[2], 5);
+
+        swapchain.resize(8, 2).unwrap();
+        assert_eq!(swapchain.buffer_age(), 0);
+        assert!(swapchain.front_buffer().is_none());
+        assert_eq!((swapchain.width(), swapchain.height()), (8, 2));
+        for _ in 0..3 {
+            swapchain.present(&HeadlessWindow).unwrap();
+        }
+        assert_eq!(swapchain.buffer_age(), 3);
+    }
+}
@@ -0,0 +1,24 @@
+//! Fixtures shared by the unit tests of several modules.
+
+use crate::{Gray8, PixelBufferTyped};
+
+/// Draws the pixel buffer as text: `#` for fully on, `.` for off and `+` for anything in between.
+pub(crate) fn render(pb: &PixelBufferTyped<Gray8>) -> Vec<String> {
+    pb.rows()
+        .map(|row| {
+            row.iter()
+                .map(|pixel| match pixel.0 {
+                    0 => '.',
+                    255 => '#',
+                    _ => '+',
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Collects the pixels of the pixel buffer, row by row.
+#[cfg(any(feature = "images", feature = "image"))]
+pub(crate) fn pixels<P: crate::PixelBufferFormat>(pb: &PixelBufferTyped<P>) -> Vec<P> {
+    pb.rows().flatten().copied().collect()
+}
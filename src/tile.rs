@@ -0,0 +1,146 @@
+use rayon::prelude::*;
+
+use crate::{view::Captures, Rect};
+
+/// A rectangular part of a pixel buffer that can be written to independently of the rest of it.
+///
+/// Tiles are handed out by [`PixelBuffer::par_tiles_mut`](crate::PixelBuffer::par_tiles_mut),
+/// in which case `T` is `u8`, and
+/// [`PixelBufferTyped::par_tiles_mut`](crate::PixelBufferTyped::par_tiles_mut), in which case
+/// it's the pixel type. Row indices are relative to the top of the tile.
+pub struct TileMut<'a, T> {
+    rect: Rect,
+    rows: Vec<&'a mut [T]>,
+}
+
+impl<'a, T> TileMut<'a, T> {
+    /// The part of the pixel buffer that the tile covers.
+    pub fn rect(&self) -> Rect {
+        self.rect
+    }
+
+    /// Gets the row at the particular height within the tile.
+    pub fn row(&self, row: u32) -> Option<&[T]> {
+        self.rows.get(row as usize).map(|row| &**row)
+    }
+
+    /// Mutably gets the row at the particular height within the tile.
+    pub fn row_mut(&mut self, row: u32) -> Option<&mut [T]> {
+        self.rows.get_mut(row as usize).map(|row| &mut **row)
+    }
+
+    /// Iterate through all rows in the tile.
+    pub fn rows(&self) -> impl ExactSizeIterator + DoubleEndedIterator<Item = &[T]> + Captures<'a> {
+        self.rows.iter().map(|row| &**row)
+    }
+
+    /// Mutably iterate through all rows in the tile.
+    pub fn rows_mut(
+        &mut self,
+    ) -> impl ExactSizeIterator + DoubleEndedIterator<Item = &mut [T]> + Captures<'a> {
+        self.rows.iter_mut().map(|row| &mut **row)
+    }
+}
+
+/// Splits the rows of a `width`-pixel-wide buffer into tiles of `tile_size` pixels, where each
+/// pixel takes up `elems_per_pixel` elements of a row.
+///
+/// Tiles along the right and bottom edges are cut short if the buffer doesn't divide evenly.
+///
+/// # Panics
+/// Panics if either dimension of `tile_size` is zero.
+pub(crate) fn par_tiles_mut<'a, T: Send + 'a>(
+    rows: impl IndexedParallelIterator<Item = &'a mut [T]>,
+    width: u32,
+    tile_size: (u32, u32),
+    elems_per_pixel: usize,
+) -> impl ParallelIterator<Item = TileMut<'a, T>> {
+    let (tile_width, tile_height) = tile_size;
+    assert!(
+        tile_width != 0 && tile_height != 0,
+        "tiles must be at least 1x1, not {}x{}",
+        tile_width,
+        tile_height
+    );
+    let tile_len = tile_width as usize * elems_per_pixel;
+    // Each band of rows is handed to a thread, which then carves it up column-wise.
+    rows.chunks(tile_height as usize)
+        .enumerate()
+        .flat_map_iter(move |(band, rows)| {
+            let y = band as u32 * tile_height;
+            let height = rows.len() as u32;
+            let mut tiles: Vec<_> = (0..width)
+                .step_by(tile_width as usize)
+                .map(|x| TileMut {
+                    rect: Rect::new(x, y, tile_width.min(width - x), height),
+                    rows: Vec::with_capacity(rows.len()),
+                })
+                .collect();
+            for row in rows {
+                for (tile, piece) in tiles.iter_mut().zip(row.chunks_mut(tile_len)) {
+                    tile.rows.push(piece);
+                }
+            }
+            tiles
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{PixelBuffer, PixelBufferFormatType, PixelBufferTyped, RGB};
+
+    #[test]
+    fn tiles_cover_buffer() {
+        let mut pb = PixelBuffer::new_headless(10, 7, PixelBufferFormatType::RGB);
+        let mut rects: Vec<_> = pb
+            .par_tiles_mut(4, 3)
+            .map(|mut tile| {
+                let rect = tile.rect();
+                for (y, row) in tile.rows_mut().enumerate() {
+                    assert_eq!(row.len(), rect.width as usize * 3);
+                    for (x, pixel) in row.chunks_mut(3).enumerate() {
+                        pixel.copy_from_slice(&[rect.x as u8 + x as u8, rect.y as u8 + y as u8, 1]);
+                    }
+                }
+                rect
+            })
+            .collect();
+        rects.sort_by_key(|rect| (rect.y, rect.x));
+        assert_eq!(
+            rects,
+            [
+                Rect::new(0, 0, 4, 3),
+                Rect::new(4, 0, 4, 3),
+                Rect::new(8, 0, 2, 3),
+                Rect::new(0, 3, 4, 3),
+                Rect::new(4, 3, 4, 3),
+                Rect::new(8, 3, 2, 3),
+                Rect::new(0, 6, 4, 1),
+                Rect::new(4, 6, 4, 1),
+                Rect::new(8, 6, 2, 1),
+            ]
+        );
+        for (y, row) in pb.rows().enumerate() {
+            for (x, pixel) in row.chunks(3).enumerate() {
+                assert_eq!(pixel, &[x as u8, y as u8, 1]);
+            }
+        }
+        assert_eq!(pb.damage().rects(), &[Rect::new(0, 0, 10, 7)]);
+    }
+
+    #[test]
+    fn typed_tiles() {
+        let mut pb = PixelBufferTyped::<RGB>::new_headless(5, 5);
+        pb.par_tiles_mut(2, 2).for_each(|mut tile| {
+            let rect = tile.rect();
+            for row in tile.rows_mut() {
+                assert_eq!(row.len(), rect.width as usize);
+                row.fill(RGB::from_rgb(rect.x as u8, rect.y as u8, 0));
+            }
+        });
+        assert_eq!(pb.row(4).unwrap()[3], RGB::from_rgb(2, 4, 0));
+        assert_eq!(pb.par_tiles_mut(8, 8).count(), 1);
+    }
+}
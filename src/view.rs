@@ -0,0 +1,504 @@
+//! Borrowed rectangles of pixels from a pixel buffer.
+//!
+//! A view holds one slice per row, so it reads the same whether the platform stores rows top to
+//! bottom or bottom to top. Mutable views can be split into disjoint views, which is how a pixel
+//! buffer gets drawn to from several threads at once.
+
+use std::ops::{Index, IndexMut};
+
+use crate::{Rect, BGRA};
+
+/// Lets an `impl Trait` return type borrow from a view's or tile's rows, which edition 2018
+/// otherwise only allows if the lifetime shows up in one of its bounds.
+#[doc(hidden)]
+pub trait Captures<'a> {}
+
+impl<T: ?Sized> Captures<'_> for T {}
+
+/// A borrowed rectangle of pixels from a [`PixelBufferTyped`](crate::PixelBufferTyped).
+///
+/// Coordinates are relative to the view's top-left corner, and rows run top to bottom whatever
+/// order the platform stores them in.
+#[derive(Debug, Clone)]
+pub struct PixelView<'a, P> {
+    rect: Rect,
+    rows: Vec<&'a [P]>,
+    stride: usize,
+    palette: Option<&'a [BGRA; 256]>,
+}
+
+/// A mutably borrowed rectangle of pixels from a [`PixelBufferTyped`](crate::PixelBufferTyped).
+///
+/// Views can be split into disjoint views, which can then be handed to different threads.
+/// Coordinates are relative to the view's top-left corner, and rows run top to bottom whatever
+/// order the platform stores them in.
+#[derive(Debug)]
+pub struct PixelViewMut<'a, P> {
+    rect: Rect,
+    rows: Vec<&'a mut [P]>,
+    stride: usize,
+}
+
+/// Clips `rect`, relative to a `width`x`height` area, to that area.
+fn clip(rect: Rect, width: u32, height: u32) -> Rect {
+    rect.intersection(&Rect::new(0, 0, width, height))
+}
+
+/// Collapses empty rectangles to nothing at the origin, since clipping can leave them past the
+/// last row or column.
+fn normalize(rect: Rect) -> Rect {
+    if rect.is_empty() {
+        Rect::new(0, 0, 0, 0)
+    } else {
+        rect
+    }
+}
+
+/// Turns `rect`, relative to the top-left corner of `origin`, into a rectangle in the same
+/// coordinates as `origin`.
+fn offset(rect: Rect, origin: Rect) -> Rect {
+    Rect::new(
+        origin.x + rect.x,
+        origin.y + rect.y,
+        rect.width,
+        rect.height,
+    )
+}
+
+impl<'a, P> PixelView<'a, P> {
+    /// Takes the part of `rows`, which are `stride` bytes apart in the pixel buffer, covered by
+    /// `rect`, which must already be clipped to them.
+    pub(crate) fn new(
+        rows: impl Iterator<Item = &'a [P]>,
+        rect: Rect,
+        stride: usize,
+    ) -> PixelView<'a, P> {
+        let rect = normalize(rect);
+        let columns = rect.x as usize..rect.right() as usize;
+        PixelView {
+            rect,
+            rows: rows
+                .skip(rect.y as usize)
+                .take(rect.height as usize)
+                .map(|row| &row[columns.clone()])
+                .collect(),
+            stride,
+            palette: None,
+        }
+    }
+
+    /// Sets the palette that the view's pixels are looked up in, if they're indices.
+    pub(crate) fn with_palette(self, palette: Option<&'a [BGRA; 256]>) -> PixelView<'a, P> {
+        PixelView { palette, ..self }
+    }
+
+    /// The part of the pixel buffer that the view covers.
+    pub fn rect(&self) -> Rect {
+        self.rect
+    }
+
+    /// The width, in pixels, of the view.
+    pub fn width(&self) -> u32 {
+        self.rect.width
+    }
+
+    /// The height, in pixels, of the view.
+    pub fn height(&self) -> u32 {
+        self.rect.height
+    }
+
+    /// The distance, in bytes, between the starts of consecutive rows in the pixel buffer the
+    /// view borrows from.
+    ///
+    /// This is the pixel buffer's [`row_len`](crate::PixelBufferTyped::row_len), padding included,
+    /// not the length of the view's rows.
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    /// The palette of the pixel buffer the view borrows from.
+    ///
+    /// Returns `None` unless the pixel buffer's format is [`Indexed8`](crate::Indexed8).
+    pub fn palette(&self) -> Option<&'a [BGRA; 256]> {
+        self.palette
+    }
+
+    /// Gets the pixel at `(x, y)`, or `None` if it's outside the view.
+    pub fn get(&self, x: u32, y: u32) -> Option<&'a P> {
+        self.rows.get(y as usize)?.get(x as usize)
+    }
+
+    /// Gets the row at the particular height within the view.
+    pub fn row(&self, row: u32) -> Option<&'a [P]> {
+        self.rows.get(row as usize).copied()
+    }
+
+    /// Iterate through all rows in the view.
+    pub fn rows(&self) -> impl ExactSizeIterator + DoubleEndedIterator<Item = &'a [P]> + '_ {
+        self.rows.iter().copied()
+    }
+
+    /// Iterate through all pixels in the view, along with their coordinates.
+    pub fn enumerate_pixels(&self) -> impl Iterator<Item = (u32, u32, &'a P)> + '_ {
+        (0..)
+            .zip(self.rows())
+            .flat_map(|(y, row)| (0..).zip(row).map(move |(x, pixel)| (x, y, pixel)))
+    }
+
+    /// Borrows the part of the view in `rect`, which is relative to the view and clipped to it.
+    pub fn view(&self, rect: Rect) -> PixelView<'a, P> {
+        let rect = clip(rect, self.width(), self.height());
+        let view = PixelView::new(self.rows(), rect, self.stride);
+        PixelView {
+            rect: offset(view.rect, self.rect),
+            palette: self.palette,
+            ..view
+        }
+    }
+}
+
+#[cfg(any(feature = "images", feature = "image"))]
+impl<P: crate::PixelBufferFormat> PixelView<'_, P> {
+    /// Converts the view's pixels to `D`, and returns their bytes.
+    ///
+    /// Indices are looked up in the view's palette.
+    pub(crate) fn to_bytes<D: crate::PixelBufferFormat>(&self) -> Vec<u8> {
+        use crate::convert::{convert_raw_indexed_row, convert_row};
+
+        let mut pixels = vec![D::DEFAULT; self.width() as usize * self.height() as usize];
+        if !pixels.is_empty() {
+            for (src, dst) in self
+                .rows()
+                .zip(pixels.chunks_exact_mut(self.width() as usize))
+            {
+                match self.palette {
+                    Some(palette) => convert_raw_indexed_row(
+                        P::to_raw_slice(src),
+                        palette,
+                        D::to_raw_slice_mut(dst),
+                        D::FORMAT_TYPE,
+                    ),
+                    None => convert_row(src, dst),
+                }
+            }
+        }
+        D::to_raw_slice(&pixels).to_vec()
+    }
+}
+
+impl<'a, P> PixelViewMut<'a, P> {
+    /// Takes the part of `rows`, which are `stride` bytes apart in the pixel buffer, covered by
+    /// `rect`, which must already be clipped to them.
+    pub(crate) fn new(
+        rows: impl Iterator<Item = &'a mut [P]>,
+        rect: Rect,
+        stride: usize,
+    ) -> PixelViewMut<'a, P> {
+        let rect = normalize(rect);
+        let columns = rect.x as usize..rect.right() as usize;
+        PixelViewMut {
+            rect,
+            rows: rows
+                .skip(rect.y as usize)
+                .take(rect.height as usize)
+                .map(|row| &mut row[columns.clone()])
+                .collect(),
+            stride,
+        }
+    }
+
+    /// The part of the pixel buffer that the view covers.
+    pub fn rect(&self) -> Rect {
+        self.rect
+    }
+
+    /// The width, in pixels, of the view.
+    pub fn width(&self) -> u32 {
+        self.rect.width
+    }
+
+    /// The height, in pixels, of the view.
+    pub fn height(&self) -> u32 {
+        self.rect.height
+    }
+
+    /// The distance, in bytes, between the starts of consecutive rows in the pixel buffer the
+    /// view borrows from.
+    ///
+    /// This is the pixel buffer's [`row_len`](crate::PixelBufferTyped::row_len), padding included,
+    /// not the length of the view's rows.
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    /// Gets the pixel at `(x, y)`, or `None` if it's outside the view.
+    pub fn get(&self, x: u32, y: u32) -> Option<&P> {
+        self.rows.get(y as usize)?.get(x as usize)
+    }
+
+    /// Mutably gets the pixel at `(x, y)`, or `None` if it's outside the view.
+    pub fn get_mut(&mut self, x: u32, y: u32) -> Option<&mut P> {
+        self.rows.get_mut(y as usize)?.get_mut(x as usize)
+    }
+
+    /// Gets the row at the particular height within the view.
+    pub fn row(&self, row: u32) -> Option<&[P]> {
+        self.rows.get(row as usize).map(|row| &**row)
+    }
+
+    /// Mutably gets the row at the particular height within the view.
+    pub fn row_mut(&mut self, row: u32) -> Option<&mut [P]> {
+        self.rows.get_mut(row as usize).map(|row| &mut **row)
+    }
+
+    /// Iterate through all rows in the view.
+    pub fn rows(&self) -> impl ExactSizeIterator + DoubleEndedIterator<Item = &[P]> + Captures<'a> {
+        self.rows.iter().map(|row| &**row)
+    }
+
+    /// Mutably iterate through all rows in the view.
+    pub fn rows_mut(
+        &mut self,
+    ) -> impl ExactSizeIterator + DoubleEndedIterator<Item = &mut [P]> + Captures<'a> {
+        self.rows.iter_mut().map(|row| &mut **row)
+    }
+
+    /// Mutably iterate through all pixels in the view, along with their coordinates.
+    pub fn enumerate_pixels_mut(
+        &mut self,
+    ) -> impl Iterator<Item = (u32, u32, &mut P)> + Captures<'a> {
+        (0..)
+            .zip(self.rows_mut())
+            .flat_map(|(y, row)| (0..).zip(row).map(move |(x, pixel)| (x, y, pixel)))
+    }
+
+    /// Borrows the part of the view in `rect`, which is relative to the view and clipped to it.
+    pub fn view(&self, rect: Rect) -> PixelView<'_, P> {
+        let rect = clip(rect, self.width(), self.height());
+        let view = PixelView::new(self.rows(), rect, self.stride);
+        PixelView {
+            rect: offset(view.rect, self.rect),
+            ..view
+        }
+    }
+
+    /// Mutably borrows the part of the view in `rect`, which is relative to the view and clipped
+    /// to it.
+    pub fn view_mut(&mut self, rect: Rect) -> PixelViewMut<'_, P> {
+        let rect = clip(rect, self.width(), self.height());
+        let (origin, stride) = (self.rect, self.stride);
+        let view = PixelViewMut::new(self.rows_mut(), rect, stride);
+        PixelViewMut {
+            rect: offset(view.rect, origin),
+            ..view
+        }
+    }
+
+    /// Splits the view into the rows above `row` and the rest.
+    ///
+    /// # Panics
+    /// Panics if `row` is greater than the height of the view.
+    pub fn split_at_row_mut(mut self, row: u32) -> (PixelViewMut<'a, P>, PixelViewMut<'a, P>) {
+        assert!(
+            row <= self.height(),
+            "row {} out of range for a view {} rows high",
+            row,
+            self.height()
+        );
+        let bottom = self.rows.split_off(row as usize);
+        let Rect {
+            x,
+            y,
+            width,
+            height,
+        } = self.rect;
+        let top = PixelViewMut {
+            rect: Rect::new(x, y, width, row),
+            rows: self.rows,
+            stride: self.stride,
+        };
+        let bottom = PixelViewMut {
+            rect: Rect::new(x, y + row, width, height - row),
+            rows: bottom,
+            stride: self.stride,
+        };
+        (top, bottom)
+    }
+
+    /// Splits the view into the columns left of `column` and the rest.
+    ///
+    /// # Panics
+    /// Panics if `column` is greater than the width of the view.
+    pub fn split_at_column_mut(self, column: u32) -> (PixelViewMut<'a, P>, PixelViewMut<'a, P>) {
+        assert!(
+            column <= self.width(),
+            "column {} out of range for a view {} columns wide",
+            column,
+            self.width()
+        );
+        let (left, right) = self
+            .rows
+            .into_iter()
+            .map(|row| row.split_at_mut(column as usize))
+            .unzip();
+        let Rect {
+            x,
+            y,
+            width,
+            height,
+        } = self.rect;
+        let left = PixelViewMut {
+            rect: Rect::new(x, y, column, height),
+            rows: left,
+            stride: self.stride,
+        };
+        let right = PixelViewMut {
+            rect: Rect::new(x + column, y, width - column, height),
+            rows: right,
+            stride: self.stride,
+        };
+        (left, right)
+    }
+}
+
+impl<P> Index<(u32, u32)> for PixelView<'_, P> {
+    type Output = P;
+
+    /// The pixel at `(x, y)`.
+    ///
+    /// # Panics
+    /// Panics if the pixel is outside the view.
+    fn index(&self, (x, y): (u32, u32)) -> &P {
+        &self.rows[y as usize][x as usize]
+    }
+}
+
+impl<P> Index<(u32, u32)> for PixelViewMut<'_, P> {
+    type Output = P;
+
+    /// The pixel at `(x, y)`.
+    ///
+    /// # Panics
+    /// Panics if the pixel is outside the view.
+    fn index(&self, (x, y): (u32, u32)) -> &P {
+        &self.rows[y as usize][x as usize]
+    }
+}
+
+impl<P> IndexMut<(u32, u32)> for PixelViewMut<'_, P> {
+    /// The pixel at `(x, y)`.
+    ///
+    /// # Panics
+    /// Panics if the pixel is outside the view.
+    fn index_mut(&mut self, (x, y): (u32, u32)) -> &mut P {
+        &mut self.rows[y as usize][x as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{PixelBufferTyped, Rect, RGB};
+
+    fn numbered(width: u32, height: u32) -> PixelBufferTyped<RGB> {
+        let mut pb = PixelBufferTyped::<RGB>::new_headless(width, height);
+        for (x, y, pixel) in pb.enumerate_pixels_mut() {
+            *pixel = RGB::new(x as u8, y as u8, 0);
+        }
+        pb.clear_damage();
+        pb
+    }
+
+    #[test]
+    fn addresses_pixels() {
+        let mut pb = numbered(4, 3);
+        assert_eq!(pb.get(3, 2), Some(&RGB::new(3, 2, 0)));
+        assert_eq!(pb.get(4, 0), None);
+        assert_eq!(pb.get(0, 3), None);
+        assert_eq!(pb[(1, 2)], RGB::new(1, 2, 0));
+        assert!(pb.get_mut(0, 3).is_none());
+        assert!(pb.damage().is_empty());
+        pb[(2, 1)].b = 7;
+        assert_eq!(pb.row(1).unwrap()[2], RGB::new(2, 1, 7));
+        assert_eq!(pb.damage().rects(), &[Rect::new(2, 1, 1, 1)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "pixel (4, 0) out of range for a 4x3 pixel buffer")]
+    fn index_out_of_bounds() {
+        let _ = numbered(4, 3)[(4, 0)];
+    }
+
+    #[test]
+    fn views() {
+        let mut pb = numbered(5, 4);
+        let view = pb.view(Rect::new(1, 2, 10, 10));
+        assert_eq!(view.rect(), Rect::new(1, 2, 4, 2));
+        assert_eq!(view[(0, 0)], RGB::new(1, 2, 0));
+        assert_eq!(view.get(3, 1), Some(&RGB::new(4, 3, 0)));
+        assert_eq!(view.get(4, 0), None);
+        assert_eq!(view.rows().len(), 2);
+        assert!(view.rows().all(|row| row.len() == 4));
+        assert_eq!(view.stride(), 15);
+        let inner = view.view(Rect::new(1, 1, 2, 2));
+        assert_eq!(inner.rect(), Rect::new(2, 3, 2, 1));
+        assert_eq!(inner.stride(), 15);
+        assert_eq!(inner[(0, 0)], RGB::new(2, 3, 0));
+        assert_eq!(
+            inner.enumerate_pixels().collect::<Vec<_>>(),
+            [(0, 0, &RGB::new(2, 3, 0)), (1, 0, &RGB::new(3, 3, 0))]
+        );
+        assert_eq!(pb.view(Rect::new(6, 0, 1, 1)).rows().len(), 0);
+        assert_eq!(pb.view(Rect::new(u32::MAX, 0, 2, 2)).rows().len(), 0);
+
+        let mut view = pb.view_mut(Rect::new(1, 1, 3, 2));
+        let mut inner = view.view_mut(Rect::new(1, 1, 5, 5));
+        assert_eq!(inner.rect(), Rect::new(2, 2, 2, 1));
+        for (x, y, pixel) in inner.enumerate_pixels_mut() {
+            pixel.b = 10 + x as u8 + y as u8;
+        }
+        assert_eq!(view[(2, 1)], RGB::new(3, 2, 11));
+        assert_eq!(pb.row(2).unwrap()[2], RGB::new(2, 2, 10));
+        assert_eq!(pb.damage().rects(), &[Rect::new(1, 1, 3, 2)]);
+    }
+
+    #[test]
+    fn splits() {
+        let mut pb = numbered(4, 4);
+        let (top, bottom) = pb.split_at_row_mut(1);
+        assert_eq!(
+            (top.rect(), bottom.rect()),
+            (Rect::new(0, 0, 4, 1), Rect::new(0, 1, 4, 3))
+        );
+        let (left, mut right) = bottom.split_at_column_mut(3);
+        assert_eq!(left.rect(), Rect::new(0, 1, 3, 3));
+        assert_eq!(right.rect(), Rect::new(3, 1, 1, 3));
+        assert_eq!((left.stride(), right.stride()), (12, 12));
+        assert_eq!(left[(2, 2)], RGB::new(2, 3, 0));
+        std::thread::scope(|scope| {
+            for mut view in [top, left] {
+                scope.spawn(move || {
+                    for row in view.rows_mut() {
+                        row.fill(RGB::new(9, 9, 9));
+                    }
+                });
+            }
+        });
+        right[(0, 0)] = RGB::new(1, 1, 1);
+        let (empty, all) = right.split_at_row_mut(0);
+        assert_eq!(empty.rows().len(), 0);
+        assert_eq!(all.rows().len(), 3);
+
+        let rows: Vec<_> = pb.rows().map(|row| row.to_vec()).collect();
+        assert_eq!(rows[0], [RGB::new(9, 9, 9); 4]);
+        assert_eq!(rows[1][..3], [RGB::new(9, 9, 9); 3]);
+        assert_eq!(rows[1][3], RGB::new(1, 1, 1));
+        assert_eq!(rows[3][3], RGB::new(3, 3, 0));
+        assert_eq!(pb.damage().rects(), &[Rect::new(0, 0, 4, 4)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "column 5 out of range for a view 4 columns wide")]
+    fn split_out_of_bounds() {
+        numbered(4, 4).split_at_column_mut(5);
+    }
+}